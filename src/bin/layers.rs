@@ -3,14 +3,17 @@ use std::env;
 use std::fs;
 use std::io::Write;
 use std::path::Path;
+use std::process;
 use std::ptr;
 use std::sync::atomic::{AtomicPtr, Ordering};
 use std::sync::Arc;
+use std::thread;
 #[macro_use]
 extern crate log;
 extern crate argparse;
 extern crate env_logger;
 extern crate layers;
+extern crate libc;
 #[macro_use]
 extern crate lazy_static;
 
@@ -28,6 +31,10 @@ lazy_static! {
 }
 
 extern "C" fn on_signal(sig: i32) {
+    if sig == libc::SIGUSR1 {
+        stats::request_dump();
+        return;
+    }
     debug!("on signal {}", sig);
     let main = LAYER_PTR.load(Ordering::SeqCst);
     unsafe {
@@ -40,20 +47,63 @@ extern "C" fn on_signal(sig: i32) {
 struct Layers {
     _config: Box<config::Configure>,
     dispatcher: Arc<dispatcher::Dispatcher>,
-    daq: Arc<daq::DAQ>,
+    sources: Vec<Arc<daq::PacketSource>>,
 }
 
 impl Layers {
-    fn new(config: Box<config::Configure>) -> Layers {
+    fn new(config: Box<config::Configure>) -> Result<Layers, String> {
         mime::MimeParser::init();
-        let daq = daq::init(&config.interface);
-        let dispatcher = dispatcher::init(config.worker_thread as u8);
+        let n_threads = config.worker_thread as u8;
+        let filter = &config.bpf_filter;
+        let mut sources: Vec<Arc<daq::PacketSource>> = Vec::new();
+        let dispatcher;
+
+        if !config.read_files.is_empty() {
+            sources.push(daq::pcap::init_offline(&config.read_files, filter)?);
+            dispatcher = dispatcher::init(n_threads, true, &config.defrag, &config.stream, &config.arp);
+        } else if config.daq == "afpacket" {
+            let mut workers = vec![Vec::new(); n_threads as usize];
+            for (index, interface) in config.interfaces.iter().enumerate() {
+                let group =
+                    daq::afpacket::init_fanout(interface, index as u16, n_threads, filter)?;
+                for (i, ring) in group.rings().into_iter().enumerate() {
+                    workers[i].push(ring);
+                }
+                sources.push(group);
+            }
+            dispatcher = dispatcher::init_fanout(workers, &config.defrag, &config.stream, &config.arp);
+        } else {
+            for interface in config.interfaces.iter() {
+                sources.push(daq::pcap::init(interface, filter)?);
+            }
+            dispatcher = dispatcher::init(n_threads, false, &config.defrag, &config.stream, &config.arp);
+        }
 
-        Layers {
+        for source in sources.iter() {
+            stats::register_source(source.clone());
+        }
+        if config.events.log {
+            event::register_sink(Arc::new(event::LogSink));
+        }
+        if let Some(ref file) = config.events.file {
+            event::register_sink(Arc::new(event::FileSink::new(file)?));
+        }
+        event::enable_anomalies(config.events.anomalies);
+        if config.flows.log {
+            flow::register_sink(Arc::new(flow::LogSink));
+        }
+        if let Some(ref file) = config.flows.file {
+            flow::register_sink(Arc::new(flow::FileSink::new(file)?));
+        }
+        checksum::enable_verify(config.verify_checksums);
+        ReassemblyMemory::global().set_limit(config.stream.memory_limit);
+        stats::start(config.stats_interval);
+
+        Ok(Layers {
             _config: config,
             dispatcher,
-            daq,
-        }
+            sources,
+        })
     }
 
     fn setup_workspace(path: &str) {
@@ -71,16 +121,45 @@ impl Layers {
         env::set_current_dir(path).unwrap();
     }
 
+    // one capture thread per source, all feeding the same dispatcher
     fn run(&self) {
-        let dispatcher = self.dispatcher.clone();
+        let mut threads = Vec::new();
+        for source in self.sources.iter() {
+            let source = source.clone();
+            let dispatcher = self.dispatcher.clone();
+
+            let cb = move || {
+                source.run(&move |packet: Arc<packet::Packet>| {
+                    dispatcher.dispatch(packet);
+                });
+            };
+            threads.push(thread::spawn(cb));
+        }
+        for thread in threads.into_iter() {
+            thread.join().expect("capture thread panicked");
+        }
+
+        for source in self.sources.iter() {
+            let stats = source.stats();
+            info!(
+                "capture done on {}, received {}, dropped {}, if_dropped {}",
+                source.name(),
+                stats.received,
+                stats.dropped,
+                stats.if_dropped
+            );
+        }
 
-        self.daq.run(&move |packet: Arc<packet::Packet>| {
-            dispatcher.dispatch(packet);
-        });
+        if self.sources.iter().all(|source| source.is_offline()) {
+            self.dispatcher.finish();
+        }
+        stats::stop();
     }
 
     fn stop(&mut self) {
-        self.daq.stop();
+        for source in self.sources.iter() {
+            source.stop();
+        }
         self.dispatcher.stop();
     }
 }
@@ -93,6 +172,8 @@ impl Drop for Layers {
 
 fn main() {
     let mut configure = "/etc/layers/config.yaml".to_string();
+    let mut read_files: Vec<String> = Vec::new();
+    let mut bpf_filter = String::new();
     {
         let mut ap = argparse::ArgumentParser::new();
         ap.set_description("layers");
@@ -101,6 +182,16 @@ fn main() {
             argparse::Store,
             "config file path",
         );
+        ap.refer(&mut read_files).add_option(
+            &["-r", "--read"],
+            argparse::Collect,
+            "read packets from pcap/pcapng file, may be repeated",
+        );
+        ap.refer(&mut bpf_filter).add_option(
+            &["-f", "--filter"],
+            argparse::Store,
+            "bpf capture filter, overrides bpf_filter in the config file",
+        );
         ap.parse_args_or_exit();
     }
 
@@ -117,16 +208,32 @@ fn main() {
         })
         .init();
 
-    let conf = config::load(configure);
+    let mut conf = config::load(configure);
+    if !read_files.is_empty() {
+        conf.read_files = read_files;
+    }
+    if !bpf_filter.is_empty() {
+        conf.bpf_filter = bpf_filter;
+    }
+    if conf.interfaces.is_empty() && conf.read_files.is_empty() {
+        panic!("invalid interface");
+    }
     Layers::setup_workspace(&conf.workspace);
-    let layer = Layers::new(conf);
+    let layer = match Layers::new(conf) {
+        Ok(layer) => layer,
+        Err(err) => {
+            error!("startup error: {}", err);
+            process::exit(1);
+        }
+    };
 
     let ptr = &layer as *const Layers as *mut Layers;
     LAYER_PTR.store(ptr, Ordering::SeqCst);
 
     unsafe {
-        signal(1, on_signal);
-        signal(2, on_signal);
+        signal(libc::SIGHUP, on_signal);
+        signal(libc::SIGINT, on_signal);
+        signal(libc::SIGUSR1, on_signal);
     }
 
     layer.run();
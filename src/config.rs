@@ -1,4 +1,5 @@
 use aho_corasick::{AcAutomaton, Automaton};
+use layer::defrag::OverlapPolicy;
 use std::collections::HashMap;
 use std::fs::File;
 use std::io::prelude::*;
@@ -10,10 +11,165 @@ unsafe impl Send for Configure {}
 
 unsafe impl Sync for Configure {}
 
+// capture settings of one interface
+#[derive(Clone, Debug)]
+pub struct InterfaceConfig {
+    pub name: String,
+    pub snaplen: i64,
+    // kernel capture buffer, bytes
+    pub buffer_size: i64,
+    pub promisc: bool,
+    pub immediate: bool,
+    // read timeout, milliseconds
+    pub timeout: i64,
+    // ask for nanosecond timestamps, fall back to microseconds when unsupported
+    pub nanosecond: bool,
+    // capture buffers kept for reuse once their packets are dropped, 0 allocates each one
+    pub buffer_pool: usize,
+}
+
+impl InterfaceConfig {
+    pub fn new(name: &str) -> InterfaceConfig {
+        InterfaceConfig {
+            name: name.to_string(),
+            snaplen: 1024 * 64,
+            buffer_size: 500 * 1024 * 1024,
+            promisc: true,
+            immediate: false,
+            timeout: 1000,
+            nanosecond: true,
+            buffer_pool: 8192,
+        }
+    }
+}
+
+// ipv4 reassembly, limits apply to each worker
+#[derive(Clone, Debug)]
+pub struct DefragConfig {
+    // seconds a datagram may wait for its missing fragments
+    pub timeout: u64,
+    // bytes of buffered fragments
+    pub memory_limit: usize,
+    pub policy: OverlapPolicy,
+}
+
+impl DefragConfig {
+    pub fn new() -> DefragConfig {
+        DefragConfig {
+            timeout: 30,
+            memory_limit: 32 * 1024 * 1024,
+            policy: OverlapPolicy::First,
+        }
+    }
+}
+
+// stream tracking, shared by the tcp and udp trackers
+#[derive(Clone, Debug)]
+pub struct StreamConfig {
+    // key streams by vlan too, for overlapping address spaces behind one tap
+    pub track_vlan: bool,
+    // segments with a bad checksum never reach the streams, needs verify_checksums
+    pub drop_bad_checksum: bool,
+    // start tcp streams from data segments when the syn was not captured
+    pub midstream: bool,
+    // tcp payload reassembly: which copy wins when segments overlap, and the out
+    // of order bytes held per direction and by all the streams of all workers
+    pub overlap_policy: OverlapPolicy,
+    pub flow_memory_limit: usize,
+    pub memory_limit: usize,
+    // seconds a hole in tcp payload may stay open before the data after it goes on
+    pub gap_timeout: u64,
+    // hold tcp payload until the other side acknowledges it, so the dissectors see
+    // both directions interleaved
+    pub ack_ordered: bool,
+}
+
+impl StreamConfig {
+    pub fn new() -> StreamConfig {
+        StreamConfig {
+            track_vlan: false,
+            drop_bad_checksum: false,
+            midstream: false,
+            overlap_policy: OverlapPolicy::First,
+            flow_memory_limit: 1024 * 1024,
+            memory_limit: 256 * 1024 * 1024,
+            gap_timeout: 5,
+            ack_ordered: false,
+        }
+    }
+}
+
+// ip to mac binding table kept by each worker
+#[derive(Clone, Debug)]
+pub struct ArpConfig {
+    // seconds over which gratuitous announcements are counted
+    pub storm_window: u64,
+    // gratuitous announcements for one address within the window
+    pub storm_threshold: u64,
+    pub max_bindings: usize,
+}
+
+impl ArpConfig {
+    pub fn new() -> ArpConfig {
+        ArpConfig {
+            storm_window: 10,
+            storm_threshold: 20,
+            max_bindings: 64 * 1024,
+        }
+    }
+}
+
+// where events go, the file is relative to the workspace
+#[derive(Clone, Debug)]
+pub struct EventConfig {
+    pub log: bool,
+    pub file: Option<String>,
+    // decode errors and packet anomalies as events too
+    pub anomalies: bool,
+}
+
+impl EventConfig {
+    pub fn new() -> EventConfig {
+        EventConfig {
+            log: true,
+            file: None,
+            anomalies: false,
+        }
+    }
+}
+
+// where flow records go as streams end, nowhere unless asked for
+#[derive(Clone, Debug)]
+pub struct FlowConfig {
+    pub log: bool,
+    pub file: Option<String>,
+}
+
+impl FlowConfig {
+    pub fn new() -> FlowConfig {
+        FlowConfig {
+            log: false,
+            file: None,
+        }
+    }
+}
+
 pub struct Configure {
-    pub interface: String,
+    pub interfaces: Vec<InterfaceConfig>,
+    pub daq: String,
+    pub bpf_filter: String,
+    pub read_files: Vec<String>,
     pub workspace: String,
     pub worker_thread: i64,
+    // seconds between stats log reports, 0 writes them only on SIGUSR1
+    pub stats_interval: u64,
+    // ipv4, tcp and udp checksums, bad ones are recorded as packet anomalies
+    pub verify_checksums: bool,
+    pub defrag: DefragConfig,
+    pub stream: StreamConfig,
+    pub arp: ArpConfig,
+    pub events: EventConfig,
+    pub flows: FlowConfig,
     pub dissectors: HashMap<String, ()>,
     http_content_ac_automaton: Box<AcAutomaton<String>>,
 }
@@ -64,8 +220,41 @@ pub fn load(path: String) -> Box<Configure> {
     assert_eq!(docs.capacity(), 1);
     let doc = &docs[0];
 
-    let interface = doc["interface"].as_str().expect("invalid interface");
-    info!("interface = {}", interface);
+    let mut interfaces = Vec::new();
+    match doc["interface"].as_vec() {
+        Some(list) => {
+            for interface in list.iter() {
+                interfaces.push(load_interface(interface));
+            }
+        }
+        None => {
+            if !doc["interface"].is_badvalue() {
+                interfaces.push(load_interface(&doc["interface"]));
+            }
+        }
+    }
+    for interface in interfaces.iter() {
+        info!("interface = {:?}", interface);
+    }
+
+    let daq = doc["daq"].as_str().unwrap_or("pcap");
+    match daq {
+        "pcap" | "afpacket" => {}
+        _ => panic!("invalid daq {}", daq),
+    }
+    info!("daq = {}", daq);
+
+    let bpf_filter = doc["bpf_filter"].as_str().unwrap_or("");
+    info!("bpf_filter = {}", bpf_filter);
+
+    let mut read_files = Vec::new();
+    if let Some(files) = doc["read_files"].as_vec() {
+        for file in files.iter() {
+            let file = file.as_str().expect("invalid read_files config");
+            info!("read file {}", file);
+            read_files.push(file.to_string());
+        }
+    }
 
     let workspace = doc["workspace"].as_str().expect("invalid workspace");
     info!("workspace = {}", workspace);
@@ -75,6 +264,27 @@ pub fn load(path: String) -> Box<Configure> {
         .expect("invalid worker_thread");
     info!("worker_thread = {}", worker_thread);
 
+    let stats_interval = doc["stats_interval"].as_i64().unwrap_or(60) as u64;
+    info!("stats_interval = {}", stats_interval);
+
+    let verify_checksums = doc["verify_checksums"].as_bool().unwrap_or(false);
+    info!("verify_checksums = {}", verify_checksums);
+
+    let defrag = load_defrag(&doc["defrag"]);
+    info!("defrag = {:?}", defrag);
+
+    let stream = load_stream(&doc["stream"]);
+    info!("stream = {:?}", stream);
+
+    let arp = load_arp(&doc["arp"]);
+    info!("arp = {:?}", arp);
+
+    let events = load_events(&doc["events"]);
+    info!("events = {:?}", events);
+
+    let flows = load_flows(&doc["flows"]);
+    info!("flows = {:?}", flows);
+
     let mut skip_http_content_keys = Vec::new();
     for key in doc["skip_http_content_key"]
         .as_vec()
@@ -100,9 +310,19 @@ pub fn load(path: String) -> Box<Configure> {
     }
 
     let conf = Box::new(Configure {
-        interface: interface.to_string(),
+        interfaces,
+        daq: daq.to_string(),
+        bpf_filter: bpf_filter.to_string(),
+        read_files,
         workspace: workspace.to_string(),
         worker_thread,
+        stats_interval,
+        verify_checksums,
+        defrag,
+        stream,
+        arp,
+        events,
+        flows,
         dissectors,
         http_content_ac_automaton,
     });
@@ -111,3 +331,122 @@ pub fn load(path: String) -> Box<Configure> {
     CONFIG_PTR.store(raw, Ordering::SeqCst);
     return conf;
 }
+
+fn load_defrag(doc: &yaml::Yaml) -> DefragConfig {
+    let mut defrag = DefragConfig::new();
+    if let Some(timeout) = doc["timeout"].as_i64() {
+        defrag.timeout = timeout as u64;
+    }
+    if let Some(memory_limit) = doc["memory_limit"].as_i64() {
+        defrag.memory_limit = memory_limit as usize;
+    }
+    if let Some(policy) = doc["policy"].as_str() {
+        defrag.policy = OverlapPolicy::from_str(policy).expect("invalid defrag policy");
+    }
+    return defrag;
+}
+
+fn load_stream(doc: &yaml::Yaml) -> StreamConfig {
+    let mut stream = StreamConfig::new();
+    if let Some(track_vlan) = doc["track_vlan"].as_bool() {
+        stream.track_vlan = track_vlan;
+    }
+    if let Some(drop_bad_checksum) = doc["drop_bad_checksum"].as_bool() {
+        stream.drop_bad_checksum = drop_bad_checksum;
+    }
+    if let Some(midstream) = doc["midstream"].as_bool() {
+        stream.midstream = midstream;
+    }
+    if let Some(policy) = doc["overlap_policy"].as_str() {
+        stream.overlap_policy = OverlapPolicy::from_str(policy).expect("invalid overlap policy");
+    }
+    if let Some(flow_memory_limit) = doc["flow_memory_limit"].as_i64() {
+        stream.flow_memory_limit = flow_memory_limit as usize;
+    }
+    if let Some(memory_limit) = doc["memory_limit"].as_i64() {
+        stream.memory_limit = memory_limit as usize;
+    }
+    if let Some(gap_timeout) = doc["gap_timeout"].as_i64() {
+        stream.gap_timeout = gap_timeout as u64;
+    }
+    if let Some(ack_ordered) = doc["ack_ordered"].as_bool() {
+        stream.ack_ordered = ack_ordered;
+    }
+    return stream;
+}
+
+fn load_arp(doc: &yaml::Yaml) -> ArpConfig {
+    let mut arp = ArpConfig::new();
+    if let Some(storm_window) = doc["storm_window"].as_i64() {
+        arp.storm_window = storm_window as u64;
+    }
+    if let Some(storm_threshold) = doc["storm_threshold"].as_i64() {
+        arp.storm_threshold = storm_threshold as u64;
+    }
+    if let Some(max_bindings) = doc["max_bindings"].as_i64() {
+        arp.max_bindings = max_bindings as usize;
+    }
+    return arp;
+}
+
+fn load_events(doc: &yaml::Yaml) -> EventConfig {
+    let mut events = EventConfig::new();
+    if let Some(log) = doc["log"].as_bool() {
+        events.log = log;
+    }
+    if let Some(anomalies) = doc["anomalies"].as_bool() {
+        events.anomalies = anomalies;
+    }
+    if let Some(file) = doc["file"].as_str() {
+        events.file = Some(file.to_string());
+    }
+    return events;
+}
+
+fn load_flows(doc: &yaml::Yaml) -> FlowConfig {
+    let mut flows = FlowConfig::new();
+    if let Some(log) = doc["log"].as_bool() {
+        flows.log = log;
+    }
+    if let Some(file) = doc["file"].as_str() {
+        flows.file = Some(file.to_string());
+    }
+    return flows;
+}
+
+// either a plain interface name or a mapping with the capture settings
+fn load_interface(doc: &yaml::Yaml) -> InterfaceConfig {
+    if let Some(name) = doc.as_str() {
+        return InterfaceConfig::new(name);
+    }
+
+    let name = doc["name"].as_str().expect("invalid interface name");
+    let mut interface = InterfaceConfig::new(name);
+
+    if let Some(snaplen) = doc["snaplen"].as_i64() {
+        interface.snaplen = snaplen;
+    }
+    if let Some(buffer_size) = doc["buffer_size"].as_i64() {
+        interface.buffer_size = buffer_size;
+    }
+    if let Some(promisc) = doc["promisc"].as_bool() {
+        interface.promisc = promisc;
+    }
+    if let Some(immediate) = doc["immediate"].as_bool() {
+        interface.immediate = immediate;
+    }
+    if let Some(timeout) = doc["timeout"].as_i64() {
+        interface.timeout = timeout;
+    }
+    if let Some(buffer_pool) = doc["buffer_pool"].as_i64() {
+        interface.buffer_pool = buffer_pool as usize;
+    }
+    if let Some(precision) = doc["tstamp_precision"].as_str() {
+        interface.nanosecond = match precision {
+            "nano" => true,
+            "micro" => false,
+            _ => panic!("invalid tstamp_precision {}", precision),
+        };
+    }
+    return interface;
+}
@@ -0,0 +1,553 @@
+use config::InterfaceConfig;
+use daq::pcap;
+use daq::{self, PacketSource, Stats};
+use inet;
+use layer::packet::Packet;
+use layer::{BufferPool, LinkType};
+use libc::{self, c_int, c_uint, c_void};
+use std::cmp;
+use std::ffi::CString;
+use std::io;
+use std::mem;
+use std::process;
+use std::ptr;
+use std::slice;
+use std::sync::atomic::{fence, AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+const ETH_P_ALL: u16 = 0x0003;
+
+const SOL_PACKET: c_int = 263;
+const PACKET_ADD_MEMBERSHIP: c_int = 1;
+const PACKET_RX_RING: c_int = 5;
+const PACKET_STATISTICS: c_int = 6;
+const PACKET_VERSION: c_int = 10;
+const PACKET_FANOUT: c_int = 18;
+
+const PACKET_MR_PROMISC: u16 = 1;
+const TPACKET_V3: c_int = 2;
+
+const PACKET_FANOUT_HASH: u32 = 0;
+// reassemble fragments before hashing so all fragments of a datagram meet on one socket
+const PACKET_FANOUT_FLAG_DEFRAG: u32 = 0x8000;
+
+const SO_ATTACH_FILTER: c_int = 26;
+
+const SIOCGIFHWADDR: libc::c_ulong = 0x8927;
+const ARPHRD_ETHER: u16 = 1;
+const ARPHRD_PPP: u16 = 512;
+const ARPHRD_RAWIP: u16 = 519;
+const ARPHRD_TUNNEL: u16 = 768;
+const ARPHRD_TUNNEL6: u16 = 769;
+const ARPHRD_LOOPBACK: u16 = 772;
+const ARPHRD_SIT: u16 = 776;
+const ARPHRD_IPGRE: u16 = 778;
+const ARPHRD_NONE: u16 = 0xfffe;
+
+const TP_STATUS_KERNEL: u32 = 0;
+const TP_STATUS_USER: u32 = 1;
+const TP_STATUS_VLAN_VALID: u32 = 1 << 4;
+const TP_STATUS_VLAN_TPID_VALID: u32 = 1 << 6;
+
+const ETH_P_8021Q: u16 = 0x8100;
+
+#[repr(C)]
+struct TPacketReq3 {
+    block_size: c_uint,
+    block_nr: c_uint,
+    frame_size: c_uint,
+    frame_nr: c_uint,
+    retire_blk_tov: c_uint,
+    sizeof_priv: c_uint,
+    feature_req_word: c_uint,
+}
+
+#[repr(C)]
+struct TPacketStatsV3 {
+    packets: c_uint,
+    drops: c_uint,
+    freeze_q_cnt: c_uint,
+}
+
+#[repr(C)]
+struct SockFProg {
+    len: u16,
+    filter: *const pcap::BpfInsn,
+}
+
+#[repr(C)]
+struct PacketMReq {
+    ifindex: c_int,
+    mr_type: u16,
+    alen: u16,
+    address: [u8; 8],
+}
+
+// struct ifreq with the ifr_hwaddr member
+#[repr(C)]
+struct IfReqHwAddr {
+    name: [u8; 16],
+    family: u16,
+    data: [u8; 22],
+}
+
+#[repr(C)]
+struct SockAddrLL {
+    family: u16,
+    protocol: u16,
+    ifindex: c_int,
+    hatype: u16,
+    pkttype: u8,
+    halen: u8,
+    addr: [u8; 8],
+}
+
+// struct tpacket_block_desc with the tpacket_hdr_v1 header inlined
+#[repr(C)]
+struct BlockDesc {
+    version: u32,
+    offset_to_priv: u32,
+    block_status: u32,
+    num_pkts: u32,
+    offset_to_first_pkt: u32,
+    blk_len: u32,
+    seq_num: u64,
+    ts_first_pkt: [u32; 2],
+    ts_last_pkt: [u32; 2],
+}
+
+#[repr(C)]
+struct TPacket3Header {
+    next_offset: u32,
+    sec: u32,
+    nsec: u32,
+    snaplen: u32,
+    len: u32,
+    status: u32,
+    mac: u16,
+    net: u16,
+    rxhash: u32,
+    vlan_tci: u32,
+    vlan_tpid: u16,
+    padding: u16,
+    padding2: [u8; 8],
+}
+
+// one AF_PACKET socket with its TPACKET_V3 receive ring
+pub struct AfPacketSource {
+    interface: Arc<str>,
+    linktype: LinkType,
+    fd: c_int,
+    ring: *mut u8,
+    block_size: usize,
+    block_nr: usize,
+    snaplen: usize,
+    current: AtomicUsize,
+    running: AtomicBool,
+    received: AtomicU64,
+    dropped: AtomicU64,
+    // frames are copied out of the ring so blocks go back to the kernel right away
+    pool: Option<Arc<BufferPool>>,
+}
+
+unsafe impl Send for AfPacketSource {}
+
+unsafe impl Sync for AfPacketSource {}
+
+impl AfPacketSource {
+    // 4M per block, the ring gets buffer_size / BLOCK_SIZE blocks
+    const BLOCK_SIZE: usize = 1 << 22;
+    const FRAME_SIZE: usize = 1 << 11;
+
+    // process the next filled block, waiting up to timeout milliseconds for one
+    pub fn poll(&self, timeout: c_int, cb: &mut FnMut(Arc<Packet>)) -> usize {
+        let current = self.current.load(Ordering::Relaxed);
+        let block = unsafe { self.ring.offset((current * self.block_size) as isize) };
+        let desc = block as *mut BlockDesc;
+
+        if unsafe { ptr::read_volatile(&(*desc).block_status) } & TP_STATUS_USER == 0 {
+            let mut pfd = libc::pollfd {
+                fd: self.fd,
+                events: libc::POLLIN | libc::POLLERR,
+                revents: 0,
+            };
+            unsafe {
+                libc::poll(&mut pfd, 1, timeout);
+            }
+            if unsafe { ptr::read_volatile(&(*desc).block_status) } & TP_STATUS_USER == 0 {
+                return 0;
+            }
+        }
+        fence(Ordering::Acquire);
+
+        let num_pkts = unsafe { (*desc).num_pkts as usize };
+        let mut offset = unsafe { (*desc).offset_to_first_pkt as usize };
+        for _ in 0..num_pkts {
+            unsafe {
+                let frame = block.offset(offset as isize);
+                let header = &*(frame as *const TPacket3Header);
+                let tm = header.sec as u64 * 1000 * 1000 * 1000 + header.nsec as u64;
+                let caplen = cmp::min(header.snaplen as usize, self.snaplen);
+                let mut data = slice::from_raw_parts(frame.offset(header.mac as isize), caplen);
+                let tagged;
+                if header.status & TP_STATUS_VLAN_VALID != 0 {
+                    tagged = self.vlan_tag(header, data);
+                    data = &tagged;
+                }
+                let p = match self.pool {
+                    Some(ref pool) => {
+                        Packet::with_pool(&self.interface, self.linktype, tm, data, pool)
+                    }
+                    None => Packet::new(&self.interface, self.linktype, tm, data),
+                };
+                if daq::accept(&p) {
+                    cb(p);
+                }
+                offset += header.next_offset as usize;
+            }
+        }
+
+        fence(Ordering::Release);
+        unsafe {
+            ptr::write_volatile(&mut (*desc).block_status, TP_STATUS_KERNEL);
+        }
+        self.current
+            .store((current + 1) % self.block_nr, Ordering::Relaxed);
+        self.received
+            .fetch_add(num_pkts as u64, Ordering::Relaxed);
+        return num_pkts;
+    }
+
+    // the kernel strips the outer vlan tag into the frame header, put it back after the mac addresses
+    fn vlan_tag(&self, header: &TPacket3Header, data: &[u8]) -> Vec<u8> {
+        if self.linktype != LinkType::EN10MB || data.len() < 12 {
+            return data.to_vec();
+        }
+        let tpid = if header.status & TP_STATUS_VLAN_TPID_VALID != 0 {
+            header.vlan_tpid
+        } else {
+            ETH_P_8021Q
+        };
+        let tci = header.vlan_tci as u16;
+        let mut tagged = Vec::with_capacity(data.len() + 4);
+        tagged.extend_from_slice(&data[..12]);
+        tagged.extend_from_slice(&[(tpid >> 8) as u8, tpid as u8, (tci >> 8) as u8, tci as u8]);
+        tagged.extend_from_slice(&data[12..]);
+        return tagged;
+    }
+
+    fn open(
+        interface: &InterfaceConfig,
+        fanout_group: Option<u16>,
+        filter: &str,
+    ) -> Result<AfPacketSource, String> {
+        // no protocol yet, packets only flow once the ring is set up and the socket bound
+        let fd = unsafe { libc::socket(libc::AF_PACKET, libc::SOCK_RAW, 0) };
+        if fd < 0 {
+            return Err(format!(
+                "af_packet socket error {}",
+                io::Error::last_os_error()
+            ));
+        }
+
+        let linktype = match link_type(fd, &interface.name) {
+            Ok(linktype) => linktype,
+            Err(err) => {
+                unsafe {
+                    libc::close(fd);
+                }
+                return Err(format!("af_packet {} {}", interface.name, err));
+            }
+        };
+
+        let mut source = AfPacketSource {
+            interface: Arc::from(interface.name.as_str()),
+            linktype,
+            fd,
+            ring: ptr::null_mut(),
+            block_size: AfPacketSource::BLOCK_SIZE,
+            block_nr: cmp::max(
+                interface.buffer_size as usize / AfPacketSource::BLOCK_SIZE,
+                1,
+            ),
+            snaplen: interface.snaplen as usize,
+            current: AtomicUsize::new(0),
+            running: AtomicBool::new(true),
+            received: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+            pool: pcap::new_pool(interface),
+        };
+
+        if !filter.is_empty() {
+            let program = pcap::compile_filter(linktype.0, 0xffff, filter)?;
+            let fprog = SockFProg {
+                len: program.insns.len() as u16,
+                filter: program.insns.as_ptr(),
+            };
+            let ret = unsafe {
+                libc::setsockopt(
+                    fd,
+                    libc::SOL_SOCKET,
+                    SO_ATTACH_FILTER,
+                    &fprog as *const SockFProg as *const c_void,
+                    mem::size_of::<SockFProg>() as libc::socklen_t,
+                )
+            };
+            if ret != 0 {
+                return Err(format!(
+                    "af_packet attach filter error {}",
+                    io::Error::last_os_error()
+                ));
+            }
+            debug!("bpf filter \"{}\"", filter);
+        }
+
+        if let Err(err) = source.setup(interface, fanout_group) {
+            return Err(format!("af_packet setup {} error {}", interface.name, err));
+        }
+        return Ok(source);
+    }
+
+    fn setup(&mut self, interface: &InterfaceConfig, fanout_group: Option<u16>) -> io::Result<()> {
+        let name = CString::new(interface.name.as_bytes()).unwrap();
+        let ifindex = unsafe { libc::if_nametoindex(name.as_ptr()) } as c_int;
+        if ifindex == 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        self.set_option(PACKET_VERSION, &TPACKET_V3)?;
+
+        let req = TPacketReq3 {
+            block_size: self.block_size as c_uint,
+            block_nr: self.block_nr as c_uint,
+            frame_size: AfPacketSource::FRAME_SIZE as c_uint,
+            frame_nr: (self.block_size * self.block_nr / AfPacketSource::FRAME_SIZE) as c_uint,
+            // milliseconds before the kernel hands over a partially filled block
+            retire_blk_tov: interface.timeout as c_uint,
+            sizeof_priv: 0,
+            feature_req_word: 0,
+        };
+        self.set_option(PACKET_RX_RING, &req)?;
+
+        let ring = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                self.block_size * self.block_nr,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                self.fd,
+                0,
+            )
+        };
+        if ring == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        self.ring = ring as *mut u8;
+
+        let addr = SockAddrLL {
+            family: libc::AF_PACKET as u16,
+            protocol: unsafe { inet::htons(ETH_P_ALL) },
+            ifindex,
+            hatype: 0,
+            pkttype: 0,
+            halen: 0,
+            addr: [0; 8],
+        };
+        let ret = unsafe {
+            libc::bind(
+                self.fd,
+                &addr as *const SockAddrLL as *const libc::sockaddr,
+                mem::size_of::<SockAddrLL>() as libc::socklen_t,
+            )
+        };
+        if ret != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        if interface.promisc {
+            let mreq = PacketMReq {
+                ifindex,
+                mr_type: PACKET_MR_PROMISC,
+                alen: 0,
+                address: [0; 8],
+            };
+            self.set_option(PACKET_ADD_MEMBERSHIP, &mreq)?;
+        }
+
+        if let Some(group) = fanout_group {
+            let fanout = group as u32 | ((PACKET_FANOUT_HASH | PACKET_FANOUT_FLAG_DEFRAG) << 16);
+            self.set_option(PACKET_FANOUT, &fanout)?;
+        }
+        Ok(())
+    }
+
+    fn set_option<T>(&self, name: c_int, value: &T) -> io::Result<()> {
+        let ret = unsafe {
+            libc::setsockopt(
+                self.fd,
+                SOL_PACKET,
+                name,
+                value as *const T as *const c_void,
+                mem::size_of::<T>() as libc::socklen_t,
+            )
+        };
+        if ret != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+impl PacketSource for AfPacketSource {
+    fn name(&self) -> &str {
+        &self.interface
+    }
+
+    fn run(&self, cb: &Fn(Arc<Packet>)) {
+        let mut cb = |packet: Arc<Packet>| cb(packet);
+        while self.running.load(Ordering::SeqCst) {
+            self.poll(100, &mut cb);
+        }
+    }
+
+    fn stop(&self) {
+        self.running.store(false, Ordering::SeqCst);
+    }
+
+    fn stats(&self) -> Stats {
+        let mut stat = TPacketStatsV3 {
+            packets: 0,
+            drops: 0,
+            freeze_q_cnt: 0,
+        };
+        let mut len = mem::size_of::<TPacketStatsV3>() as libc::socklen_t;
+        // the kernel resets its counters on every read
+        let ret = unsafe {
+            libc::getsockopt(
+                self.fd,
+                SOL_PACKET,
+                PACKET_STATISTICS,
+                &mut stat as *mut TPacketStatsV3 as *mut c_void,
+                &mut len,
+            )
+        };
+        if ret == 0 {
+            self.dropped
+                .fetch_add(stat.drops as u64, Ordering::Relaxed);
+        }
+
+        let mut stats = Stats::default();
+        stats.received = self.received.load(Ordering::Relaxed);
+        stats.dropped = self.dropped.load(Ordering::Relaxed);
+        return stats;
+    }
+}
+
+impl Drop for AfPacketSource {
+    fn drop(&mut self) {
+        debug!("af_packet cleanup");
+        unsafe {
+            if !self.ring.is_null() {
+                libc::munmap(self.ring as *mut c_void, self.block_size * self.block_nr);
+            }
+            libc::close(self.fd);
+        }
+    }
+}
+
+// sockets of one PACKET_FANOUT group, the kernel spreads flows over them by hash
+pub struct AfPacketGroup {
+    name: String,
+    rings: Vec<Arc<AfPacketSource>>,
+    running: AtomicBool,
+}
+
+impl AfPacketGroup {
+    pub fn rings(&self) -> Vec<Arc<AfPacketSource>> {
+        self.rings.clone()
+    }
+}
+
+impl PacketSource for AfPacketGroup {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    // the rings are drained by the dispatcher workers, just wait to be stopped
+    fn run(&self, _cb: &Fn(Arc<Packet>)) {
+        while self.running.load(Ordering::SeqCst) {
+            thread::sleep(Duration::from_millis(100));
+        }
+    }
+
+    fn stop(&self) {
+        self.running.store(false, Ordering::SeqCst);
+        for ring in self.rings.iter() {
+            ring.stop();
+        }
+    }
+
+    fn stats(&self) -> Stats {
+        let mut stats = Stats::default();
+        for ring in self.rings.iter() {
+            let s = ring.stats();
+            stats.received += s.received;
+            stats.dropped += s.dropped;
+        }
+        return stats;
+    }
+}
+
+// SOCK_RAW keeps the device link header, tunnels and ppp have none
+fn link_type(fd: c_int, name: &str) -> Result<LinkType, String> {
+    let mut req = IfReqHwAddr {
+        name: [0; 16],
+        family: 0,
+        data: [0; 22],
+    };
+    if name.len() >= req.name.len() {
+        return Err("invalid interface name".to_string());
+    }
+    req.name[..name.len()].copy_from_slice(name.as_bytes());
+
+    let ret = unsafe { libc::ioctl(fd, SIOCGIFHWADDR, &mut req as *mut IfReqHwAddr) };
+    if ret != 0 {
+        return Err(format!("SIOCGIFHWADDR error {}", io::Error::last_os_error()));
+    }
+
+    match req.family {
+        ARPHRD_ETHER | ARPHRD_LOOPBACK => Ok(LinkType::EN10MB),
+        ARPHRD_NONE | ARPHRD_PPP | ARPHRD_RAWIP | ARPHRD_TUNNEL | ARPHRD_TUNNEL6 | ARPHRD_SIT
+        | ARPHRD_IPGRE => Ok(LinkType::RAW),
+        family => Err(format!("unsupported hardware type {}", family)),
+    }
+}
+
+pub fn init(interface: &InterfaceConfig, filter: &str) -> Result<Arc<AfPacketSource>, String> {
+    let source = AfPacketSource::open(interface, None, filter)?;
+    Ok(Arc::new(source))
+}
+
+// group ids are per process, index tells apart the groups of several interfaces
+pub fn init_fanout(
+    interface: &InterfaceConfig,
+    index: u16,
+    n: u8,
+    filter: &str,
+) -> Result<Arc<AfPacketGroup>, String> {
+    let group = (process::id() as u16).wrapping_add(index);
+    let mut rings = Vec::new();
+    for _i in 0..n {
+        let source = AfPacketSource::open(interface, Some(group), filter)?;
+        rings.push(Arc::new(source));
+    }
+    debug!("af_packet fanout group {}, sockets {}", group, n);
+
+    Ok(Arc::new(AfPacketGroup {
+        name: interface.name.clone(),
+        rings,
+        running: AtomicBool::new(true),
+    }))
+}
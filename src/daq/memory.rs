@@ -0,0 +1,76 @@
+use daq::{self, PacketSource, Stats};
+use layer::packet::Packet;
+use layer::LinkType;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+// replays packets built in memory, for tests and for library users that
+// decode frames with their own capture stack
+pub struct MemorySource {
+    name: Arc<str>,
+    linktype: LinkType,
+    packets: Mutex<Vec<Arc<Packet>>>,
+    running: AtomicBool,
+    received: AtomicU64,
+}
+
+impl MemorySource {
+    // ethernet frames
+    pub fn new(name: &str) -> MemorySource {
+        MemorySource::with_linktype(name, LinkType::EN10MB)
+    }
+
+    pub fn with_linktype(name: &str, linktype: LinkType) -> MemorySource {
+        MemorySource {
+            name: Arc::from(name),
+            linktype,
+            packets: Mutex::new(Vec::new()),
+            running: AtomicBool::new(true),
+            received: AtomicU64::new(0),
+        }
+    }
+
+    pub fn push(&self, packet: Arc<Packet>) {
+        self.packets.lock().unwrap().push(packet);
+    }
+
+    // timestamp in nano second
+    pub fn push_frame(&self, timestamp: u64, data: &[u8]) {
+        let packet = Packet::new(&self.name, self.linktype, timestamp, data);
+        self.push(packet);
+    }
+}
+
+impl PacketSource for MemorySource {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn run(&self, cb: &Fn(Arc<Packet>)) {
+        let packets: Vec<Arc<Packet>> = self.packets.lock().unwrap().drain(..).collect();
+        for packet in packets.into_iter() {
+            if !self.running.load(Ordering::SeqCst) {
+                break;
+            }
+            if !daq::accept(&packet) {
+                continue;
+            }
+            self.received.fetch_add(1, Ordering::Relaxed);
+            cb(packet);
+        }
+    }
+
+    fn stop(&self) {
+        self.running.store(false, Ordering::SeqCst);
+    }
+
+    fn stats(&self) -> Stats {
+        let mut stats = Stats::default();
+        stats.received = self.received.load(Ordering::Relaxed);
+        return stats;
+    }
+
+    fn is_offline(&self) -> bool {
+        true
+    }
+}
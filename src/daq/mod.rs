@@ -0,0 +1,66 @@
+use event::{self, Event};
+use layer::packet::Packet;
+use stats;
+use std::sync::Arc;
+
+pub mod afpacket;
+pub mod memory;
+pub mod pcap;
+
+pub use self::afpacket::{AfPacketGroup, AfPacketSource};
+pub use self::memory::MemorySource;
+pub use self::pcap::PcapSource;
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Stats {
+    // packets handed to the callback
+    pub received: u64,
+    // dropped by the capture buffer
+    pub dropped: u64,
+    // dropped by the network interface or driver
+    pub if_dropped: u64,
+}
+
+// whether a freshly decoded packet goes on to the callback; the ones that failed
+// to decode are counted by reason, both they and anomalies become events if enabled
+pub fn accept(packet: &Packet) -> bool {
+    if let Some(error) = packet.error {
+        debug!("invalid packet {} 0b{:b}", error.to_string(), packet.state);
+        stats::count_decode_error(error);
+        if event::anomalies_enabled() {
+            event::emit(Event::DecodeError {
+                timestamp: packet.timestamp,
+                interface: packet.interface.clone(),
+                error,
+            });
+        }
+        return false;
+    }
+
+    if !packet.anomalies.is_empty() && event::anomalies_enabled() {
+        for anomaly in packet.anomalies.iter() {
+            event::emit(Event::Anomaly {
+                timestamp: packet.timestamp,
+                interface: packet.interface.clone(),
+                src_ip: packet.src_ip,
+                dst_ip: packet.dst_ip,
+                anomaly: *anomaly,
+            });
+        }
+    }
+    return true;
+}
+
+// a capture backend, feeds decoded packets into the callback until it is stopped
+// or, for offline sources, until the input is exhausted
+pub trait PacketSource: Send + Sync {
+    fn name(&self) -> &str;
+    fn run(&self, cb: &Fn(Arc<Packet>));
+    fn stop(&self);
+    fn stats(&self) -> Stats;
+
+    // offline sources carry their own clock and end by themselves
+    fn is_offline(&self) -> bool {
+        false
+    }
+}
@@ -0,0 +1,486 @@
+use config::InterfaceConfig;
+use daq::{self, PacketSource, Stats};
+use layer::packet::Packet;
+use layer::{BufferPool, LinkType};
+use libc::{c_char, c_int, c_uint};
+use std::ffi::{CStr, CString};
+use std::ptr;
+use std::slice;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+const PCAP_ERRBUF_SIZE: usize = 256;
+const PCAP_NETMASK_UNKNOWN: u32 = 0xffffffff;
+const PCAP_TSTAMP_PRECISION_MICRO: c_int = 0;
+const PCAP_TSTAMP_PRECISION_NANO: c_int = 1;
+
+// libpcap backend, captures from a live interface or replays capture files
+pub struct PcapSource {
+    name: String,
+    // locked across breakloop, stats and close so none of them sees a closed handle
+    handle: Mutex<Handle>,
+    files: Vec<String>,
+    filter: String,
+    running: AtomicBool,
+    received: AtomicU64,
+    // live capture only
+    pool: Option<Arc<BufferPool>>,
+}
+
+struct Handle(*const c_char);
+
+unsafe impl Send for Handle {}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct Timeval {
+    sec: u64,
+    // microseconds, or nanoseconds when the handle has nanosecond precision
+    usec: u64,
+}
+
+struct LoopContext<'a> {
+    cb: &'a Fn(Arc<Packet>),
+    interface: Arc<str>,
+    linktype: LinkType,
+    nanosecond: bool,
+    pool: Option<Arc<BufferPool>>,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct BpfInsn {
+    pub code: u16,
+    pub jt: u8,
+    pub jf: u8,
+    pub k: u32,
+}
+
+#[repr(C)]
+struct PcapProgram {
+    len: c_uint,
+    insns: *mut BpfInsn,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct PcapStat {
+    recv: c_uint,
+    drop: c_uint,
+    ifdrop: c_uint,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct PacketHeader {
+    pub ts: Timeval,
+    pub caplen: c_uint,
+    pub len: c_uint,
+}
+
+extern "C" fn loop_callback(ctx: *mut c_char, packet: *const PacketHeader, bytes: *const c_char) {
+    unsafe {
+        let ctx = &*(ctx as *const LoopContext);
+        let ts = (*packet).ts;
+        let tm = if ctx.nanosecond {
+            ts.sec * 1000 * 1000 * 1000 + ts.usec
+        } else {
+            ts.sec * 1000 * 1000 * 1000 + ts.usec * 1000
+        };
+        let data = slice::from_raw_parts(bytes as *const u8, (*packet).caplen as usize);
+        let p = match ctx.pool {
+            Some(ref pool) => Packet::with_pool(&ctx.interface, ctx.linktype, tm, data, pool),
+            None => Packet::new(&ctx.interface, ctx.linktype, tm, data),
+        };
+        if daq::accept(&p) {
+            (ctx.cb)(p);
+        }
+    };
+}
+
+#[link(name = "pcap")]
+extern "C" {
+    fn pcap_create(_device: *const c_char, _error: *mut c_char) -> *const c_char;
+    fn pcap_open_offline_with_tstamp_precision(
+        _fname: *const c_char,
+        _precision: c_int,
+        _error: *mut c_char,
+    ) -> *const c_char;
+    fn pcap_set_snaplen(_handle: *const c_char, _snaplen: c_int) -> c_int;
+    fn pcap_set_buffer_size(_handle: *const c_char, _buffer_size: c_int) -> c_int;
+    fn pcap_set_promisc(_handle: *const c_char, _promisc: c_int) -> c_int;
+    fn pcap_set_immediate_mode(_handle: *const c_char, _immediate: c_int) -> c_int;
+    fn pcap_set_timeout(_handle: *const c_char, _timeout: c_int) -> c_int;
+    fn pcap_set_tstamp_precision(_handle: *const c_char, _precision: c_int) -> c_int;
+    fn pcap_get_tstamp_precision(_handle: *const c_char) -> c_int;
+    fn pcap_datalink(_handle: *const c_char) -> c_int;
+    fn pcap_activate(_handle: *const c_char) -> c_int;
+    fn pcap_close(_handle: *const c_char);
+    fn pcap_loop(
+        _handle: *const c_char,
+        _count: c_int,
+        _cb: extern "C" fn(ctx: *mut c_char, *const PacketHeader, *const c_char),
+        _ctx: *const c_char,
+    ) -> c_int;
+
+    fn pcap_breakloop(_handle: *const c_char);
+    fn pcap_geterr(_handle: *const c_char) -> *const c_char;
+    fn pcap_stats(_handle: *const c_char, _stat: *mut PcapStat) -> c_int;
+    fn pcap_open_dead(_linktype: c_int, _snaplen: c_int) -> *const c_char;
+    fn pcap_compile(
+        _handle: *const c_char,
+        _program: *mut PcapProgram,
+        _filter: *const c_char,
+        _optimize: c_int,
+        _netmask: u32,
+    ) -> c_int;
+    fn pcap_setfilter(_handle: *const c_char, _program: *const PcapProgram) -> c_int;
+    fn pcap_freecode(_program: *mut PcapProgram);
+}
+
+impl PacketSource for PcapSource {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn run(&self, cb: &Fn(Arc<Packet>)) {
+        let cb = move |packet: Arc<Packet>| {
+            self.received.fetch_add(1, Ordering::Relaxed);
+            cb(packet);
+        };
+
+        if !self.is_offline() {
+            self.run_loop(&self.name, &cb);
+            return;
+        }
+
+        for file in self.files.iter() {
+            if !self.running.load(Ordering::SeqCst) {
+                break;
+            }
+            match open_offline(file, &self.filter) {
+                Ok(h) => {
+                    info!("read file {}", file);
+                    self.handle.lock().unwrap().0 = h;
+                    self.run_loop(file, &cb);
+                    let mut handle = self.handle.lock().unwrap();
+                    unsafe {
+                        pcap_close(handle.0);
+                    }
+                    handle.0 = ptr::null();
+                }
+                Err(err) => {
+                    error!("skip file {}, {}", file, err);
+                }
+            }
+        }
+        info!("all files done");
+    }
+
+    fn stop(&self) {
+        self.running.store(false, Ordering::SeqCst);
+        let handle = self.handle.lock().unwrap();
+        if !handle.0.is_null() {
+            unsafe {
+                pcap_breakloop(handle.0);
+            }
+        }
+    }
+
+    fn stats(&self) -> Stats {
+        let mut stats = Stats::default();
+        stats.received = self.received.load(Ordering::Relaxed);
+
+        // pcap_stats is not supported on savefiles
+        if self.is_offline() {
+            return stats;
+        }
+
+        let guard = self.handle.lock().unwrap();
+        let handle = guard.0;
+        if handle.is_null() {
+            return stats;
+        }
+        let mut stat = PcapStat {
+            recv: 0,
+            drop: 0,
+            ifdrop: 0,
+        };
+        unsafe {
+            if pcap_stats(handle, &mut stat) != 0 {
+                debug!(
+                    "pcap_stats error {}",
+                    CStr::from_ptr(pcap_geterr(handle)).to_string_lossy()
+                );
+                return stats;
+            }
+        }
+        stats.dropped = stat.drop as u64;
+        stats.if_dropped = stat.ifdrop as u64;
+        return stats;
+    }
+
+    #[inline]
+    fn is_offline(&self) -> bool {
+        !self.files.is_empty()
+    }
+}
+
+impl PcapSource {
+    // the handle is only closed by the thread running the loop, after it returns
+    fn run_loop(&self, interface: &str, cb: &Fn(Arc<Packet>)) {
+        let handle = self.handle.lock().unwrap().0;
+        let ctx = LoopContext {
+            cb,
+            interface: Arc::from(interface),
+            linktype: LinkType(unsafe { pcap_datalink(handle) }),
+            nanosecond: unsafe { pcap_get_tstamp_precision(handle) } == PCAP_TSTAMP_PRECISION_NANO,
+            pool: self.pool.clone(),
+        };
+        info!("pcap_loop");
+        let ret = unsafe {
+            pcap_loop(
+                handle,
+                -1,
+                loop_callback,
+                &ctx as *const LoopContext as *const c_char,
+            )
+        };
+        if ret == -1 {
+            error!("pcap_loop error {}", unsafe {
+                CStr::from_ptr(pcap_geterr(handle)).to_string_lossy()
+            });
+        }
+        debug!("pcap_loop exit {}", ret);
+    }
+}
+
+impl Drop for PcapSource {
+    fn drop(&mut self) {
+        debug!("daq cleanup");
+        let handle = self.handle.get_mut().unwrap();
+        if !handle.0.is_null() {
+            unsafe {
+                pcap_close(handle.0);
+            }
+        }
+        handle.0 = ptr::null();
+    }
+}
+
+pub fn init(interface: &InterfaceConfig, filter: &str) -> Result<Arc<PcapSource>, String> {
+    let handle = open_device(interface)?;
+    if let Err(err) = check_datalink(handle).and_then(|_| set_filter(handle, filter)) {
+        unsafe {
+            pcap_close(handle);
+        }
+        return Err(err);
+    }
+
+    let daq = PcapSource {
+        name: interface.name.clone(),
+        handle: Mutex::new(Handle(handle)),
+        files: Vec::new(),
+        filter: filter.to_string(),
+        running: AtomicBool::new(true),
+        received: AtomicU64::new(0),
+        pool: new_pool(interface),
+    };
+    return Ok(Arc::new(daq));
+}
+
+// classic pcap and pcapng files, read one after another in the given order
+pub fn init_offline(files: &[String], filter: &str) -> Result<Arc<PcapSource>, String> {
+    for file in files.iter() {
+        let handle = open_offline(file, filter)?;
+        unsafe {
+            pcap_close(handle);
+        }
+    }
+
+    let daq = PcapSource {
+        name: "files".to_string(),
+        handle: Mutex::new(Handle(ptr::null())),
+        files: files.to_vec(),
+        filter: filter.to_string(),
+        running: AtomicBool::new(true),
+        received: AtomicU64::new(0),
+        pool: None,
+    };
+    return Ok(Arc::new(daq));
+}
+
+pub fn new_pool(interface: &InterfaceConfig) -> Option<Arc<BufferPool>> {
+    if interface.buffer_pool == 0 {
+        return None;
+    }
+    Some(BufferPool::new(interface.buffer_pool))
+}
+
+pub struct BpfProgram {
+    pub insns: Vec<BpfInsn>,
+}
+
+// compile a filter for sockets that do not go through libpcap
+pub fn compile_filter(linktype: c_int, snaplen: c_int, filter: &str) -> Result<BpfProgram, String> {
+    unsafe {
+        let handle = pcap_open_dead(linktype, snaplen);
+        if handle.is_null() {
+            return Err("pcap_open_dead error".to_string());
+        }
+
+        let ret = compile(handle, filter).map(|program| {
+            let insns =
+                slice::from_raw_parts(program.insns, program.len as usize).to_vec();
+            let mut program = program;
+            pcap_freecode(&mut program);
+            BpfProgram { insns }
+        });
+        pcap_close(handle);
+        return ret;
+    }
+}
+
+fn compile(handle: *const c_char, filter: &str) -> Result<PcapProgram, String> {
+    let expression = CString::new(filter.as_bytes())
+        .map_err(|_| format!("invalid bpf filter \"{}\"", filter))?;
+    let mut program = PcapProgram {
+        len: 0,
+        insns: ptr::null_mut(),
+    };
+    unsafe {
+        let ret = pcap_compile(
+            handle,
+            &mut program,
+            expression.as_ptr(),
+            1,
+            PCAP_NETMASK_UNKNOWN,
+        );
+        if ret != 0 {
+            return Err(format!(
+                "invalid bpf filter \"{}\": {}",
+                filter,
+                CStr::from_ptr(pcap_geterr(handle)).to_string_lossy()
+            ));
+        }
+    }
+    return Ok(program);
+}
+
+fn check_datalink(handle: *const c_char) -> Result<(), String> {
+    let linktype = LinkType(unsafe { pcap_datalink(handle) });
+    if !linktype.is_supported() {
+        return Err(format!("unsupported link type {}", linktype.0));
+    }
+    debug!("link type {}", LinkType::link_type_string(linktype));
+    return Ok(());
+}
+
+fn set_filter(handle: *const c_char, filter: &str) -> Result<(), String> {
+    if filter.is_empty() {
+        return Ok(());
+    }
+
+    let mut program = compile(handle, filter)?;
+    unsafe {
+        let ret = pcap_setfilter(handle, &program);
+        pcap_freecode(&mut program);
+        if ret != 0 {
+            return Err(format!(
+                "pcap_setfilter error {}",
+                CStr::from_ptr(pcap_geterr(handle)).to_string_lossy()
+            ));
+        }
+    }
+    debug!("bpf filter \"{}\"", filter);
+    return Ok(());
+}
+
+fn open_offline(file: &str, filter: &str) -> Result<*const c_char, String> {
+    let file = CString::new(file.as_bytes()).unwrap();
+    let mut buffer: [c_char; PCAP_ERRBUF_SIZE] = [0; PCAP_ERRBUF_SIZE];
+    unsafe {
+        let handle = pcap_open_offline_with_tstamp_precision(
+            file.as_ptr(),
+            PCAP_TSTAMP_PRECISION_NANO,
+            buffer.as_mut_ptr(),
+        );
+        if handle.is_null() {
+            return Err(format!(
+                "pcap_open_offline error {}",
+                CStr::from_ptr(buffer.as_ptr()).to_string_lossy()
+            ));
+        }
+        if let Err(err) = check_datalink(handle).and_then(|_| set_filter(handle, filter)) {
+            pcap_close(handle);
+            return Err(err);
+        }
+        return Ok(handle);
+    }
+}
+
+fn open_device(interface: &InterfaceConfig) -> Result<*const c_char, String> {
+    let device = CString::new(interface.name.as_bytes()).unwrap();
+    let mut buffer: [c_char; PCAP_ERRBUF_SIZE] = [0; PCAP_ERRBUF_SIZE];
+    unsafe {
+        let handle = pcap_create(device.as_ptr(), buffer.as_mut_ptr());
+
+        if handle.is_null() {
+            return Err(format!(
+                "pcap_create error {}",
+                CStr::from_ptr(buffer.as_ptr()).to_string_lossy()
+            ));
+        }
+
+        let ret = pcap_set_snaplen(handle, interface.snaplen as c_int);
+        if ret != 0 {
+            pcap_close(handle);
+            return Err("pcap_set_snaplen error".to_string());
+        }
+
+        let ret = pcap_set_buffer_size(handle, interface.buffer_size as c_int);
+        if ret != 0 {
+            pcap_close(handle);
+            return Err("pcap_set_buffer_size error".to_string());
+        }
+
+        let ret = pcap_set_promisc(handle, interface.promisc as c_int);
+        if ret != 0 {
+            pcap_close(handle);
+            return Err("pcap_set_promisc error".to_string());
+        }
+
+        let ret = pcap_set_immediate_mode(handle, interface.immediate as c_int);
+        if ret != 0 {
+            pcap_close(handle);
+            return Err("pcap_set_immediate_mode error".to_string());
+        }
+
+        let ret = pcap_set_timeout(handle, interface.timeout as c_int);
+        if ret != 0 {
+            pcap_close(handle);
+            return Err("pcap_set_timeout error".to_string());
+        }
+
+        let precision = if interface.nanosecond {
+            PCAP_TSTAMP_PRECISION_NANO
+        } else {
+            PCAP_TSTAMP_PRECISION_MICRO
+        };
+        if pcap_set_tstamp_precision(handle, precision) != 0 {
+            warn!(
+                "{} does not support nanosecond timestamps, use microseconds",
+                interface.name
+            );
+        }
+
+        let ret = pcap_activate(handle);
+
+        if ret != 0 {
+            let err = CStr::from_ptr(pcap_geterr(handle)).to_string_lossy().into_owned();
+            pcap_close(handle);
+            return Err(format!("pcap_activate error {}", err));
+        }
+        return Ok(handle);
+    }
+}
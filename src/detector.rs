@@ -309,6 +309,8 @@ extern "C" {
         len: u32,
     ) -> *const c_char;
 
+    fn ndpi_category_get_name(ctx: *const c_char, category: u32) -> *const c_char;
+
     fn ndpi_get_http_url(ctx: *const c_char, flow: *const c_char) -> *const c_char;
 
     fn ndpi_get_http_content_type(ctx: *const c_char, flow: *const c_char) -> *const c_char;
@@ -341,6 +343,7 @@ impl Detector {
         }
     }
 
+    // tm in nano second, ndpi is fed micro seconds
     #[inline]
     pub fn detect(
         &self,
@@ -357,7 +360,7 @@ impl Detector {
                 flow,
                 ip_layer as *const c_char,
                 ip_layer_len,
-                tm,
+                tm / 1000,
                 src_id,
                 dst_id,
             )
@@ -395,8 +398,18 @@ impl Detector {
         let c_str;
         unsafe {
             ndpi_protocol2name(self.ctx, *proto, array.as_mut_ptr() as *mut i8, 16);
-            c_str = CStr::from_bytes_with_nul_unchecked(&array);
+            // the name ends at the first nul, the rest of the buffer is not part of it
+            c_str = CStr::from_ptr(array.as_ptr() as *const c_char);
+        }
+        c_str.to_string_lossy().into_owned()
+    }
+
+    pub fn category_name(&self, proto: &Proto) -> String {
+        let name = unsafe { ndpi_category_get_name(self.ctx, proto.category) };
+        if name.is_null() {
+            return "Unspecified".to_string();
         }
+        let c_str = unsafe { CStr::from_ptr(name) };
         c_str.to_string_lossy().into_owned()
     }
 
@@ -0,0 +1,230 @@
+use layer::{mac_to_string, Anomaly, DecodeError};
+use sink::Sinks;
+pub use sink::{FileSink, LogSink};
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+// something worth telling the operator about, raised by the workers
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Event {
+    // first mac seen for an address
+    ArpNew {
+        timestamp: u64,
+        interface: Arc<str>,
+        ip: IpAddr,
+        mac: [u8; 6],
+    },
+    // the address moved to another mac, possible spoofing
+    ArpChanged {
+        timestamp: u64,
+        interface: Arc<str>,
+        ip: IpAddr,
+        old_mac: [u8; 6],
+        new_mac: [u8; 6],
+    },
+    // too many gratuitous announcements for one address in a window
+    ArpStorm {
+        timestamp: u64,
+        interface: Arc<str>,
+        ip: IpAddr,
+        mac: [u8; 6],
+        count: u64,
+    },
+    // a frame the decoder gave up on
+    DecodeError {
+        timestamp: u64,
+        interface: Arc<str>,
+        error: DecodeError,
+    },
+    // something odd in a frame that decoded
+    Anomaly {
+        timestamp: u64,
+        interface: Arc<str>,
+        src_ip: IpAddr,
+        dst_ip: IpAddr,
+        anomaly: Anomaly,
+    },
+    // a tcp segment overlapping buffered data with other content, what the
+    // monitored host keeps may not be what the dissectors saw
+    TCPOverlap {
+        timestamp: u64,
+        interface: Arc<str>,
+        src_ip: IpAddr,
+        src_port: u16,
+        dst_ip: IpAddr,
+        dst_port: u16,
+        // bytes that differ
+        bytes: u64,
+    },
+}
+
+impl Event {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Event::ArpNew { .. } => "arp_new",
+            Event::ArpChanged { .. } => "arp_changed",
+            Event::ArpStorm { .. } => "arp_storm",
+            Event::DecodeError { .. } => "decode_error",
+            Event::Anomaly { .. } => "anomaly",
+            Event::TCPOverlap { .. } => "tcp_overlap",
+        }
+    }
+
+    // nano second, packet time
+    pub fn timestamp(&self) -> u64 {
+        match *self {
+            Event::ArpNew { timestamp, .. } => timestamp,
+            Event::ArpChanged { timestamp, .. } => timestamp,
+            Event::ArpStorm { timestamp, .. } => timestamp,
+            Event::DecodeError { timestamp, .. } => timestamp,
+            Event::Anomaly { timestamp, .. } => timestamp,
+            Event::TCPOverlap { timestamp, .. } => timestamp,
+        }
+    }
+
+    // alerts are logged as warnings, the rest is informational
+    pub fn is_alert(&self) -> bool {
+        match *self {
+            Event::ArpNew { .. } | Event::DecodeError { .. } | Event::Anomaly { .. } => false,
+            _ => true,
+        }
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Event::ArpNew {
+                ref interface,
+                ref ip,
+                ref mac,
+                ..
+            } => write!(
+                f,
+                "{} {} {} is-at {}",
+                self.name(),
+                interface,
+                ip,
+                mac_to_string(mac)
+            ),
+            Event::ArpChanged {
+                ref interface,
+                ref ip,
+                ref old_mac,
+                ref new_mac,
+                ..
+            } => write!(
+                f,
+                "{} {} {} was-at {} is-at {}",
+                self.name(),
+                interface,
+                ip,
+                mac_to_string(old_mac),
+                mac_to_string(new_mac)
+            ),
+            Event::ArpStorm {
+                ref interface,
+                ref ip,
+                ref mac,
+                count,
+                ..
+            } => write!(
+                f,
+                "{} {} {} is-at {} count {}",
+                self.name(),
+                interface,
+                ip,
+                mac_to_string(mac),
+                count
+            ),
+            Event::DecodeError {
+                ref interface,
+                error,
+                ..
+            } => write!(f, "{} {} {}", self.name(), interface, error.to_string()),
+            Event::Anomaly {
+                ref interface,
+                ref src_ip,
+                ref dst_ip,
+                anomaly,
+                ..
+            } => write!(
+                f,
+                "{} {} {} -> {} {}",
+                self.name(),
+                interface,
+                src_ip,
+                dst_ip,
+                anomaly.to_string()
+            ),
+            Event::TCPOverlap {
+                ref interface,
+                ref src_ip,
+                src_port,
+                ref dst_ip,
+                dst_port,
+                bytes,
+                ..
+            } => write!(
+                f,
+                "{} {} {}:{} -> {}:{} bytes {}",
+                self.name(),
+                interface,
+                src_ip,
+                src_port,
+                dst_ip,
+                dst_port,
+                bytes
+            ),
+        }
+    }
+}
+
+pub trait EventSink: Send + Sync {
+    fn on_event(&self, event: &Event);
+}
+
+// events into the application log
+impl EventSink for LogSink {
+    fn on_event(&self, event: &Event) {
+        if event.is_alert() {
+            warn!("event {}", event);
+        } else {
+            info!("event {}", event);
+        }
+    }
+}
+
+// one line per event, prefixed with the packet time
+impl EventSink for FileSink {
+    fn on_event(&self, event: &Event) {
+        if let Err(err) = self.write_line(event.timestamp(), event) {
+            error!("write event error {}", err);
+        }
+    }
+}
+
+lazy_static! {
+    static ref SINKS: Sinks<EventSink> = Sinks::new();
+    // decode errors and anomalies are one event per packet, off unless asked for
+    static ref ANOMALIES: AtomicBool = AtomicBool::new(false);
+}
+
+pub fn register_sink(sink: Arc<EventSink>) {
+    SINKS.register(sink);
+}
+
+pub fn enable_anomalies(enable: bool) {
+    ANOMALIES.store(enable, Ordering::Relaxed);
+}
+
+pub fn anomalies_enabled() -> bool {
+    ANOMALIES.load(Ordering::Relaxed)
+}
+
+// hand the event to every registered sink
+pub fn emit(event: Event) {
+    SINKS.for_each(|sink| sink.on_event(&event));
+}
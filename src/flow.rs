@@ -0,0 +1,236 @@
+use layer::icmp::ICMPError;
+use layer::packet::Packet;
+use layer::tcp::{SynFingerprint, TCPCounters, TCPHeader, TCPOutcome, TCPState};
+use layer::IPProto;
+use sink::Sinks;
+pub use sink::{FileSink, LogSink};
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::Arc;
+
+// what one end of a flow sent
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FlowCounters {
+    pub packets: u64,
+    // ip layer bytes
+    pub bytes: u64,
+    // tcp flags of all its segments or-ed together
+    pub tcp_flags: u8,
+}
+
+impl FlowCounters {
+    pub fn on_packet(&mut self, packet: &Packet) {
+        self.packets += 1;
+        self.bytes += packet.ip_layer_len as u64;
+        if let Some(tcp) = packet.tcp() {
+            self.tcp_flags |= tcp.flags();
+        }
+    }
+}
+
+// tcpdump letters, - when none was seen
+pub fn tcp_flags_to_string(flags: u8) -> String {
+    let names = [
+        (TCPHeader::SYN, 'S'),
+        (TCPHeader::FIN, 'F'),
+        (TCPHeader::RST, 'R'),
+        (TCPHeader::PUSH, 'P'),
+        (TCPHeader::ACK, '.'),
+        (TCPHeader::URG, 'U'),
+    ];
+    let mut ret = String::new();
+    for &(flag, name) in names.iter() {
+        if flags & flag > 0 {
+            ret.push(name);
+        }
+    }
+    if ret.is_empty() {
+        ret.push('-');
+    }
+    return ret;
+}
+
+// why the tracker dropped a stream
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CloseReason {
+    // the connection ended, the tcp outcome tells how
+    Finished,
+    // nothing seen for the cleanup duration
+    Idle,
+    // a new connection took over its addresses and ports
+    Reused,
+    // still open when the capture stopped
+    Shutdown,
+}
+
+impl CloseReason {
+    pub fn to_string(self) -> &'static str {
+        match self {
+            CloseReason::Finished => "finished",
+            CloseReason::Idle => "idle",
+            CloseReason::Reused => "reused",
+            CloseReason::Shutdown => "shutdown",
+        }
+    }
+}
+
+// what only a tcp stream knows
+#[derive(Clone, Debug, PartialEq)]
+pub struct TCPFlowRecord {
+    pub state: TCPState,
+    pub outcome: Option<TCPOutcome>,
+    pub client_fingerprint: Option<SynFingerprint>,
+    pub server_fingerprint: Option<SynFingerprint>,
+    // smoothed, nano second
+    pub client_rtt: Option<u64>,
+    pub server_rtt: Option<u64>,
+    pub client_counters: TCPCounters,
+    pub server_counters: TCPCounters,
+}
+
+// a stream summed up when it is dropped
+#[derive(Clone, Debug, PartialEq)]
+pub struct FlowRecord {
+    pub ip_proto: IPProto,
+    //ingress interface of the first packet
+    pub interface: Arc<str>,
+    pub client: IpAddr,
+    pub client_port: u16,
+    pub server: IpAddr,
+    pub server_port: u16,
+    // nano second, first and last packet
+    pub start: u64,
+    pub end: u64,
+    pub from_client: FlowCounters,
+    pub from_server: FlowCounters,
+    // ndpi names, unknown when detection failed
+    pub protocol: String,
+    pub category: String,
+    pub reason: CloseReason,
+    // the last icmp error quoting the stream
+    pub icmp_error: Option<ICMPError>,
+    pub tcp: Option<TCPFlowRecord>,
+}
+
+impl FlowRecord {
+    // nano second
+    pub fn duration(&self) -> u64 {
+        self.end.saturating_sub(self.start)
+    }
+}
+
+fn write_time(f: &mut fmt::Formatter, tm: u64) -> fmt::Result {
+    write!(f, "{}.{:09}", tm / 1000000000, tm % 1000000000)
+}
+
+fn write_rtt(f: &mut fmt::Formatter, name: &str, rtt: Option<u64>) -> fmt::Result {
+    match rtt {
+        Some(rtt) => write!(f, " {}_rtt_us {}", name, rtt / 1000),
+        None => write!(f, " {}_rtt_us -", name),
+    }
+}
+
+impl fmt::Display for FlowRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let transport = match self.ip_proto {
+            IPProto::TCP => "tcp",
+            IPProto::UDP => "udp",
+            _ => "ip",
+        };
+        write!(
+            f,
+            "{} {} {}:{} -> {}:{} protocol {} category {} start ",
+            transport,
+            self.interface,
+            self.client,
+            self.client_port,
+            self.server,
+            self.server_port,
+            self.protocol,
+            self.category
+        )?;
+        write_time(f, self.start)?;
+        write!(f, " duration ")?;
+        write_time(f, self.duration())?;
+        write!(
+            f,
+            " reason {} client packets {} bytes {} server packets {} bytes {}",
+            self.reason.to_string(),
+            self.from_client.packets,
+            self.from_client.bytes,
+            self.from_server.packets,
+            self.from_server.bytes
+        )?;
+        match self.icmp_error {
+            Some(ref error) => write!(f, " icmp_error {}", error)?,
+            None => write!(f, " icmp_error -")?,
+        }
+
+        let tcp = match self.tcp {
+            Some(ref tcp) => tcp,
+            None => return Ok(()),
+        };
+        write!(
+            f,
+            " client_flags {} server_flags {} state {} outcome {}",
+            tcp_flags_to_string(self.from_client.tcp_flags),
+            tcp_flags_to_string(self.from_server.tcp_flags),
+            tcp.state.to_string(),
+            tcp.outcome
+                .map_or("unfinished", |outcome| outcome.to_string())
+        )?;
+        write_rtt(f, "client", tcp.client_rtt)?;
+        write_rtt(f, "server", tcp.server_rtt)?;
+        match tcp.client_fingerprint {
+            Some(ref fingerprint) => write!(f, " client_fingerprint {}", fingerprint)?,
+            None => write!(f, " client_fingerprint -")?,
+        }
+        match tcp.server_fingerprint {
+            Some(ref fingerprint) => write!(f, " server_fingerprint {}", fingerprint)?,
+            None => write!(f, " server_fingerprint -")?,
+        }
+        write!(
+            f,
+            " client {} server {}",
+            tcp.client_counters, tcp.server_counters
+        )
+    }
+}
+
+pub trait FlowSink: Send + Sync {
+    fn on_flow(&self, record: &FlowRecord);
+}
+
+// flow records into the application log
+impl FlowSink for LogSink {
+    fn on_flow(&self, record: &FlowRecord) {
+        info!("flow {}", record);
+    }
+}
+
+// one line per flow, prefixed with the time of its last packet
+impl FlowSink for FileSink {
+    fn on_flow(&self, record: &FlowRecord) {
+        if let Err(err) = self.write_line(record.end, record) {
+            error!("write flow error {}", err);
+        }
+    }
+}
+
+lazy_static! {
+    static ref SINKS: Sinks<FlowSink> = Sinks::new();
+}
+
+pub fn register_sink(sink: Arc<FlowSink>) {
+    SINKS.register(sink);
+}
+
+// no record is built when nobody takes it
+pub fn enabled() -> bool {
+    !SINKS.is_empty()
+}
+
+// hand the record to every registered sink
+pub fn emit(record: &FlowRecord) {
+    SINKS.for_each(|sink| sink.on_flow(record));
+}
@@ -22,3 +22,14 @@ pub fn ip_to_string(ip: u32) -> String {
     }
     return c_str.to_string_lossy().into_owned();
 }
+
+// big endian reads for the header views, which check their length up front
+#[inline]
+pub fn read_u16(data: &[u8], offset: usize) -> u16 {
+    return (data[offset] as u16) << 8 | data[offset + 1] as u16;
+}
+
+#[inline]
+pub fn read_u32(data: &[u8], offset: usize) -> u32 {
+    return (read_u16(data, offset) as u32) << 16 | read_u16(data, offset + 2) as u32;
+}
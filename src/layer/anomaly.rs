@@ -0,0 +1,121 @@
+// why a frame could not be decoded, the first failure stops the decoder
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum DecodeError {
+    UnsupportedLinkType,
+    // ethernet, linux cooked or loopback header cut short
+    LinkTruncated,
+    VlanTruncated,
+    MPLSTruncated,
+    // pppoe header cut short or its length past the frame
+    PPPoELength,
+    ARPTruncated,
+    // ip header, or the datagram its length announces, past the end of the frame
+    IPTruncated,
+    IPVersion,
+    // ihl below 5 words
+    IPv4HeaderLen,
+    // total length shorter than the header
+    IPLength,
+    IPv6ExtHeader,
+    TCPTruncated,
+    // data offset below 5 words or past the segment
+    TCPDataOffset,
+    UDPTruncated,
+    // udp length past the ip payload
+    UDPLength,
+    ICMPTruncated,
+    // gre header, its optional fields or the erspan header
+    GRETruncated,
+}
+
+impl DecodeError {
+    pub const COUNT: usize = 17;
+
+    pub const ALL: [DecodeError; DecodeError::COUNT] = [
+        DecodeError::UnsupportedLinkType,
+        DecodeError::LinkTruncated,
+        DecodeError::VlanTruncated,
+        DecodeError::MPLSTruncated,
+        DecodeError::PPPoELength,
+        DecodeError::ARPTruncated,
+        DecodeError::IPTruncated,
+        DecodeError::IPVersion,
+        DecodeError::IPv4HeaderLen,
+        DecodeError::IPLength,
+        DecodeError::IPv6ExtHeader,
+        DecodeError::TCPTruncated,
+        DecodeError::TCPDataOffset,
+        DecodeError::UDPTruncated,
+        DecodeError::UDPLength,
+        DecodeError::ICMPTruncated,
+        DecodeError::GRETruncated,
+    ];
+
+    // position in ALL, for counters kept in arrays
+    pub fn index(self) -> usize {
+        self as usize
+    }
+
+    pub fn to_string(self) -> &'static str {
+        match self {
+            DecodeError::UnsupportedLinkType => "unsupported_link_type",
+            DecodeError::LinkTruncated => "link_truncated",
+            DecodeError::VlanTruncated => "vlan_truncated",
+            DecodeError::MPLSTruncated => "mpls_truncated",
+            DecodeError::PPPoELength => "pppoe_bad_length",
+            DecodeError::ARPTruncated => "arp_truncated",
+            DecodeError::IPTruncated => "ip_truncated",
+            DecodeError::IPVersion => "ip_bad_version",
+            DecodeError::IPv4HeaderLen => "ipv4_bad_ihl",
+            DecodeError::IPLength => "ip_bad_length",
+            DecodeError::IPv6ExtHeader => "ipv6_bad_ext_header",
+            DecodeError::TCPTruncated => "tcp_truncated",
+            DecodeError::TCPDataOffset => "tcp_bad_offset",
+            DecodeError::UDPTruncated => "udp_truncated",
+            DecodeError::UDPLength => "udp_bad_length",
+            DecodeError::ICMPTruncated => "icmp_truncated",
+            DecodeError::GRETruncated => "gre_truncated",
+        }
+    }
+}
+
+// odd but decodable, the packet is still handed to the trackers
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Anomaly {
+    // ipv4 ttl or ipv6 hop limit of 0, should have been dropped by the last router
+    TTLZero,
+    // the evil bit
+    IPv4ReservedFlag,
+    // udp length not matching the ip payload
+    UDPLengthMismatch,
+    // syn with fin or rst, or no flag at all
+    TCPFlags,
+    // an option length below 2 or past the header
+    TCPOptions,
+    // only looked for when checksum verification is enabled
+    IPv4Checksum,
+    TCPChecksum,
+    UDPChecksum,
+}
+
+impl Anomaly {
+    pub fn is_checksum(self) -> bool {
+        match self {
+            Anomaly::IPv4Checksum | Anomaly::TCPChecksum | Anomaly::UDPChecksum => true,
+            _ => false,
+        }
+    }
+
+    pub fn to_string(self) -> &'static str {
+        match self {
+            Anomaly::TTLZero => "ttl_zero",
+            Anomaly::IPv4ReservedFlag => "ipv4_reserved_flag",
+            Anomaly::UDPLengthMismatch => "udp_length_mismatch",
+            Anomaly::TCPFlags => "tcp_bad_flags",
+            Anomaly::TCPOptions => "tcp_bad_options",
+            Anomaly::IPv4Checksum => "ipv4_bad_checksum",
+            Anomaly::TCPChecksum => "tcp_bad_checksum",
+            Anomaly::UDPChecksum => "udp_bad_checksum",
+        }
+    }
+}
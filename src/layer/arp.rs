@@ -0,0 +1,76 @@
+use crate::inet;
+use layer::read_mac;
+use std::net::Ipv4Addr;
+
+// ethernet and ipv4 only, the only kind the binding table cares about
+#[derive(Clone, Copy)]
+pub struct ARPHeader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> ARPHeader<'a> {
+    pub const LEN: usize = 28;
+
+    pub const ETHERNET: u16 = 1;
+    pub const REQUEST: u16 = 1;
+    pub const REPLY: u16 = 2;
+
+    pub fn new(data: &'a [u8]) -> Option<ARPHeader<'a>> {
+        if data.len() < ARPHeader::LEN {
+            return None;
+        }
+        Some(ARPHeader { data })
+    }
+
+    #[inline]
+    pub fn hardware_type(&self) -> u16 {
+        return inet::read_u16(self.data, 0);
+    }
+
+    #[inline]
+    pub fn protocol_type(&self) -> u16 {
+        return inet::read_u16(self.data, 2);
+    }
+
+    #[inline]
+    pub fn hardware_len(&self) -> u8 {
+        return self.data[4];
+    }
+
+    #[inline]
+    pub fn protocol_len(&self) -> u8 {
+        return self.data[5];
+    }
+
+    #[inline]
+    pub fn operation(&self) -> u16 {
+        return inet::read_u16(self.data, 6);
+    }
+
+    #[inline]
+    pub fn sender_mac(&self) -> [u8; 6] {
+        return read_mac(self.data, 8);
+    }
+
+    #[inline]
+    pub fn sender_ip(&self) -> Ipv4Addr {
+        return Ipv4Addr::from(inet::read_u32(self.data, 14));
+    }
+
+    #[inline]
+    pub fn target_mac(&self) -> [u8; 6] {
+        return read_mac(self.data, 18);
+    }
+
+    #[inline]
+    pub fn target_ip(&self) -> Ipv4Addr {
+        return Ipv4Addr::from(inet::read_u32(self.data, 24));
+    }
+
+    // announces the sender's own binding rather than asking for one
+    #[inline]
+    pub fn is_gratuitous(&self) -> bool {
+        return self.sender_ip() == self.target_ip()
+            || self.operation() == ARPHeader::REPLY && self.target_mac() == [0xff; 6];
+    }
+}
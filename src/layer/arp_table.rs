@@ -0,0 +1,171 @@
+use config::ArpConfig;
+use event::Event;
+use layer::ip::ip_hash;
+use layer::packet::Packet;
+use stats::WorkerStats;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+// an address is only unique on one l2 segment, the capture interface and vlans
+// tell the segments apart
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct BindingKey {
+    pub interface: Arc<str>,
+    pub vlan: [u16; 2],
+    pub ip: IpAddr,
+}
+
+impl BindingKey {
+    pub fn from_packet(packet: &Packet) -> BindingKey {
+        BindingKey {
+            interface: packet.interface.clone(),
+            vlan: packet.vlan_key(),
+            ip: packet.src_ip,
+        }
+    }
+
+    // picks the worker whose table holds the binding
+    pub fn route_hash(&self) -> u32 {
+        let mut hash = ip_hash(&self.ip);
+        for byte in self.interface.bytes() {
+            hash = hash.wrapping_mul(31).wrapping_add(byte as u32);
+        }
+        hash.wrapping_add((self.vlan[0] as u32) << 16 | self.vlan[1] as u32)
+    }
+}
+
+struct Binding {
+    mac: [u8; 6],
+    last_seen: u64,
+    // gratuitous announcements in the current window
+    window_start: u64,
+    gratuitous: u64,
+    storm_reported: bool,
+}
+
+// ip to mac bindings learnt from the arp senders routed to this worker
+pub struct ARPTable {
+    bindings: HashMap<BindingKey, Binding>,
+    //nano second
+    storm_window: u64,
+    storm_threshold: u64,
+    max_bindings: usize,
+    stats: Arc<WorkerStats>,
+}
+
+impl ARPTable {
+    pub fn new(stats: Arc<WorkerStats>, config: &ArpConfig) -> ARPTable {
+        ARPTable {
+            bindings: HashMap::new(),
+            storm_window: config.storm_window * 1000 * 1000 * 1000,
+            storm_threshold: config.storm_threshold,
+            max_bindings: config.max_bindings,
+            stats,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.bindings.len()
+    }
+
+    pub fn lookup(&self, key: &BindingKey) -> Option<[u8; 6]> {
+        self.bindings.get(key).map(|binding| binding.mac)
+    }
+
+    // learn the sender binding, the event describes what changed if anything did
+    pub fn on_packet(&mut self, packet: &Arc<Packet>) -> Option<Event> {
+        let arp = match packet.arp() {
+            Some(arp) => arp,
+            None => return None,
+        };
+        let key = BindingKey::from_packet(packet);
+        let ip = packet.src_ip;
+        let mac = arp.sender_mac();
+        let tm = packet.timestamp;
+
+        // probes carry no sender address yet
+        if arp.sender_ip().is_unspecified() {
+            return None;
+        }
+
+        if !self.bindings.contains_key(&key) {
+            if self.bindings.len() >= self.max_bindings {
+                self.evict_oldest();
+            }
+            self.bindings.insert(
+                key,
+                Binding {
+                    mac,
+                    last_seen: tm,
+                    window_start: tm,
+                    gratuitous: 0,
+                    storm_reported: false,
+                },
+            );
+            self.update_stats();
+            return Some(Event::ArpNew {
+                timestamp: tm,
+                interface: packet.interface.clone(),
+                ip,
+                mac,
+            });
+        }
+
+        let binding = self.bindings.get_mut(&key).unwrap();
+        binding.last_seen = tm;
+
+        if binding.mac != mac {
+            let old_mac = binding.mac;
+            binding.mac = mac;
+            return Some(Event::ArpChanged {
+                timestamp: tm,
+                interface: packet.interface.clone(),
+                ip,
+                old_mac,
+                new_mac: mac,
+            });
+        }
+
+        if !arp.is_gratuitous() {
+            return None;
+        }
+        if binding.window_start + self.storm_window <= tm {
+            binding.window_start = tm;
+            binding.gratuitous = 0;
+            binding.storm_reported = false;
+        }
+        binding.gratuitous += 1;
+        // one report per window
+        if binding.gratuitous > self.storm_threshold && !binding.storm_reported {
+            binding.storm_reported = true;
+            return Some(Event::ArpStorm {
+                timestamp: tm,
+                interface: packet.interface.clone(),
+                ip,
+                mac,
+                count: binding.gratuitous,
+            });
+        }
+        return None;
+    }
+
+    fn evict_oldest(&mut self) {
+        let oldest = self
+            .bindings
+            .iter()
+            .min_by_key(|&(_, binding)| binding.last_seen)
+            .map(|(key, _)| key.clone());
+        if let Some(key) = oldest {
+            debug!("arp table full, evict {} on {}", key.ip, key.interface);
+            self.bindings.remove(&key);
+        }
+    }
+
+    fn update_stats(&self) {
+        self.stats
+            .arp_bindings
+            .store(self.bindings.len() as u64, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,75 @@
+use std::mem;
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
+
+// the frame bytes a Packet decodes, goes back to its pool when the packet is dropped
+pub struct PacketBuffer {
+    data: Vec<u8>,
+    pool: Option<Arc<BufferPool>>,
+}
+
+impl PacketBuffer {
+    pub fn new(data: Vec<u8>) -> PacketBuffer {
+        PacketBuffer { data, pool: None }
+    }
+
+    pub fn is_pooled(&self) -> bool {
+        self.pool.is_some()
+    }
+}
+
+impl Deref for PacketBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+impl Drop for PacketBuffer {
+    fn drop(&mut self) {
+        if let Some(pool) = self.pool.take() {
+            pool.put(mem::replace(&mut self.data, Vec::new()));
+        }
+    }
+}
+
+// recycled buffers for a capture loop, so a ring slot or a pcap callback costs a
+// copy but no allocation; packets live on in the trackers long after their ring
+// block is handed back to the kernel, which rules out borrowing the slot itself
+pub struct BufferPool {
+    free: Mutex<Vec<Vec<u8>>>,
+    // buffers kept for reuse, the rest are freed when they come back
+    max_free: usize,
+}
+
+impl BufferPool {
+    pub fn new(max_free: usize) -> Arc<BufferPool> {
+        Arc::new(BufferPool {
+            free: Mutex::new(Vec::with_capacity(max_free)),
+            max_free,
+        })
+    }
+
+    // data copied into a recycled buffer, a new one when none is free
+    pub fn copy(pool: &Arc<BufferPool>, data: &[u8]) -> PacketBuffer {
+        let mut buffer = pool.free.lock().unwrap().pop().unwrap_or_default();
+        buffer.extend_from_slice(data);
+        PacketBuffer {
+            data: buffer,
+            pool: Some(pool.clone()),
+        }
+    }
+
+    pub fn free_len(&self) -> usize {
+        self.free.lock().unwrap().len()
+    }
+
+    fn put(&self, mut buffer: Vec<u8>) {
+        buffer.clear();
+        let mut free = self.free.lock().unwrap();
+        if free.len() < self.max_free {
+            free.push(buffer);
+        }
+    }
+}
@@ -0,0 +1,76 @@
+use layer::IPProto;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+lazy_static! {
+    // off unless asked for, a capture on the sending host sees its own
+    // outgoing packets before the nic filled the checksums in
+    static ref VERIFY: AtomicBool = AtomicBool::new(false);
+}
+
+pub fn enable_verify(enable: bool) {
+    VERIFY.store(enable, Ordering::Relaxed);
+}
+
+pub fn verify_enabled() -> bool {
+    VERIFY.load(Ordering::Relaxed)
+}
+
+// ones' complement sum of big endian words, an odd byte at the end is padded with zero
+pub fn sum(data: &[u8], initial: u64) -> u64 {
+    let mut sum = initial;
+    let mut words = data.chunks_exact(2);
+    for word in &mut words {
+        sum += ((word[0] as u64) << 8) | word[1] as u64;
+    }
+    if let Some(&last) = words.remainder().first() {
+        sum += (last as u64) << 8;
+    }
+    return sum;
+}
+
+pub fn fold(mut sum: u64) -> u16 {
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    return sum as u16;
+}
+
+// what goes in the checksum field, which must be zero in data
+pub fn checksum(data: &[u8]) -> u16 {
+    return !fold(sum(data, 0));
+}
+
+// sum of the ipv4 or ipv6 pseudo header covered by the tcp and udp checksums
+pub fn pseudo_header(src: &IpAddr, dst: &IpAddr, proto: IPProto, len: usize) -> u64 {
+    let mut pseudo = proto.0 as u64 + len as u64;
+    for ip in [src, dst].iter() {
+        pseudo = match **ip {
+            IpAddr::V4(ip) => sum(&ip.octets(), pseudo),
+            IpAddr::V6(ip) => sum(&ip.octets(), pseudo),
+        };
+    }
+    return pseudo;
+}
+
+// an ipv4 header, its checksum included, adds up to all ones
+pub fn ipv4_header_valid(header: &[u8]) -> bool {
+    return fold(sum(header, 0)) == 0xffff;
+}
+
+// the same for a tcp or udp segment and its pseudo header; a field holding only
+// the pseudo header sum is left for the nic to complete, what an offloading
+// sender hands to a local capture, and is not counted as bad
+pub fn transport_valid(
+    src: &IpAddr,
+    dst: &IpAddr,
+    proto: IPProto,
+    segment: &[u8],
+    stored: u16,
+) -> bool {
+    let pseudo = pseudo_header(src, dst, proto, segment.len());
+    if fold(sum(segment, pseudo)) == 0xffff {
+        return true;
+    }
+    return stored == fold(pseudo);
+}
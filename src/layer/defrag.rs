@@ -0,0 +1,364 @@
+use config::DefragConfig;
+use layer::checksum;
+use layer::ip::IPv4Header;
+use layer::packet::Packet;
+use layer::{LinkType, PacketBuffer, Tunnel};
+use std::cmp;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Arc;
+
+// which copy wins when fragments or tcp segments overlap, after the target stacks
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OverlapPolicy {
+    // the data seen first
+    First,
+    // the data seen last
+    Last,
+    // the new fragment wins only where it starts before the old one
+    Bsd,
+    // the new fragment wins where it starts at or before the old one
+    Linux,
+    // the old data wins unless the new fragment starts before it and covers it to the end
+    Windows,
+}
+
+impl OverlapPolicy {
+    pub fn from_str(name: &str) -> Option<OverlapPolicy> {
+        match name {
+            "first" => Some(OverlapPolicy::First),
+            "last" => Some(OverlapPolicy::Last),
+            "bsd" => Some(OverlapPolicy::Bsd),
+            "linux" => Some(OverlapPolicy::Linux),
+            "windows" => Some(OverlapPolicy::Windows),
+            _ => None,
+        }
+    }
+
+    // whether the bytes the new data shares with the old are taken from the new,
+    // both given as the start and end offsets of the data they came with
+    #[inline]
+    pub fn new_wins(self, new: (u64, u64), old: (u64, u64)) -> bool {
+        match self {
+            OverlapPolicy::First => false,
+            OverlapPolicy::Last => true,
+            OverlapPolicy::Bsd => new.0 < old.0,
+            OverlapPolicy::Linux => new.0 <= old.0,
+            OverlapPolicy::Windows => new.0 < old.0 && new.1 >= old.1,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+struct DatagramID {
+    // the same addresses may be reused on another vlan
+    vlan: [u16; 2],
+    src: IpAddr,
+    dst: IpAddr,
+    id: u16,
+    proto: u8,
+}
+
+struct Fragment {
+    // bytes from the start of the ip payload
+    offset: usize,
+    data: Vec<u8>,
+}
+
+struct Datagram {
+    // ip header of the first fragment
+    header: Vec<u8>,
+    // in arrival order, the overlap policy depends on it
+    fragments: Vec<Fragment>,
+    // payload length, known once the last fragment arrived
+    total_len: Option<usize>,
+    first_seen: u64,
+    last_seen: u64,
+    bytes: usize,
+    interface: Arc<str>,
+    tunnels: Vec<Tunnel>,
+    vlan_ids: Vec<u16>,
+    mpls_labels: Vec<u32>,
+}
+
+impl Datagram {
+    fn is_complete(&self) -> bool {
+        let total_len = match self.total_len {
+            Some(len) => len,
+            None => return false,
+        };
+        if self.header.is_empty() {
+            return false;
+        }
+
+        let mut ranges: Vec<(usize, usize)> = self
+            .fragments
+            .iter()
+            .map(|f| (f.offset, f.offset + f.data.len()))
+            .collect();
+        ranges.sort();
+
+        let mut covered = 0;
+        for &(start, end) in ranges.iter() {
+            if start > covered {
+                return false;
+            }
+            covered = cmp::max(covered, end);
+        }
+        return covered >= total_len;
+    }
+
+    // ip header with the fragment fields cleared followed by the merged payload
+    fn assemble(&self, policy: OverlapPolicy) -> (Vec<u8>, usize) {
+        let total_len = self.total_len.unwrap();
+        let mut payload = vec![0u8; total_len];
+        // start and end of the fragment that wrote each byte
+        let mut owner: Vec<Option<(u64, u64)>> = vec![None; total_len];
+        let mut overlaps = 0;
+
+        for fragment in self.fragments.iter() {
+            let end = cmp::min(fragment.offset + fragment.data.len(), total_len);
+            let range = (fragment.offset as u64, end as u64);
+            for i in fragment.offset..end {
+                let byte = fragment.data[i - fragment.offset];
+                match owner[i] {
+                    None => {
+                        payload[i] = byte;
+                        owner[i] = Some(range);
+                    }
+                    Some(old) => {
+                        if payload[i] != byte {
+                            overlaps += 1;
+                        }
+                        if policy.new_wins(range, old) {
+                            payload[i] = byte;
+                            owner[i] = Some(range);
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut data = self.header.clone();
+        let total = data.len() + total_len;
+        data[2] = (total >> 8) as u8;
+        data[3] = total as u8;
+        // no more fragments, offset 0, keep DF
+        data[6] &= 0x40;
+        data[7] = 0;
+        data[10] = 0;
+        data[11] = 0;
+        let checksum = checksum::checksum(&data);
+        data[10] = (checksum >> 8) as u8;
+        data[11] = checksum as u8;
+
+        data.extend_from_slice(&payload);
+        return (data, overlaps);
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DefragStats {
+    pub fragments: u64,
+    pub reassembled: u64,
+    // datagrams given up on, timed out, evicted or malformed
+    pub dropped: u64,
+    // overlapping bytes whose content differed
+    pub overlaps: u64,
+}
+
+// per worker IPv4 reassembly, fed with packets flagged STATE_FRAGMENT
+pub struct Defragmenter {
+    datagrams: HashMap<DatagramID, Datagram>,
+    policy: OverlapPolicy,
+    //nano second
+    timeout: u64,
+    memory_limit: usize,
+    memory: usize,
+    last_cleanup: u64,
+    stats: DefragStats,
+}
+
+impl Defragmenter {
+    const MAX_FRAGMENTS: usize = 256;
+    const MAX_DATAGRAM: usize = 65535;
+
+    pub fn new(config: &DefragConfig) -> Defragmenter {
+        Defragmenter {
+            datagrams: HashMap::new(),
+            policy: config.policy,
+            timeout: config.timeout * 1000 * 1000 * 1000,
+            memory_limit: config.memory_limit,
+            memory: 0,
+            last_cleanup: 0,
+            stats: DefragStats::default(),
+        }
+    }
+
+    pub fn stats(&self) -> DefragStats {
+        self.stats
+    }
+
+    pub fn memory(&self) -> usize {
+        self.memory
+    }
+
+    // the reassembled datagram once the last missing fragment arrives
+    pub fn on_fragment(&mut self, packet: &Arc<Packet>) -> Option<Arc<Packet>> {
+        assert!(packet.state & Packet::STATE_FRAGMENT > 0);
+        self.stats.fragments += 1;
+
+        let ip = packet.ip_layer_slice();
+        let header = match packet.ipv4() {
+            Some(header) => header,
+            None => {
+                self.stats.dropped += 1;
+                return None;
+            }
+        };
+        let header_len = header.header_len() as usize;
+        if header_len < IPv4Header::LEN || ip.len() < header_len {
+            self.stats.dropped += 1;
+            return None;
+        }
+
+        let id = DatagramID {
+            vlan: packet.vlan_key(),
+            src: packet.src_ip,
+            dst: packet.dst_ip,
+            id: header.identification(),
+            proto: header.proto().0,
+        };
+        let offset = header.frag_offset() as usize;
+        let data = &ip[header_len..];
+        let tm = packet.timestamp;
+
+        if offset + data.len() > Defragmenter::MAX_DATAGRAM - header_len {
+            debug!("fragment beyond 64k, offset {}", offset);
+            self.drop_datagram(&id);
+            return None;
+        }
+
+        self.cleanup(tm);
+        while self.memory + data.len() > self.memory_limit && self.evict_oldest() {}
+        if self.memory + data.len() > self.memory_limit {
+            self.stats.dropped += 1;
+            return None;
+        }
+
+        let complete = {
+            let datagram = self.datagrams.entry(id).or_insert_with(|| Datagram {
+                header: Vec::new(),
+                fragments: Vec::new(),
+                total_len: None,
+                first_seen: tm,
+                last_seen: tm,
+                bytes: 0,
+                interface: packet.interface.clone(),
+                tunnels: packet.tunnels.clone(),
+                vlan_ids: packet.vlan_ids.clone(),
+                mpls_labels: packet.mpls_labels.clone(),
+            });
+
+            datagram.last_seen = tm;
+            if offset == 0 && datagram.header.is_empty() {
+                datagram.header = ip[..header_len].to_vec();
+            }
+            if !header.more_fragments() {
+                datagram.total_len = Some(offset + data.len());
+            }
+            datagram.fragments.push(Fragment {
+                offset,
+                data: data.to_vec(),
+            });
+            datagram.bytes += data.len();
+            self.memory += data.len();
+
+            if datagram.fragments.len() > Defragmenter::MAX_FRAGMENTS {
+                None
+            } else {
+                Some(datagram.is_complete())
+            }
+        };
+
+        match complete {
+            None => {
+                debug!("too many fragments");
+                self.drop_datagram(&id);
+                return None;
+            }
+            Some(false) => return None,
+            Some(true) => {}
+        }
+
+        let datagram = self.datagrams.remove(&id).unwrap();
+        self.memory -= datagram.bytes;
+        let (data, overlaps) = datagram.assemble(self.policy);
+        self.stats.overlaps += overlaps as u64;
+        self.stats.reassembled += 1;
+        trace!("reassembled {} bytes from {}", data.len(), datagram.fragments.len());
+
+        // the link header is not kept, the datagram continues as raw ip
+        let mut packet = Packet::with_buffer(
+            &datagram.interface,
+            LinkType::RAW,
+            datagram.last_seen,
+            PacketBuffer::new(data),
+        );
+        if let Some(p) = Arc::get_mut(&mut packet) {
+            let mut tunnels = datagram.tunnels;
+            tunnels.append(&mut p.tunnels);
+            p.tunnels = tunnels;
+            p.vlan_ids = datagram.vlan_ids;
+            p.mpls_labels = datagram.mpls_labels;
+        }
+        return Some(packet);
+    }
+
+    pub fn cleanup(&mut self, tm: u64) -> usize {
+        if self.last_cleanup + self.timeout > tm {
+            return 0;
+        }
+
+        let before = self.datagrams.len();
+        let timeout = self.timeout;
+        let mut freed = 0;
+        self.datagrams.retain(|_k, datagram| -> bool {
+            let keep = datagram.first_seen + timeout > tm;
+            if !keep {
+                freed += datagram.bytes;
+            }
+            keep
+        });
+        let after = self.datagrams.len();
+
+        self.memory -= freed;
+        self.stats.dropped += (before - after) as u64;
+        self.last_cleanup = tm;
+        debug!("defrag cleanup {}/{}", before - after, before);
+        return before - after;
+    }
+
+    fn drop_datagram(&mut self, id: &DatagramID) {
+        if let Some(datagram) = self.datagrams.remove(id) {
+            self.memory -= datagram.bytes;
+        }
+        self.stats.dropped += 1;
+    }
+
+    fn evict_oldest(&mut self) -> bool {
+        let oldest = self
+            .datagrams
+            .iter()
+            .min_by_key(|&(_, datagram)| datagram.last_seen)
+            .map(|(id, _)| *id);
+        match oldest {
+            Some(id) => {
+                debug!("defrag memory limit, evict {:?}", id);
+                self.drop_datagram(&id);
+                true
+            }
+            None => false,
+        }
+    }
+}
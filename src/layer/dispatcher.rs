@@ -1,5 +1,12 @@
-use crate::layer::{TCPTracker, UDPTracker};
+use crate::layer::{ip_hash, ARPTable, BindingKey, ICMPTracker, IPProto, TCPTracker, UDPTracker};
+use config::{ArpConfig, DefragConfig, StreamConfig};
+use event;
+use daq;
+use daq::afpacket::AfPacketSource;
+use layer::defrag::Defragmenter;
 use layer::packet::Packet;
+use stats;
+use stats::WorkerStats;
 use std::num::Wrapping;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{mpsc, Arc, Barrier};
@@ -8,59 +15,271 @@ use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 pub struct Dispatcher {
     running: Arc<AtomicBool>,
+    draining: Arc<AtomicBool>,
+    stopped: AtomicBool,
     barrier: Arc<Barrier>,
     n_threads: u8,
+    router: Arc<Router>,
+}
+
+// hands packets to the worker owning their flow, shared by the capture
+// threads and by the workers for reassembled datagrams; in fanout mode the
+// workers forward through it what the kernel hashed to the wrong one
+struct Router {
     senders: Vec<mpsc::Sender<Arc<Packet>>>,
+    stats: Vec<Arc<WorkerStats>>,
+}
+
+impl Router {
+    fn index(&self, packet: &Arc<Packet>) -> usize {
+        worker_index(packet, self.senders.len())
+    }
+
+    fn route(&self, packet: Arc<Packet>) -> Result<(), mpsc::SendError<Arc<Packet>>> {
+        let index = self.index(&packet);
+        self.stats[index].enqueued.fetch_add(1, Ordering::Relaxed);
+        self.senders[index].send(packet)
+    }
+}
+
+// the worker owning the flow of a packet, out of workers
+pub fn worker_index(packet: &Packet, workers: usize) -> usize {
+    let workers = Wrapping(workers as u32);
+
+    // the binding table of an address lives on one worker
+    if packet.state & Packet::STATE_ARP > 0 {
+        let hash = Wrapping(BindingKey::from_packet(packet).route_hash());
+        return (hash % workers).0 as usize;
+    }
+
+    // an icmp error goes where the stream it quotes lives
+    if let Some(quoted) = packet.icmp_quoted {
+        let hash = Wrapping(ip_hash(&quoted.src_ip))
+            + Wrapping(ip_hash(&quoted.dst_ip))
+            + Wrapping(quoted.src_port as u32)
+            + Wrapping(quoted.dst_port as u32);
+        return (hash % workers).0 as usize;
+    }
+
+    // tunnels are decoded down to the inner flow, the outer headers do not count
+    let mut hash = Wrapping(ip_hash(&packet.src_ip)) + Wrapping(ip_hash(&packet.dst_ip));
+    // fragments carry no ports, they meet on one worker by address
+    if packet.state & Packet::STATE_FRAGMENT == 0 {
+        hash += Wrapping(packet.src_port as u32) + Wrapping(packet.dst_port as u32);
+    }
+    return (hash % workers).0 as usize;
+}
+
+// per worker pipeline, defragmentation then the trackers
+struct Worker {
+    tcp_tracker: Box<TCPTracker>,
+    udp_tracker: Box<UDPTracker>,
+    icmp_tracker: ICMPTracker,
+    arp_table: ARPTable,
+    defrag: Defragmenter,
+    stats: Arc<WorkerStats>,
+}
+
+impl Worker {
+    fn new(
+        stats: Arc<WorkerStats>,
+        defrag: &DefragConfig,
+        stream: &StreamConfig,
+        arp: &ArpConfig,
+    ) -> Worker {
+        Worker {
+            tcp_tracker: Box::new(TCPTracker::new(stats.clone(), stream)),
+            udp_tracker: Box::new(UDPTracker::new(stats.clone(), stream)),
+            icmp_tracker: ICMPTracker::new(stats.clone(), stream),
+            arp_table: ARPTable::new(stats.clone(), arp),
+            defrag: Defragmenter::new(defrag),
+            stats,
+        }
+    }
+
+    // a reassembled datagram is returned to the caller, which knows where it belongs
+    fn on_packet(&mut self, packet: &Arc<Packet>) -> Option<Arc<Packet>> {
+        if packet.state & Packet::STATE_FRAGMENT > 0 {
+            let whole = self.defrag.on_fragment(packet);
+            self.update_defrag_stats();
+            return whole.filter(|whole| daq::accept(whole));
+        }
+        if packet.state & Packet::STATE_IPV6_FRAGMENT > 0 {
+            self.stats.ipv6_fragments.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+
+        if packet.state & Packet::STATE_TCP > 0 {
+            trace!(
+                "tcp {} {}:{} ->{}:{} {}",
+                packet.interface,
+                packet.src_ip_str(),
+                packet.src_port,
+                packet.dst_ip_str(),
+                packet.dst_port,
+                packet.tunnels_str()
+            );
+            self.tcp_tracker.on_packet(packet);
+            return None;
+        }
+
+        if packet.state & Packet::STATE_UDP > 0 {
+            trace!(
+                "udp {} {}:{} ->{}:{} {}",
+                packet.interface,
+                packet.src_ip_str(),
+                packet.src_port,
+                packet.dst_ip_str(),
+                packet.dst_port,
+                packet.tunnels_str()
+            );
+            self.udp_tracker.on_packet(packet);
+            return None;
+        }
+
+        if packet.state & Packet::STATE_ARP > 0 {
+            trace!(
+                "arp {} {} ->{} {}",
+                packet.interface,
+                packet.src_ip_str(),
+                packet.dst_ip_str(),
+                packet.src_mac()
+            );
+            if let Some(event) = self.arp_table.on_packet(packet) {
+                event::emit(event);
+            }
+            return None;
+        }
+
+        if packet.state & Packet::STATE_ICMP > 0 {
+            trace!(
+                "icmp {} {} ->{} {} {}",
+                packet.interface,
+                packet.src_ip_str(),
+                packet.dst_ip_str(),
+                packet.icmp_type_str(),
+                packet.tunnels_str()
+            );
+            match packet.icmp_quoted {
+                Some(quoted) => {
+                    let found = match quoted.proto {
+                        IPProto::TCP => self.tcp_tracker.on_icmp_error(packet),
+                        IPProto::UDP => self.udp_tracker.on_icmp_error(packet),
+                        _ => false,
+                    };
+                    if !found {
+                        trace!("icmp error without stream {:?}", quoted);
+                    }
+                }
+                None => self.icmp_tracker.on_packet(packet),
+            }
+            return None;
+        }
+        return None;
+    }
+
+    fn cleanup(&mut self, now: u64) {
+        self.tcp_tracker.cleanup_stream(now);
+        self.udp_tracker.cleanup_stream(now);
+        self.icmp_tracker.cleanup_flow(now);
+        self.defrag.cleanup(now);
+        self.update_defrag_stats();
+    }
+
+    fn flush(&mut self) {
+        self.tcp_tracker.flush();
+        self.udp_tracker.flush();
+        self.icmp_tracker.flush();
+    }
+
+    fn update_defrag_stats(&self) {
+        let defrag = self.defrag.stats();
+        self.stats
+            .fragments
+            .store(defrag.fragments, Ordering::Relaxed);
+        self.stats
+            .reassembled
+            .store(defrag.reassembled, Ordering::Relaxed);
+        self.stats
+            .defrag_dropped
+            .store(defrag.dropped, Ordering::Relaxed);
+    }
 }
 
 impl Dispatcher {
     pub fn dispatch(&self, packet: Arc<Packet>) {
-        let hash = (Wrapping(packet.src_ip)
-            + Wrapping(packet.src_port as u32)
-            + Wrapping(packet.dst_ip)
-            + Wrapping(packet.dst_port as u32))
-            % Wrapping(self.n_threads as u32);
-        self.senders[hash.0 as usize]
-            .send(packet)
-            .expect("channel send error");
+        self.router.route(packet).expect("channel send error");
     }
 
     pub fn stop(&self) {
+        if self.stopped.swap(true, Ordering::SeqCst) {
+            return;
+        }
         self.running.store(false, Ordering::Relaxed);
         self.barrier.wait();
         debug!("app stopped")
     }
 
+    // wait for the workers to drain their queues and flush all streams
+    pub fn finish(&self) {
+        if self.stopped.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        self.draining.store(true, Ordering::SeqCst);
+        self.barrier.wait();
+        debug!("app finished")
+    }
+
     fn worker(
         running: Arc<AtomicBool>,
+        draining: Arc<AtomicBool>,
         barrier: Arc<Barrier>,
         receiver: mpsc::Receiver<Arc<Packet>>,
+        router: Arc<Router>,
+        stats: Arc<WorkerStats>,
+        defrag: DefragConfig,
+        stream: StreamConfig,
+        arp: ArpConfig,
+        offline: bool,
     ) {
-        let mut tcp_tracker = Box::new(TCPTracker::new());
-        let mut udp_tracker = Box::new(UDPTracker::new());
-
+        let mut worker = Worker::new(stats, &defrag, &stream, &arp);
         let timeout = Duration::new(1, 0);
+        let mut last_timestamp = 0;
 
         loop {
             if !running.load(Ordering::Relaxed) {
                 debug!("stop running");
+                worker.flush();
                 barrier.wait();
                 return;
             }
             match receiver.recv_timeout(timeout) {
                 Ok(packet) => {
-                    Dispatcher::dispatch_packet(&mut tcp_tracker, &mut udp_tracker, &packet);
+                    worker.stats.processed.fetch_add(1, Ordering::Relaxed);
+                    last_timestamp = packet.timestamp;
+                    if let Some(whole) = worker.on_packet(&packet) {
+                        if let Err(_) = router.route(whole) {
+                            debug!("worker gone, drop reassembled datagram");
+                        }
+                    }
                     continue;
                 }
                 Err(e) => match e {
                     mpsc::RecvTimeoutError::Timeout => {
-                        let now = SystemTime::now()
-                            .duration_since(UNIX_EPOCH)
-                            .unwrap()
-                            .as_secs()
-                            * 1000
-                            * 1000;
-                        tcp_tracker.cleanup_stream(now) + udp_tracker.cleanup_stream(now);
+                        if draining.load(Ordering::SeqCst) {
+                            debug!("queue drained");
+                            worker.flush();
+                            barrier.wait();
+                            return;
+                        }
+
+                        // offline captures are driven by the packet clock
+                        let now = if offline {
+                            last_timestamp
+                        } else {
+                            Dispatcher::wall_clock()
+                        };
+                        worker.cleanup(now);
                     }
 
                     mpsc::RecvTimeoutError::Disconnected => {
@@ -72,53 +291,179 @@ impl Dispatcher {
         }
     }
 
-    fn dispatch_packet(tcp: &mut Box<TCPTracker>, udp: &mut Box<UDPTracker>, packet: &Arc<Packet>) {
-        if packet.state & Packet::STATE_TCP > 0 {
-            trace!(
-                "tcp {}:{} ->{}:{}",
-                packet.src_ip_str(),
-                packet.src_port,
-                packet.dst_ip_str(),
-                packet.dst_port
-            );
-            tcp.on_packet(packet);
-            return;
+    // the worker owns one capture ring per interface; the kernel hashes the outer
+    // headers, so packets the router gives to another worker, tunnelled flows,
+    // icmp errors and arp, go over its queue
+    fn fanout_worker(
+        index: usize,
+        running: Arc<AtomicBool>,
+        barrier: Arc<Barrier>,
+        rings: Vec<Arc<AfPacketSource>>,
+        receiver: mpsc::Receiver<Arc<Packet>>,
+        router: Arc<Router>,
+        stats: Arc<WorkerStats>,
+        defrag: DefragConfig,
+        stream: StreamConfig,
+        arp: ArpConfig,
+    ) {
+        let mut worker = Worker::new(stats.clone(), &defrag, &stream, &arp);
+
+        // share the poll interval between the rings, short enough for the
+        // forwarded packets not to wait
+        let timeout = 100 / rings.len() as i32;
+
+        loop {
+            if !running.load(Ordering::Relaxed) {
+                debug!("stop running");
+                worker.flush();
+                barrier.wait();
+                return;
+            }
+
+            let mut n = 0;
+            for ring in rings.iter() {
+                n += ring.poll(timeout, &mut |packet: Arc<Packet>| {
+                    Dispatcher::fanout_packet(index, &mut worker, &router, packet);
+                });
+            }
+            while let Ok(packet) = receiver.try_recv() {
+                worker.stats.processed.fetch_add(1, Ordering::Relaxed);
+                if let Some(whole) = worker.on_packet(&packet) {
+                    Dispatcher::fanout_packet(index, &mut worker, &router, whole);
+                }
+                n += 1;
+            }
+            if n == 0 {
+                worker.cleanup(Dispatcher::wall_clock());
+            }
         }
+    }
 
-        if packet.state & Packet::STATE_UDP > 0 {
-            trace!(
-                "udp {}:{} ->{}:{}",
-                packet.src_ip_str(),
-                packet.src_port,
-                packet.dst_ip_str(),
-                packet.dst_port
-            );
-            udp.on_packet(packet);
+    // handled here when the router picks this worker, forwarded otherwise
+    fn fanout_packet(index: usize, worker: &mut Worker, router: &Router, packet: Arc<Packet>) {
+        if router.index(&packet) != index {
+            if let Err(_) = router.route(packet) {
+                debug!("worker gone, drop forwarded packet");
+            }
             return;
         }
+        worker.stats.enqueued.fetch_add(1, Ordering::Relaxed);
+        worker.stats.processed.fetch_add(1, Ordering::Relaxed);
+        if let Some(whole) = worker.on_packet(&packet) {
+            Dispatcher::fanout_packet(index, worker, router, whole);
+        }
+    }
+
+    fn wall_clock() -> u64 {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        now.as_secs() * 1000 * 1000 * 1000 + now.subsec_nanos() as u64
     }
 }
 
-pub fn init(n_threads: u8) -> Arc<Dispatcher> {
-    let mut dispatcher = Dispatcher {
+pub fn init(
+    n_threads: u8,
+    offline: bool,
+    defrag: &DefragConfig,
+    stream: &StreamConfig,
+    arp: &ArpConfig,
+) -> Arc<Dispatcher> {
+    let mut senders = Vec::new();
+    let mut receivers = Vec::new();
+    let mut worker_stats = Vec::new();
+    for _i in 0..n_threads {
+        let (tx, rx) = mpsc::channel::<Arc<Packet>>();
+        senders.push(tx);
+        receivers.push(rx);
+        worker_stats.push(stats::register_worker());
+    }
+
+    let router = Arc::new(Router {
+        senders,
+        stats: worker_stats,
+    });
+    let dispatcher = Dispatcher {
         running: Arc::new(AtomicBool::new(true)),
+        draining: Arc::new(AtomicBool::new(false)),
+        stopped: AtomicBool::new(false),
         barrier: Arc::new(Barrier::new((n_threads + 1) as usize)),
         n_threads,
-        senders: Vec::new(),
+        router: router.clone(),
     };
 
-    for _i in 0..n_threads {
+    for (i, rx) in receivers.into_iter().enumerate() {
         let running = dispatcher.running.clone();
+        let draining = dispatcher.draining.clone();
         let barrier = dispatcher.barrier.clone();
-        let (tx, rx) = mpsc::channel::<Arc<Packet>>();
+        let router = router.clone();
+        let stats = router.stats[i].clone();
+        let defrag = defrag.clone();
+        let stream = stream.clone();
+        let arp = arp.clone();
 
-        let cb = move || Dispatcher::worker(running, barrier, rx);
+        let cb = move || {
+            Dispatcher::worker(
+                running, draining, barrier, rx, router, stats, defrag, stream, arp, offline,
+            )
+        };
 
         thread::spawn(cb);
-        dispatcher.senders.push(tx);
     }
 
     debug!("threads = {}", dispatcher.n_threads);
 
     return Arc::new(dispatcher);
 }
+
+// workers[i] holds the i-th fanout ring of every interface
+pub fn init_fanout(
+    workers: Vec<Vec<Arc<AfPacketSource>>>,
+    defrag: &DefragConfig,
+    stream: &StreamConfig,
+    arp: &ArpConfig,
+) -> Arc<Dispatcher> {
+    let n_threads = workers.len() as u8;
+    let mut senders = Vec::new();
+    let mut receivers = Vec::new();
+    let mut worker_stats = Vec::new();
+    for _i in 0..n_threads {
+        let (tx, rx) = mpsc::channel::<Arc<Packet>>();
+        senders.push(tx);
+        receivers.push(rx);
+        worker_stats.push(stats::register_worker());
+    }
+
+    let router = Arc::new(Router {
+        senders,
+        stats: worker_stats,
+    });
+    let dispatcher = Dispatcher {
+        running: Arc::new(AtomicBool::new(true)),
+        draining: Arc::new(AtomicBool::new(false)),
+        stopped: AtomicBool::new(false),
+        barrier: Arc::new(Barrier::new((n_threads + 1) as usize)),
+        n_threads,
+        router: router.clone(),
+    };
+
+    for (i, (rings, rx)) in workers.into_iter().zip(receivers).enumerate() {
+        let running = dispatcher.running.clone();
+        let barrier = dispatcher.barrier.clone();
+        let router = router.clone();
+        let stats = router.stats[i].clone();
+        let defrag = defrag.clone();
+        let stream = stream.clone();
+        let arp = arp.clone();
+
+        let cb = move || {
+            Dispatcher::fanout_worker(
+                i, running, barrier, rings, rx, router, stats, defrag, stream, arp,
+            )
+        };
+
+        thread::spawn(cb);
+    }
+
+    debug!("fanout threads = {}", dispatcher.n_threads);
+
+    return Arc::new(dispatcher);
+}
@@ -1,3 +1,5 @@
+use crate::inet;
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct EthernetType(pub u16);
 
@@ -17,7 +19,10 @@ impl EthernetType {
     pub const VLAN: EthernetType = EthernetType(0x8100);
     pub const LOOP: EthernetType = EthernetType(0x9000);
     pub const T8021QINQ: EthernetType = EthernetType(0x9100);
+    pub const MPLS_UC: EthernetType = EthernetType(0x8847);
+    pub const MPLS_MC: EthernetType = EthernetType(0x8848);
     pub const ERSPAN: EthernetType = EthernetType(0x88BE);
+    pub const ERSPAN_III: EthernetType = EthernetType(0x22EB);
     pub const DCE: EthernetType = EthernetType(0x8903); /* Data center ethernet*/
 
     pub fn ethernet_type_string(value: EthernetType) -> &'static str {
@@ -37,42 +42,66 @@ impl EthernetType {
             EthernetType::VLAN => "VLAN",
             EthernetType::LOOP => "LOOP",
             EthernetType::T8021QINQ => "8021QINQ",
+            EthernetType::MPLS_UC => "MPLS_UC",
+            EthernetType::MPLS_MC => "MPLS_MC",
             EthernetType::ERSPAN => "ERSPAN",
+            EthernetType::ERSPAN_III => "ERSPAN_III",
             EthernetType::DCE => "DCE",
             _ => "Unknown",
         }
     }
 }
 
-#[repr(C, packed)]
-pub struct EthernetHeader {
-    pub eth_dst: [u8; 6],
-    pub eth_src: [u8; 6],
-    pub eth_type: u16,
+#[derive(Clone, Copy)]
+pub struct EthernetHeader<'a> {
+    data: &'a [u8],
+}
+
+pub fn mac_to_string(mac: &[u8; 6]) -> String {
+    return format!(
+        "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+        mac[0], mac[1], mac[2], mac[3], mac[4], mac[5]
+    );
+}
+
+// six bytes at offset, the caller checked the length
+pub fn read_mac(data: &[u8], offset: usize) -> [u8; 6] {
+    let mut mac = [0u8; 6];
+    mac.copy_from_slice(&data[offset..offset + 6]);
+    return mac;
 }
 
-impl EthernetHeader {
+impl<'a> EthernetHeader<'a> {
+    pub const LEN: usize = 14;
+
+    // None when the slice cannot hold the header
+    pub fn new(data: &'a [u8]) -> Option<EthernetHeader<'a>> {
+        if data.len() < EthernetHeader::LEN {
+            return None;
+        }
+        Some(EthernetHeader { data })
+    }
+
+    #[inline]
+    pub fn dst(&self) -> [u8; 6] {
+        return read_mac(self.data, 0);
+    }
+
+    #[inline]
+    pub fn src(&self) -> [u8; 6] {
+        return read_mac(self.data, 6);
+    }
+
+    #[inline]
+    pub fn eth_type(&self) -> EthernetType {
+        return EthernetType(inet::read_u16(self.data, 12));
+    }
+
     pub fn src_mac(&self) -> String {
-        return format!(
-            "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
-            (self.eth_src)[0],
-            (self.eth_src)[1],
-            (self.eth_src)[2],
-            (self.eth_src)[3],
-            (self.eth_src)[4],
-            (self.eth_src)[5]
-        );
+        return mac_to_string(&self.src());
     }
 
     pub fn dst_mac(&self) -> String {
-        return format!(
-            "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
-            (self.eth_dst)[0],
-            (self.eth_dst)[1],
-            (self.eth_dst)[2],
-            (self.eth_dst)[3],
-            (self.eth_dst)[4],
-            (self.eth_dst)[5]
-        );
+        return mac_to_string(&self.dst());
     }
 }
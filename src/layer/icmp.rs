@@ -0,0 +1,165 @@
+use crate::inet;
+use layer::ip::IPProto;
+use std::fmt;
+use std::net::IpAddr;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ICMPType(pub u8);
+
+impl ICMPType {
+    pub const ECHO_REPLY: ICMPType = ICMPType(0);
+    pub const DEST_UNREACH: ICMPType = ICMPType(3);
+    pub const SOURCE_QUENCH: ICMPType = ICMPType(4);
+    pub const REDIRECT: ICMPType = ICMPType(5);
+    pub const ECHO_REQUEST: ICMPType = ICMPType(8);
+    pub const TIME_EXCEEDED: ICMPType = ICMPType(11);
+    pub const PARAMETER_PROBLEM: ICMPType = ICMPType(12);
+
+    // errors quote the header of the offending datagram
+    pub fn is_error(self) -> bool {
+        match self {
+            ICMPType::DEST_UNREACH
+            | ICMPType::SOURCE_QUENCH
+            | ICMPType::REDIRECT
+            | ICMPType::TIME_EXCEEDED
+            | ICMPType::PARAMETER_PROBLEM => true,
+            _ => false,
+        }
+    }
+
+    pub fn to_string(self) -> &'static str {
+        match self {
+            ICMPType::ECHO_REPLY => "echo_reply",
+            ICMPType::DEST_UNREACH => "unreachable",
+            ICMPType::SOURCE_QUENCH => "source_quench",
+            ICMPType::REDIRECT => "redirect",
+            ICMPType::ECHO_REQUEST => "echo_request",
+            ICMPType::TIME_EXCEEDED => "time_exceeded",
+            ICMPType::PARAMETER_PROBLEM => "parameter_problem",
+            _ => "Unknown",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ICMPv6Type(pub u8);
+
+impl ICMPv6Type {
+    pub const DEST_UNREACH: ICMPv6Type = ICMPv6Type(1);
+    pub const PACKET_TOO_BIG: ICMPv6Type = ICMPv6Type(2);
+    pub const TIME_EXCEEDED: ICMPv6Type = ICMPv6Type(3);
+    pub const PARAMETER_PROBLEM: ICMPv6Type = ICMPv6Type(4);
+    pub const ECHO_REQUEST: ICMPv6Type = ICMPv6Type(128);
+    pub const ECHO_REPLY: ICMPv6Type = ICMPv6Type(129);
+    pub const ROUTER_SOLICIT: ICMPv6Type = ICMPv6Type(133);
+    pub const ROUTER_ADVERT: ICMPv6Type = ICMPv6Type(134);
+    pub const NEIGHBOR_SOLICIT: ICMPv6Type = ICMPv6Type(135);
+    pub const NEIGHBOR_ADVERT: ICMPv6Type = ICMPv6Type(136);
+
+    // types below 128 are errors
+    pub fn is_error(self) -> bool {
+        return self.0 < 128;
+    }
+
+    pub fn to_string(self) -> &'static str {
+        match self {
+            ICMPv6Type::DEST_UNREACH => "unreachable",
+            ICMPv6Type::PACKET_TOO_BIG => "packet_too_big",
+            ICMPv6Type::TIME_EXCEEDED => "time_exceeded",
+            ICMPv6Type::PARAMETER_PROBLEM => "parameter_problem",
+            ICMPv6Type::ECHO_REQUEST => "echo_request",
+            ICMPv6Type::ECHO_REPLY => "echo_reply",
+            ICMPv6Type::ROUTER_SOLICIT => "router_solicit",
+            ICMPv6Type::ROUTER_ADVERT => "router_advert",
+            ICMPv6Type::NEIGHBOR_SOLICIT => "neighbor_solicit",
+            ICMPv6Type::NEIGHBOR_ADVERT => "neighbor_advert",
+            _ => "Unknown",
+        }
+    }
+}
+
+// same layout for icmp and icmpv6
+#[derive(Clone, Copy)]
+pub struct ICMPHeader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> ICMPHeader<'a> {
+    pub const LEN: usize = 8;
+
+    pub fn new(data: &'a [u8]) -> Option<ICMPHeader<'a>> {
+        if data.len() < ICMPHeader::LEN {
+            return None;
+        }
+        Some(ICMPHeader { data })
+    }
+
+    #[inline]
+    pub fn icmp_type(&self) -> u8 {
+        return self.data[0];
+    }
+
+    #[inline]
+    pub fn code(&self) -> u8 {
+        return self.data[1];
+    }
+
+    #[inline]
+    pub fn checksum(&self) -> u16 {
+        return inet::read_u16(self.data, 2);
+    }
+
+    //echo id & sequence, unused or mtu for errors
+    #[inline]
+    pub fn id(&self) -> u16 {
+        return inet::read_u16(self.data, 4);
+    }
+
+    #[inline]
+    pub fn sequence(&self) -> u16 {
+        return inet::read_u16(self.data, 6);
+    }
+}
+
+// the tuple of the datagram an icmp error was sent for, as the sender saw it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct QuotedFlow {
+    pub proto: IPProto,
+    pub src_ip: IpAddr,
+    pub dst_ip: IpAddr,
+    pub src_port: u16,
+    pub dst_port: u16,
+}
+
+// an icmp error recorded on the tcp or udp stream it quotes
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ICMPError {
+    pub icmp_type: u8,
+    pub code: u8,
+    pub ipv6: bool,
+    // the host or router that sent the error
+    pub reporter: IpAddr,
+    pub timestamp: u64,
+}
+
+impl ICMPError {
+    pub fn type_string(&self) -> &'static str {
+        if self.ipv6 {
+            ICMPv6Type(self.icmp_type).to_string()
+        } else {
+            ICMPType(self.icmp_type).to_string()
+        }
+    }
+}
+
+impl fmt::Display for ICMPError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} code {} from {}",
+            self.type_string(),
+            self.code,
+            self.reporter
+        )
+    }
+}
@@ -0,0 +1,178 @@
+use config::StreamConfig;
+use layer::ip::StreamID;
+use layer::packet::Packet;
+use stats::WorkerStats;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+// echo requests of one identifier between two hosts and their replies
+pub struct EchoFlow {
+    pub interface: Arc<str>,
+    // sender of the first request
+    pub client: IpAddr,
+    pub server: IpAddr,
+    pub id: u16,
+
+    pub requests: u64,
+    pub replies: u64,
+    pub first_seen: u64,
+    pub last_seen: u64,
+    // nano second, of the last answered request
+    pub rtt: Option<u64>,
+
+    // request timestamp by sequence number
+    pending: HashMap<u16, u64>,
+}
+
+impl EchoFlow {
+    const MAX_PENDING: usize = 64;
+
+    fn new(packet: &Arc<Packet>, id: u16) -> EchoFlow {
+        let (client, server) = if packet.is_echo_request() {
+            (packet.src_ip, packet.dst_ip)
+        } else {
+            (packet.dst_ip, packet.src_ip)
+        };
+        EchoFlow {
+            interface: packet.interface.clone(),
+            client,
+            server,
+            id,
+            requests: 0,
+            replies: 0,
+            first_seen: packet.timestamp,
+            last_seen: packet.timestamp,
+            rtt: None,
+            pending: HashMap::new(),
+        }
+    }
+
+    fn on_packet(&mut self, packet: &Arc<Packet>, sequence: u16) {
+        self.last_seen = packet.timestamp;
+
+        if packet.is_echo_request() {
+            self.requests += 1;
+            // unanswered requests are forgotten once too many are waiting
+            if self.pending.len() >= EchoFlow::MAX_PENDING {
+                self.pending.clear();
+            }
+            self.pending.insert(sequence, packet.timestamp);
+        } else {
+            self.replies += 1;
+            if let Some(sent) = self.pending.remove(&sequence) {
+                self.rtt = Some(packet.timestamp.saturating_sub(sent));
+            }
+        }
+    }
+
+    fn log(&self) {
+        debug!(
+            "icmp echo {} {} -> {} id {} requests {} replies {} rtt {:?}",
+            self.interface,
+            self.client,
+            self.server,
+            self.id,
+            self.requests,
+            self.replies,
+            self.rtt
+        );
+    }
+}
+
+// pairs echo requests with their replies; icmp errors go to the stream they quote
+pub struct ICMPTracker {
+    flows: HashMap<StreamID, EchoFlow>,
+    last_cleanup: u64,
+    stats: Arc<WorkerStats>,
+    config: StreamConfig,
+}
+
+impl ICMPTracker {
+    //nano second
+    const FLOW_CLEANUP_DURATION: u64 = 1000 * 1000 * 1000 * 30;
+
+    pub fn new(stats: Arc<WorkerStats>, config: &StreamConfig) -> ICMPTracker {
+        ICMPTracker {
+            flows: HashMap::new(),
+            last_cleanup: 0,
+            stats,
+            config: config.clone(),
+        }
+    }
+
+    pub fn on_packet(&mut self, packet: &Arc<Packet>) {
+        let icmp = match packet.icmp() {
+            Some(icmp) => icmp,
+            None => return,
+        };
+        if !packet.is_echo_request() && !packet.is_echo_reply() {
+            trace!("icmp {} {}", packet.icmp_type_str(), icmp.code());
+            return;
+        }
+
+        let echo_id = icmp.id();
+        let vlan = if self.config.track_vlan {
+            packet.vlan_key()
+        } else {
+            [0, 0]
+        };
+        // the echo identifier stands in for both ports
+        let id = StreamID::with_vlan(vlan, packet.src_ip, packet.dst_ip, echo_id, echo_id);
+
+        if !self.flows.contains_key(&id) {
+            self.stats.icmp_flows_total.fetch_add(1, Ordering::Relaxed);
+            self.flows.insert(id, EchoFlow::new(packet, echo_id));
+        }
+        self.flows
+            .get_mut(&id)
+            .unwrap()
+            .on_packet(packet, icmp.sequence());
+
+        self.cleanup_flow(packet.timestamp);
+        self.update_stats();
+    }
+
+    pub fn flows(&self) -> Vec<&EchoFlow> {
+        self.flows.values().collect()
+    }
+
+    pub fn cleanup_flow(&mut self, tm: u64) -> usize {
+        if self.last_cleanup + ICMPTracker::FLOW_CLEANUP_DURATION > tm {
+            return 0;
+        }
+
+        let before = self.flows.len();
+        self.flows.retain(|_k, flow| -> bool {
+            let keep = flow.last_seen + ICMPTracker::FLOW_CLEANUP_DURATION > tm;
+            if !keep {
+                flow.log();
+            }
+            keep
+        });
+
+        let after = self.flows.len();
+        debug!("icmp flow cleanup {}/{}", before - after, before);
+        self.last_cleanup = tm;
+        self.update_stats();
+        return before - after;
+    }
+
+    pub fn flush(&mut self) -> usize {
+        let n = self.flows.len();
+        for flow in self.flows.values() {
+            flow.log();
+        }
+        self.flows.clear();
+        debug!("icmp flow flush {}", n);
+        self.update_stats();
+        return n;
+    }
+
+    fn update_stats(&self) {
+        self.stats
+            .icmp_flows
+            .store(self.flows.len() as u64, Ordering::Relaxed);
+    }
+}
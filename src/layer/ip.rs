@@ -1,5 +1,6 @@
 use crate::inet;
 use std::cmp;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct IPProto(pub u8);
@@ -17,10 +18,15 @@ impl IPProto {
     pub const TP: IPProto = IPProto(29); /* SO Transport Protocol Class 4.  */
     pub const DCCP: IPProto = IPProto(33); /* Datagram Congestion Control Protocol.  */
     pub const IPV6: IPProto = IPProto(41); /* IPv6 header.  */
+    pub const ROUTING: IPProto = IPProto(43); /* IPv6 routing header.  */
+    pub const FRAGMENT: IPProto = IPProto(44); /* IPv6 fragmentation header.  */
     pub const RSVP: IPProto = IPProto(46); /* Reservation Protocol.  */
     pub const GRE: IPProto = IPProto(47); /* General Routing Encapsulation.  */
     pub const ESP: IPProto = IPProto(50); /* encapsulating security payload.  */
     pub const AH: IPProto = IPProto(51); /* authentication header.  */
+    pub const ICMPV6: IPProto = IPProto(58); /* ICMPv6.  */
+    pub const NONE: IPProto = IPProto(59); /* IPv6 no next header.  */
+    pub const DSTOPTS: IPProto = IPProto(60); /* IPv6 destination options.  */
     pub const MTP: IPProto = IPProto(92); /* Multicast Transport Protocol.  */
     pub const BEETPH: IPProto = IPProto(94); /* IP option pseudo header for BEET.  */
     pub const ENCAP: IPProto = IPProto(98); /* Encapsulation Header.  */
@@ -30,6 +36,9 @@ impl IPProto {
     pub const UDPLITE: IPProto = IPProto(136); /* UDP-Lite protocol.  */
     pub const RAW: IPProto = IPProto(255); /* Raw IP packets.  */
 
+    // IPv6 hop-by-hop options share the value of IP
+    pub const HOPOPTS: IPProto = IPProto(0);
+
     pub fn to_string(self) -> &'static str {
         match self {
             IPProto::IP => "IP",
@@ -44,10 +53,15 @@ impl IPProto {
             IPProto::TP => "TP",
             IPProto::DCCP => "DCCP",
             IPProto::IPV6 => "IPV6",
+            IPProto::ROUTING => "ROUTING",
+            IPProto::FRAGMENT => "FRAGMENT",
             IPProto::RSVP => "RSVP",
             IPProto::GRE => "GRE",
             IPProto::ESP => "ESP",
             IPProto::AH => "AH",
+            IPProto::ICMPV6 => "ICMPV6",
+            IPProto::NONE => "NONE",
+            IPProto::DSTOPTS => "DSTOPTS",
             IPProto::MTP => "MTP",
             IPProto::BEETPH => "BEETPH",
             IPProto::ENCAP => "ENCAP",
@@ -61,71 +75,279 @@ impl IPProto {
     }
 }
 
-#[repr(C, packed)]
-pub struct IPv4Header {
-    //version & header length
-    pub version_length: u8,
+// the fixed ipv4 header, options follow it
+#[derive(Clone, Copy)]
+pub struct IPv4Header<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> IPv4Header<'a> {
+    pub const LEN: usize = 20;
+
+    // None when the slice cannot hold the fixed header
+    pub fn new(data: &'a [u8]) -> Option<IPv4Header<'a>> {
+        if data.len() < IPv4Header::LEN {
+            return None;
+        }
+        Some(IPv4Header { data })
+    }
+
+    #[inline]
+    pub fn version(&self) -> u8 {
+        return self.data[0] >> 4;
+    }
 
-    //type of service
-    pub tos: u8,
+    #[inline]
+    pub fn header_len(&self) -> u8 {
+        return (self.data[0] & 0x0F) << 2;
+    }
+
+    #[inline]
+    pub fn tos(&self) -> u8 {
+        return self.data[1];
+    }
 
     // including header and data
-    pub len: u16,
+    #[inline]
+    pub fn total_length(&self) -> u16 {
+        return inet::read_u16(self.data, 2);
+    }
 
-    //id
-    pub id: u16,
+    #[inline]
+    pub fn identification(&self) -> u16 {
+        return inet::read_u16(self.data, 4);
+    }
 
-    //Flags & frag offset
-    pub flag_offset: u16,
+    // in bytes
+    #[inline]
+    pub fn frag_offset(&self) -> u16 {
+        return (inet::read_u16(self.data, 6) & 0x1fff) << 3;
+    }
 
-    //time to live
-    pub ttl: u8,
+    #[inline]
+    pub fn more_fragments(&self) -> bool {
+        return inet::read_u16(self.data, 6) & 0x2000 > 0;
+    }
 
-    //protocol (tcp, udp, etc)
-    pub proto: u8,
+    #[inline]
+    pub fn is_fragment(&self) -> bool {
+        return self.more_fragments() || self.frag_offset() != 0;
+    }
 
-    //checksum
-    pub checksum: u16,
+    #[inline]
+    pub fn dont_fragment(&self) -> bool {
+        return inet::read_u16(self.data, 6) & 0x4000 > 0;
+    }
+
+    #[inline]
+    pub fn reserved_flag(&self) -> bool {
+        return inet::read_u16(self.data, 6) & 0x8000 > 0;
+    }
+
+    #[inline]
+    pub fn ttl(&self) -> u8 {
+        return self.data[8];
+    }
+
+    #[inline]
+    pub fn proto(&self) -> IPProto {
+        return IPProto(self.data[9]);
+    }
+
+    #[inline]
+    pub fn checksum(&self) -> u16 {
+        return inet::read_u16(self.data, 10);
+    }
+
+    #[inline]
+    pub fn src(&self) -> Ipv4Addr {
+        return Ipv4Addr::from(inet::read_u32(self.data, 12));
+    }
 
-    //source address
-    pub src: u32,
+    #[inline]
+    pub fn dst(&self) -> Ipv4Addr {
+        return Ipv4Addr::from(inet::read_u32(self.data, 16));
+    }
+}
 
-    //destination address
-    pub dst: u32,
+#[derive(Clone, Copy)]
+pub struct IPv6Header<'a> {
+    data: &'a [u8],
 }
 
-impl IPv4Header {
+impl<'a> IPv6Header<'a> {
+    pub const LEN: usize = 40;
+
+    pub fn new(data: &'a [u8]) -> Option<IPv6Header<'a>> {
+        if data.len() < IPv6Header::LEN {
+            return None;
+        }
+        Some(IPv6Header { data })
+    }
+
     #[inline]
     pub fn version(&self) -> u8 {
-        return (self.version_length & 0xF0) >> 4;
+        return self.data[0] >> 4;
     }
 
+    // extension headers and data, excluding this header
     #[inline]
-    pub fn header_len(&self) -> u8 {
-        return (self.version_length & 0x0F) << 2;
+    pub fn payload_length(&self) -> u16 {
+        return inet::read_u16(self.data, 4);
     }
 
     #[inline]
-    pub fn total_length(&self) -> u16 {
-        unsafe { inet::ntohs(self.len) }
+    pub fn next_header(&self) -> IPProto {
+        return IPProto(self.data[6]);
+    }
+
+    #[inline]
+    pub fn hop_limit(&self) -> u8 {
+        return self.data[7];
+    }
+
+    #[inline]
+    pub fn src(&self) -> Ipv6Addr {
+        return Ipv6Addr::from(self.address(8));
+    }
+
+    #[inline]
+    pub fn dst(&self) -> Ipv6Addr {
+        return Ipv6Addr::from(self.address(24));
+    }
+
+    fn address(&self, offset: usize) -> [u8; 16] {
+        let mut address = [0u8; 16];
+        address.copy_from_slice(&self.data[offset..offset + 16]);
+        return address;
+    }
+}
+
+// hop-by-hop, routing and destination options headers
+#[derive(Clone, Copy)]
+pub struct IPv6ExtHeader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> IPv6ExtHeader<'a> {
+    pub const LEN: usize = 2;
+
+    pub fn new(data: &'a [u8]) -> Option<IPv6ExtHeader<'a>> {
+        if data.len() < IPv6ExtHeader::LEN {
+            return None;
+        }
+        Some(IPv6ExtHeader { data })
+    }
+
+    #[inline]
+    pub fn next_header(&self) -> IPProto {
+        return IPProto(self.data[0]);
+    }
+
+    // the length field is in 8 bytes units, not including the first 8 bytes
+    #[inline]
+    pub fn header_len(&self) -> usize {
+        return (self.data[1] as usize + 1) * 8;
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct IPv6FragmentHeader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> IPv6FragmentHeader<'a> {
+    pub const LEN: usize = 8;
+
+    pub fn new(data: &'a [u8]) -> Option<IPv6FragmentHeader<'a>> {
+        if data.len() < IPv6FragmentHeader::LEN {
+            return None;
+        }
+        Some(IPv6FragmentHeader { data })
+    }
+
+    #[inline]
+    pub fn next_header(&self) -> IPProto {
+        return IPProto(self.data[0]);
+    }
+
+    // in bytes
+    #[inline]
+    pub fn offset(&self) -> u16 {
+        return inet::read_u16(self.data, 2) & 0xfff8;
+    }
+
+    #[inline]
+    pub fn more_fragments(&self) -> bool {
+        return inet::read_u16(self.data, 2) & 0x1 > 0;
+    }
+
+    #[inline]
+    pub fn identification(&self) -> u32 {
+        return inet::read_u32(self.data, 4);
+    }
+}
+
+// symmetric in source and destination once summed, used to spread flows on workers
+pub fn ip_hash(ip: &IpAddr) -> u32 {
+    match *ip {
+        IpAddr::V4(ip) => u32::from(ip),
+        IpAddr::V6(ip) => {
+            let octets = ip.octets();
+            let mut hash: u32 = 0;
+            for word in octets.chunks(4) {
+                let word = (word[0] as u32) << 24
+                    | (word[1] as u32) << 16
+                    | (word[2] as u32) << 8
+                    | word[3] as u32;
+                hash = hash.wrapping_add(word);
+            }
+            hash
+        }
+    }
+}
+
+// host byte order, 0 for IPv6 (ndpi guesses v6 flows by port only)
+pub fn ipv4_host_order(ip: &IpAddr) -> u32 {
+    match *ip {
+        IpAddr::V4(ip) => u32::from(ip),
+        IpAddr::V6(_) => 0,
     }
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub struct StreamID {
-    min_ip: u32,
-    max_ip: u32,
+    min_ip: IpAddr,
+    max_ip: IpAddr,
     min_port: u16,
     max_port: u16,
+    vlan: [u16; 2],
 }
 
 impl StreamID {
-    pub fn new(client_ip: u32, server_ip: u32, client_port: u16, server_port: u16) -> StreamID {
+    pub fn new(
+        client_ip: IpAddr,
+        server_ip: IpAddr,
+        client_port: u16,
+        server_port: u16,
+    ) -> StreamID {
+        StreamID::with_vlan([0, 0], client_ip, server_ip, client_port, server_port)
+    }
+
+    // see Packet::vlan_key
+    pub fn with_vlan(
+        vlan: [u16; 2],
+        client_ip: IpAddr,
+        server_ip: IpAddr,
+        client_port: u16,
+        server_port: u16,
+    ) -> StreamID {
         StreamID {
             min_ip: cmp::min(client_ip, server_ip),
             max_ip: cmp::max(client_ip, server_ip),
             min_port: cmp::min(client_port, server_port),
             max_port: cmp::max(client_port, server_port),
+            vlan,
         }
     }
 }
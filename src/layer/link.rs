@@ -0,0 +1,98 @@
+use crate::inet;
+use layer::EthernetType;
+
+// datalink type of a capture, DLT_ values as returned by pcap_datalink
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LinkType(pub i32);
+
+impl LinkType {
+    pub const NULL: LinkType = LinkType(0); /* BSD loopback, host order family */
+    pub const EN10MB: LinkType = LinkType(1);
+    pub const RAW: LinkType = LinkType(12); /* raw IP, DLT_RAW on linux */
+    pub const LINKTYPE_RAW: LinkType = LinkType(101); /* raw IP, savefile value */
+    pub const LOOP: LinkType = LinkType(108); /* OpenBSD loopback, net order family */
+    pub const LINUX_SLL: LinkType = LinkType(113); /* linux cooked capture v1 */
+    pub const IPV4: LinkType = LinkType(228);
+    pub const IPV6: LinkType = LinkType(229);
+    pub const LINUX_SLL2: LinkType = LinkType(276); /* linux cooked capture v2 */
+
+    pub fn link_type_string(value: LinkType) -> &'static str {
+        match value {
+            LinkType::NULL => "NULL",
+            LinkType::EN10MB => "EN10MB",
+            LinkType::RAW => "RAW",
+            LinkType::LINKTYPE_RAW => "RAW",
+            LinkType::LOOP => "LOOP",
+            LinkType::LINUX_SLL => "LINUX_SLL",
+            LinkType::IPV4 => "IPV4",
+            LinkType::IPV6 => "IPV6",
+            LinkType::LINUX_SLL2 => "LINUX_SLL2",
+            _ => "Unknown",
+        }
+    }
+
+    pub fn is_supported(&self) -> bool {
+        LinkType::link_type_string(*self) != "Unknown"
+    }
+}
+
+// address families found in DLT_NULL / DLT_LOOP headers
+pub const BSD_AF_INET: u32 = 2;
+pub const BSD_AF_INET6: [u32; 4] = [10, 24, 28, 30]; /* linux, bsd, freebsd, darwin */
+
+#[derive(Clone, Copy)]
+pub struct SLLHeader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> SLLHeader<'a> {
+    pub const LEN: usize = 16;
+
+    pub fn new(data: &'a [u8]) -> Option<SLLHeader<'a>> {
+        if data.len() < SLLHeader::LEN {
+            return None;
+        }
+        Some(SLLHeader { data })
+    }
+
+    #[inline]
+    pub fn pkt_type(&self) -> u16 {
+        return inet::read_u16(self.data, 0);
+    }
+
+    #[inline]
+    pub fn protocol(&self) -> EthernetType {
+        return EthernetType(inet::read_u16(self.data, 14));
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct SLL2Header<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> SLL2Header<'a> {
+    pub const LEN: usize = 20;
+
+    pub fn new(data: &'a [u8]) -> Option<SLL2Header<'a>> {
+        if data.len() < SLL2Header::LEN {
+            return None;
+        }
+        Some(SLL2Header { data })
+    }
+
+    #[inline]
+    pub fn protocol(&self) -> EthernetType {
+        return EthernetType(inet::read_u16(self.data, 0));
+    }
+
+    #[inline]
+    pub fn if_index(&self) -> u32 {
+        return inet::read_u32(self.data, 4);
+    }
+
+    #[inline]
+    pub fn pkt_type(&self) -> u8 {
+        return self.data[10];
+    }
+}
@@ -1,23 +1,45 @@
+pub mod anomaly;
+pub mod arp;
+pub mod arp_table;
+pub mod buffer;
+pub mod checksum;
+pub mod defrag;
 pub mod dispatcher;
 pub mod ethernet;
+pub mod icmp;
+pub mod icmp_tracker;
 pub mod ip;
+pub mod link;
+pub mod mpls;
 pub mod packet;
+pub mod pppoe;
 pub mod stream_state;
 pub mod tcp;
 pub mod tcp_flow;
 pub mod tcp_stream;
 pub mod tcp_tracker;
+pub mod tunnel;
 pub mod udp;
 pub mod udp_stream;
 pub mod udp_tracker;
 pub mod vlan;
 
+pub use self::anomaly::*;
+pub use self::arp::*;
+pub use self::arp_table::*;
+pub use self::buffer::*;
 pub use self::ethernet::*;
+pub use self::icmp::*;
+pub use self::icmp_tracker::*;
 pub use self::ip::*;
+pub use self::link::*;
+pub use self::mpls::*;
+pub use self::pppoe::*;
 pub use self::tcp::*;
 pub use self::tcp_flow::TcpFlow;
 pub use self::tcp_stream::*;
 pub use self::tcp_tracker::*;
+pub use self::tunnel::*;
 pub use self::udp::*;
 pub use self::udp_tracker::*;
 pub use self::vlan::*;
@@ -0,0 +1,39 @@
+use crate::inet;
+
+// one label stack entry
+#[derive(Clone, Copy)]
+pub struct MPLSHeader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> MPLSHeader<'a> {
+    pub const LEN: usize = 4;
+
+    pub fn new(data: &'a [u8]) -> Option<MPLSHeader<'a>> {
+        if data.len() < MPLSHeader::LEN {
+            return None;
+        }
+        Some(MPLSHeader { data })
+    }
+
+    //label, traffic class, bottom of stack & ttl
+    #[inline]
+    fn entry(&self) -> u32 {
+        return inet::read_u32(self.data, 0);
+    }
+
+    #[inline]
+    pub fn label(&self) -> u32 {
+        return self.entry() >> 12;
+    }
+
+    #[inline]
+    pub fn bottom_of_stack(&self) -> bool {
+        return self.entry() & 0x100 > 0;
+    }
+
+    #[inline]
+    pub fn ttl(&self) -> u8 {
+        return self.entry() as u8;
+    }
+}
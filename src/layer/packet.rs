@@ -1,42 +1,67 @@
 use crate::inet;
+use crate::layer::checksum;
+use crate::layer::ARPHeader;
 use crate::layer::IPProto;
+use crate::layer::TCPOptions;
+use crate::layer::{Anomaly, DecodeError};
+use crate::layer::{BufferPool, PacketBuffer};
+use crate::layer::{ERSPANHeader, GREHeader, GeneveHeader, VXLANHeader};
 use crate::layer::{EthernetHeader, EthernetType, IPv4Header, TCPHeader, UDPHeader, VlanHeader};
-use std::mem;
-use std::ptr;
-use std::slice;
+use crate::layer::{ICMPError, ICMPHeader, ICMPType, ICMPv6Type, QuotedFlow};
+use crate::layer::{IPv6ExtHeader, IPv6FragmentHeader, IPv6Header};
+use crate::layer::{LinkType, SLL2Header, SLLHeader, BSD_AF_INET, BSD_AF_INET6};
+use crate::layer::{MPLSHeader, PPPProto, PPPoEHeader};
+use crate::layer::{Tunnel, TunnelType, GENEVE_PORT, VXLAN_PORT};
+use std::net::{IpAddr, Ipv4Addr};
 use std::sync::Arc;
 
 pub struct Packet {
     pub state: u32,
+    // why decoding stopped, the state bits tell how far it got
+    pub error: Option<DecodeError>,
+    // oddities found on the way, the packet is still valid
+    pub anomalies: Vec<Anomaly>,
+    // nano second
     pub timestamp: u64,
-    pub data: Vec<u8>,
+    // ingress interface, or the capture file
+    pub interface: Arc<str>,
+    buffer: PacketBuffer,
 
     // host endian
     pub src_port: u16,
     pub dst_port: u16,
 
-    // net endian
-    pub src_ip: u32,
-    pub dst_ip: u32,
+    pub src_ip: IpAddr,
+    pub dst_ip: IpAddr,
 
-    pub ethernet: *const EthernetHeader,
-    pub ipv4: *const IPv4Header,
-    pub ip_layer_len: usize,
+    // vlan ids and mpls labels in wire order, outermost first
+    pub vlan_ids: Vec<u16>,
+    pub mpls_labels: Vec<u32>,
 
-    pub tcp: *const TCPHeader,
-    pub udp: *const UDPHeader,
+    // offsets of the decoded headers in the frame, read back through the views
+    ethernet: Option<usize>,
+    // start of the ipv4 or ipv6 header, what ndpi is fed
+    ip_layer: Option<usize>,
+    pub ip_layer_len: usize,
+    // tcp, udp or icmp header, the state tells which
+    transport: Option<usize>,
+    // src_ip and dst_ip hold the sender and target addresses
+    arp: Option<usize>,
+    // set for icmp errors quoting a datagram
+    pub icmp_quoted: Option<QuotedFlow>,
 
-    payload: *const u8,
+    payload: usize,
     payload_len: usize,
-}
-
-unsafe impl Send for Packet {}
 
-unsafe impl Sync for Packet {}
+    // tunnels the inner packet was taken out of, outermost first; the fields
+    // above describe the innermost packet
+    pub tunnels: Vec<Tunnel>,
+    // tunnels, bridged frames and pseudowires entered, bounded by MAX_TUNNEL_DEPTH
+    depth: usize,
+}
 
 impl Packet {
     pub const STATE_NONE: u32 = 0;
-    pub const BAD_PACKET: u32 = 1 << 0;
     pub const STATE_IPV4: u32 = 1 << 1;
     pub const STATE_IPV6: u32 = 1 << 2;
     pub const STATE_ARP: u32 = 1 << 3;
@@ -44,85 +69,291 @@ impl Packet {
     pub const STATE_TCP: u32 = 1 << 5;
     pub const STATE_UDP: u32 = 1 << 6;
     pub const STATE_PAYLOAD: u32 = 1 << 7;
+    // ipv4 fragment, decoded up to the ip header and left to the defragmenter
+    pub const STATE_FRAGMENT: u32 = 1 << 8;
+    // ipv6 fragment, decoded up to the fragment header; not reassembled, only counted
+    pub const STATE_IPV6_FRAGMENT: u32 = 1 << 9;
+
+    const MAX_TUNNEL_DEPTH: usize = 4;
 
     pub fn valid(&self) -> bool {
-        return self.state & Packet::BAD_PACKET == 0;
+        return self.error.is_none();
+    }
+
+    // an ipv4, tcp or udp checksum did not add up, see checksum::enable_verify
+    pub fn bad_checksum(&self) -> bool {
+        self.anomalies.iter().any(|anomaly| anomaly.is_checksum())
+    }
+
+    // the whole frame as captured
+    pub fn data(&self) -> &[u8] {
+        &self.buffer
+    }
+
+    pub fn ethernet(&self) -> Option<EthernetHeader<'_>> {
+        self.ethernet
+            .and_then(|offset| EthernetHeader::new(&self.buffer[offset..]))
+    }
+
+    pub fn ipv4(&self) -> Option<IPv4Header<'_>> {
+        if self.state & Packet::STATE_IPV4 == 0 {
+            return None;
+        }
+        self.ip_layer
+            .and_then(|offset| IPv4Header::new(&self.buffer[offset..]))
+    }
+
+    pub fn ipv6(&self) -> Option<IPv6Header<'_>> {
+        if self.state & Packet::STATE_IPV6 == 0 {
+            return None;
+        }
+        self.ip_layer
+            .and_then(|offset| IPv6Header::new(&self.buffer[offset..]))
+    }
+
+    pub fn tcp(&self) -> Option<TCPHeader<'_>> {
+        if self.state & Packet::STATE_TCP == 0 {
+            return None;
+        }
+        self.transport
+            .and_then(|offset| TCPHeader::new(&self.buffer[offset..]))
+    }
+
+    pub fn udp(&self) -> Option<UDPHeader<'_>> {
+        if self.state & Packet::STATE_UDP == 0 {
+            return None;
+        }
+        self.transport
+            .and_then(|offset| UDPHeader::new(&self.buffer[offset..]))
+    }
+
+    pub fn icmp(&self) -> Option<ICMPHeader<'_>> {
+        if self.state & Packet::STATE_ICMP == 0 {
+            return None;
+        }
+        self.transport
+            .and_then(|offset| ICMPHeader::new(&self.buffer[offset..]))
+    }
+
+    pub fn arp(&self) -> Option<ARPHeader<'_>> {
+        if self.state & Packet::STATE_ARP == 0 {
+            return None;
+        }
+        self.arp
+            .and_then(|offset| ARPHeader::new(&self.buffer[offset..]))
     }
 
     #[inline]
     pub fn src_mac(&self) -> String {
-        unsafe {
-            return (*self.ethernet).src_mac();
+        match self.ethernet() {
+            Some(ethernet) => ethernet.src_mac(),
+            None => String::new(),
         }
     }
 
     #[inline]
     pub fn dst_mac(&self) -> String {
-        unsafe {
-            return (*self.ethernet).dst_mac();
+        match self.ethernet() {
+            Some(ethernet) => ethernet.dst_mac(),
+            None => String::new(),
         }
     }
 
     pub fn src_ip_str(&self) -> String {
-        return inet::ip_to_string(self.src_ip);
+        return self.src_ip.to_string();
     }
 
     pub fn dst_ip_str(&self) -> String {
-        return inet::ip_to_string(self.dst_ip);
+        return self.dst_ip.to_string();
+    }
+
+    pub fn ip_layer_slice(&self) -> &[u8] {
+        let offset = self.ip_layer.expect("no ip layer");
+        return &self.buffer[offset..offset + self.ip_layer_len];
+    }
+
+    pub fn tunnels_str(&self) -> String {
+        let tunnels: Vec<String> = self.tunnels.iter().map(|t| t.to_string()).collect();
+        return tunnels.join(" ");
+    }
+
+    // outer two tags, 0 when missing; what the trackers key streams with
+    pub fn vlan_key(&self) -> [u16; 2] {
+        let mut key = [0u16; 2];
+        for (i, vid) in self.vlan_ids.iter().take(2).enumerate() {
+            key[i] = *vid;
+        }
+        return key;
+    }
+
+    pub fn is_echo_request(&self) -> bool {
+        let icmp_type = match self.icmp() {
+            Some(icmp) => icmp.icmp_type(),
+            None => return false,
+        };
+        if self.state & Packet::STATE_IPV6 > 0 {
+            return ICMPv6Type(icmp_type) == ICMPv6Type::ECHO_REQUEST;
+        }
+        return ICMPType(icmp_type) == ICMPType::ECHO_REQUEST;
+    }
+
+    pub fn is_echo_reply(&self) -> bool {
+        let icmp_type = match self.icmp() {
+            Some(icmp) => icmp.icmp_type(),
+            None => return false,
+        };
+        if self.state & Packet::STATE_IPV6 > 0 {
+            return ICMPv6Type(icmp_type) == ICMPv6Type::ECHO_REPLY;
+        }
+        return ICMPType(icmp_type) == ICMPType::ECHO_REPLY;
+    }
+
+    pub fn icmp_type_str(&self) -> &'static str {
+        let icmp_type = match self.icmp() {
+            Some(icmp) => icmp.icmp_type(),
+            None => return "",
+        };
+        if self.state & Packet::STATE_IPV6 > 0 {
+            return ICMPv6Type(icmp_type).to_string();
+        }
+        return ICMPType(icmp_type).to_string();
+    }
+
+    // what the quoted stream records
+    pub fn icmp_error(&self) -> Option<ICMPError> {
+        if self.icmp_quoted.is_none() {
+            return None;
+        }
+        let icmp = self.icmp()?;
+        Some(ICMPError {
+            icmp_type: icmp.icmp_type(),
+            code: icmp.code(),
+            ipv6: self.state & Packet::STATE_IPV6 > 0,
+            reporter: self.src_ip,
+            timestamp: self.timestamp,
+        })
+    }
+
+    pub fn payload_len(&self) -> usize {
+        self.payload_len
     }
 
     pub fn payload_slice(&self) -> &[u8] {
         assert!(self.state & Packet::STATE_PAYLOAD > 0);
-        unsafe { slice::from_raw_parts(self.payload, self.payload_len) }
+        return &self.buffer[self.payload..self.payload + self.payload_len];
     }
 
-    pub fn new(timestamp: u64, data: *const u8, size: usize) -> Arc<Packet> {
-        let array = unsafe { slice::from_raw_parts(data, size) };
+    // decodes a copy of data, linktype selects the first decoder
+    pub fn new(
+        interface: &Arc<str>,
+        linktype: LinkType,
+        timestamp: u64,
+        data: &[u8],
+    ) -> Arc<Packet> {
+        Packet::with_buffer(
+            interface,
+            linktype,
+            timestamp,
+            PacketBuffer::new(data.to_vec()),
+        )
+    }
+
+    // like new, the copy goes into a buffer recycled by the pool
+    pub fn with_pool(
+        interface: &Arc<str>,
+        linktype: LinkType,
+        timestamp: u64,
+        data: &[u8],
+        pool: &Arc<BufferPool>,
+    ) -> Arc<Packet> {
+        Packet::with_buffer(interface, linktype, timestamp, BufferPool::copy(pool, data))
+    }
 
+    pub fn with_buffer(
+        interface: &Arc<str>,
+        linktype: LinkType,
+        timestamp: u64,
+        buffer: PacketBuffer,
+    ) -> Arc<Packet> {
+        let size = buffer.len();
         let mut packet = Packet {
             state: 0,
-            data: Vec::from(array),
+            error: None,
+            anomalies: Vec::new(),
+            buffer,
             timestamp,
-            ethernet: ptr::null(),
-            ipv4: ptr::null(),
+            interface: interface.clone(),
+            vlan_ids: Vec::new(),
+            mpls_labels: Vec::new(),
+            ethernet: None,
+            ip_layer: None,
             ip_layer_len: 0,
-            tcp: ptr::null(),
-            udp: ptr::null(),
+            transport: None,
+            arp: None,
+            icmp_quoted: None,
 
             src_port: 0,
             dst_port: 0,
-            src_ip: 0,
-            dst_ip: 0,
+            src_ip: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            dst_ip: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
 
-            payload: ptr::null(),
+            payload: 0,
             payload_len: 0,
+
+            tunnels: Vec::new(),
+            depth: 0,
         };
-        if size < mem::size_of::<EthernetHeader>() {
-            debug!("invalid packet, size = {}", size);
-            packet.state |= Packet::BAD_PACKET;
-        } else {
-            packet.decode_ethernet();
+        match linktype {
+            LinkType::EN10MB => packet.decode_ethernet(0, size),
+            LinkType::LINUX_SLL => packet.decode_sll(),
+            LinkType::LINUX_SLL2 => packet.decode_sll2(),
+            LinkType::RAW | LinkType::LINKTYPE_RAW | LinkType::IPV4 | LinkType::IPV6 => {
+                packet.decode_raw(0, size)
+            }
+            LinkType::NULL | LinkType::LOOP => packet.decode_null(linktype),
+            _ => {
+                debug!("link type {}", linktype.0);
+                packet.fail(DecodeError::UnsupportedLinkType);
+            }
         }
         return Arc::new(packet);
     }
 
-    fn decode_ethernet(&mut self) {
-        let mut offset: usize = 0;
-        let mut left: usize = self.data.len();
-        self.ethernet = self.data.as_ptr() as *const EthernetHeader;
-
-        offset += mem::size_of::<EthernetHeader>();
-        left -= mem::size_of::<EthernetHeader>();
-
-        let eth_type = unsafe { EthernetType(inet::ntohs((*self.ethernet).eth_type)) };
+    fn decode_ethernet(&mut self, offset: usize, left: usize) {
+        let eth_type = match EthernetHeader::new(&self.buffer[offset..offset + left]) {
+            Some(ethernet) => ethernet.eth_type(),
+            None => {
+                debug!("invalid packet, size = {}", left);
+                self.fail(DecodeError::LinkTruncated);
+                return;
+            }
+        };
+        self.ethernet = Some(offset);
+        let header_len = EthernetHeader::LEN;
+        self.decode_eth_type(eth_type, offset + header_len, left - header_len);
+    }
 
+    fn decode_eth_type(&mut self, eth_type: EthernetType, offset: usize, left: usize) {
         match eth_type {
             EthernetType::IP => {
                 self.state |= Packet::STATE_IPV4;
                 self.decode_ipv4(offset, left);
             }
-            EthernetType::VLAN => self.decode_vlan(offset, left),
-            EthernetType::T8021QINQ => self.decode_vlan(offset, left),
+            EthernetType::IPV6 => {
+                self.state |= Packet::STATE_IPV6;
+                self.decode_ipv6(offset, left);
+            }
+            EthernetType::ARP => self.decode_arp(offset, left),
+            EthernetType::VLAN | EthernetType::T8021AD | EthernetType::T8021QINQ => {
+                self.decode_vlan(offset, left)
+            }
+            EthernetType::MPLS_UC | EthernetType::MPLS_MC => self.decode_mpls(offset, left),
+            EthernetType::PPPOE_SESS => self.decode_pppoe(offset, left),
+            EthernetType::BRIDGE => {
+                if self.enter_frame() {
+                    self.decode_ethernet(offset, left)
+                }
+            }
             _ => {
                 trace!(
                     "ethernet type {}",
@@ -132,75 +363,382 @@ impl Packet {
         }
     }
 
+    // linux cooked capture, what libpcap produces for the "any" device
+    fn decode_sll(&mut self) {
+        let size = self.buffer.len();
+        let eth_type = match SLLHeader::new(&self.buffer) {
+            Some(sll) => sll.protocol(),
+            None => {
+                debug!("invalid sll packet, size = {}", size);
+                self.fail(DecodeError::LinkTruncated);
+                return;
+            }
+        };
+        let offset = SLLHeader::LEN;
+        self.decode_eth_type(eth_type, offset, size - offset);
+    }
+
+    fn decode_sll2(&mut self) {
+        let size = self.buffer.len();
+        let eth_type = match SLL2Header::new(&self.buffer) {
+            Some(sll) => sll.protocol(),
+            None => {
+                debug!("invalid sll2 packet, size = {}", size);
+                self.fail(DecodeError::LinkTruncated);
+                return;
+            }
+        };
+        let offset = SLL2Header::LEN;
+        self.decode_eth_type(eth_type, offset, size - offset);
+    }
+
+    // 4 bytes address family, host order of the capturing machine for NULL, net order for LOOP
+    fn decode_null(&mut self, linktype: LinkType) {
+        let size = self.buffer.len();
+        if size < 4 {
+            debug!("invalid loopback packet, size = {}", size);
+            self.fail(DecodeError::LinkTruncated);
+            return;
+        }
+        let mut family = [0u8; 4];
+        family.copy_from_slice(&self.buffer[..4]);
+        let family = if linktype == LinkType::LOOP {
+            u32::from_be_bytes(family)
+        } else {
+            // the writer may have had either byte order, families are small numbers
+            let family = u32::from_le_bytes(family);
+            if family > 0xffff {
+                family.swap_bytes()
+            } else {
+                family
+            }
+        };
+
+        if family == BSD_AF_INET || BSD_AF_INET6.contains(&family) {
+            self.decode_raw(4, size - 4);
+        } else {
+            trace!("loopback family {}", family);
+        }
+    }
+
+    // no link header, the ip version tells the protocol
+    fn decode_raw(&mut self, offset: usize, left: usize) {
+        if left == 0 {
+            self.fail(DecodeError::IPTruncated);
+            return;
+        }
+        match self.buffer[offset] >> 4 {
+            4 => {
+                self.state |= Packet::STATE_IPV4;
+                self.decode_ipv4(offset, left);
+            }
+            6 => {
+                self.state |= Packet::STATE_IPV6;
+                self.decode_ipv6(offset, left);
+            }
+            version => {
+                trace!("ip version {}", version);
+            }
+        }
+    }
+
+    fn decode_arp(&mut self, offset: usize, left: usize) {
+        let arp = match ARPHeader::new(&self.buffer[offset..offset + left]) {
+            Some(arp) => arp,
+            None => {
+                self.fail(DecodeError::ARPTruncated);
+                return;
+            }
+        };
+        if arp.hardware_type() != ARPHeader::ETHERNET
+            || EthernetType(arp.protocol_type()) != EthernetType::IP
+            || arp.hardware_len() != 6
+            || arp.protocol_len() != 4
+        {
+            trace!(
+                "arp hardware {} protocol {:x}",
+                arp.hardware_type(),
+                arp.protocol_type()
+            );
+            return;
+        }
+        self.state |= Packet::STATE_ARP;
+        self.arp = Some(offset);
+        self.src_ip = IpAddr::V4(arp.sender_ip());
+        self.dst_ip = IpAddr::V4(arp.target_ip());
+    }
+
+    // the whole tag stack, 802.1q, 802.1ad and the old qinq type
     fn decode_vlan(&mut self, offset: usize, left: usize) {
-        if left < mem::size_of::<VlanHeader>() {
-            self.state |= Packet::BAD_PACKET;
+        let mut offset = offset;
+        let mut left = left;
+        loop {
+            let (vid, eth_type) = match VlanHeader::new(&self.buffer[offset..offset + left]) {
+                Some(vlan) => (vlan.vid(), vlan.eth_type()),
+                None => {
+                    self.fail(DecodeError::VlanTruncated);
+                    return;
+                }
+            };
+            self.vlan_ids.push(vid);
+
+            offset += VlanHeader::LEN;
+            left -= VlanHeader::LEN;
+
+            match eth_type {
+                EthernetType::VLAN | EthernetType::T8021AD | EthernetType::T8021QINQ => continue,
+                _ => return self.decode_eth_type(eth_type, offset, left),
+            }
+        }
+    }
+
+    fn decode_mpls(&mut self, offset: usize, left: usize) {
+        let mut offset = offset;
+        let mut left = left;
+        loop {
+            let (label, bottom) = match MPLSHeader::new(&self.buffer[offset..offset + left]) {
+                Some(mpls) => (mpls.label(), mpls.bottom_of_stack()),
+                None => {
+                    self.fail(DecodeError::MPLSTruncated);
+                    return;
+                }
+            };
+            self.mpls_labels.push(label);
+
+            offset += MPLSHeader::LEN;
+            left -= MPLSHeader::LEN;
+            if bottom {
+                break;
+            }
+        }
+
+        if left == 0 {
             return;
         }
-        let eth_type;
-        unsafe {
-            let vlan = self.data.as_ptr().offset(offset as isize) as *const VlanHeader;
-            let vlan = &*vlan;
-            eth_type = EthernetType(inet::ntohs(vlan.eth_type));
+        // mpls does not say what it carries, guess from the first nibble
+        match self.buffer[offset] >> 4 {
+            4 | 6 => self.decode_raw(offset, left),
+            // ethernet pseudowire behind a control word
+            0 if left >= 4 => {
+                if self.enter_frame() {
+                    self.decode_ethernet(offset + 4, left - 4)
+                }
+            }
+            nibble => trace!("mpls payload {}", nibble),
         }
+    }
 
-        let offset = offset + mem::size_of::<VlanHeader>();
-        let left = left - mem::size_of::<VlanHeader>();
+    fn decode_pppoe(&mut self, offset: usize, left: usize) {
+        let pppoe = match PPPoEHeader::new(&self.buffer[offset..offset + left]) {
+            Some(pppoe) => pppoe,
+            None => {
+                self.fail(DecodeError::PPPoELength);
+                return;
+            }
+        };
+        if pppoe.version_type() != 0x11 || pppoe.code() != 0 {
+            trace!(
+                "pppoe version {:x} code {}",
+                pppoe.version_type(),
+                pppoe.code()
+            );
+            return;
+        }
+        // the length covers the ppp protocol, ethernet padding follows it
+        let length = pppoe.length();
+        if length < 2 || length + 6 > left {
+            debug!("bad pppoe packet {} {}", left, length);
+            self.fail(DecodeError::PPPoELength);
+            return;
+        }
 
-        match eth_type {
-            EthernetType::IP => {
+        let proto = pppoe.protocol();
+        let offset = offset + PPPoEHeader::LEN;
+        let left = length - 2;
+        match proto {
+            PPPProto::IP => {
                 self.state |= Packet::STATE_IPV4;
                 self.decode_ipv4(offset, left);
             }
-            _ => {
-                trace!(
-                    "ethernet type {}",
-                    EthernetType::ethernet_type_string(eth_type)
-                );
+            PPPProto::IPV6 => {
+                self.state |= Packet::STATE_IPV6;
+                self.decode_ipv6(offset, left);
             }
+            _ => trace!("ppp protocol {:x}", proto.0),
         }
     }
 
     fn decode_ipv4(&mut self, offset: usize, left: usize) {
         assert!(self.state & Packet::STATE_IPV4 > 0);
 
-        if left < mem::size_of::<IPv4Header>() {
-            self.state |= Packet::BAD_PACKET;
-            return;
-        }
-
-        let ipv4_header = unsafe { self.data.as_ptr().offset(offset as isize) as *mut IPv4Header };
-        let ip = unsafe { &mut *ipv4_header };
+        let ip = match IPv4Header::new(&self.buffer[offset..offset + left]) {
+            Some(ip) => ip,
+            None => {
+                self.fail(DecodeError::IPTruncated);
+                return;
+            }
+        };
 
-        self.ipv4 = ipv4_header;
-        self.src_ip = ip.src;
-        self.dst_ip = ip.dst;
+        self.ip_layer = Some(offset);
+        self.src_ip = IpAddr::V4(ip.src());
+        self.dst_ip = IpAddr::V4(ip.dst());
 
         if ip.version() != 4 {
             debug!("bad version {}", ip.version());
-            self.state |= Packet::BAD_PACKET;
+            self.fail(DecodeError::IPVersion);
             return;
         }
         let header_len = ip.header_len() as usize;
         let ip_layer_len = ip.total_length() as usize;
         self.ip_layer_len = ip_layer_len;
 
-        if left < self.ip_layer_len || self.ip_layer_len < header_len {
-            debug!("bad packet {}, {}, {}", left, self.ip_layer_len, header_len);
-            self.state |= Packet::BAD_PACKET;
+        if header_len < IPv4Header::LEN {
+            self.fail(DecodeError::IPv4HeaderLen);
             return;
         }
+        if ip_layer_len < header_len {
+            debug!("bad packet {}, {}", ip_layer_len, header_len);
+            self.fail(DecodeError::IPLength);
+            return;
+        }
+        if left < ip_layer_len {
+            debug!("bad packet {}, {}", left, ip_layer_len);
+            self.fail(DecodeError::IPTruncated);
+            return;
+        }
+
+        if ip.ttl() == 0 {
+            self.anomalies.push(Anomaly::TTLZero);
+        }
+        if ip.reserved_flag() {
+            self.anomalies.push(Anomaly::IPv4ReservedFlag);
+        }
+        if checksum::verify_enabled()
+            && !checksum::ipv4_header_valid(&self.buffer[offset..offset + header_len])
+        {
+            self.anomalies.push(Anomaly::IPv4Checksum);
+        }
 
-        let proto = IPProto(ip.proto);
+        if ip.is_fragment() {
+            self.state |= Packet::STATE_FRAGMENT;
+            return;
+        }
+
+        let proto = ip.proto();
+        self.decode_transport(proto, offset + header_len, ip_layer_len - header_len);
+    }
+
+    fn decode_ipv6(&mut self, offset: usize, left: usize) {
+        assert!(self.state & Packet::STATE_IPV6 > 0);
+
+        let ip = match IPv6Header::new(&self.buffer[offset..offset + left]) {
+            Some(ip) => ip,
+            None => {
+                self.fail(DecodeError::IPTruncated);
+                return;
+            }
+        };
 
+        if ip.version() != 6 {
+            debug!("bad version {}", ip.version());
+            self.fail(DecodeError::IPVersion);
+            return;
+        }
+
+        self.ip_layer = Some(offset);
+        self.src_ip = IpAddr::V6(ip.src());
+        self.dst_ip = IpAddr::V6(ip.dst());
+
+        let header_len = IPv6Header::LEN;
+        let ip_layer_len = header_len + ip.payload_length() as usize;
+        self.ip_layer_len = ip_layer_len;
+
+        if left < ip_layer_len {
+            debug!("bad packet {}, {}", left, ip_layer_len);
+            self.fail(DecodeError::IPTruncated);
+            return;
+        }
+        if ip.hop_limit() == 0 {
+            self.anomalies.push(Anomaly::TTLZero);
+        }
+
+        let mut next = ip.next_header();
+        let mut offset = offset + header_len;
+        let mut left = ip_layer_len - header_len;
+
+        // walk the extension headers up to the transport header
+        loop {
+            let data = &self.buffer[offset..offset + left];
+            match next {
+                IPProto::HOPOPTS | IPProto::ROUTING | IPProto::DSTOPTS => {
+                    let ext = match IPv6ExtHeader::new(data) {
+                        Some(ext) => ext,
+                        None => {
+                            self.fail(DecodeError::IPv6ExtHeader);
+                            return;
+                        }
+                    };
+                    let ext_len = ext.header_len();
+                    if left < ext_len {
+                        debug!("bad ipv6 extension header {} {}", left, ext_len);
+                        self.fail(DecodeError::IPv6ExtHeader);
+                        return;
+                    }
+                    next = ext.next_header();
+                    offset += ext_len;
+                    left -= ext_len;
+                }
+                IPProto::FRAGMENT => {
+                    let frag = match IPv6FragmentHeader::new(data) {
+                        Some(frag) => frag,
+                        None => {
+                            self.fail(DecodeError::IPv6ExtHeader);
+                            return;
+                        }
+                    };
+                    // only a whole datagram carries a complete transport header and payload
+                    if frag.offset() != 0 || frag.more_fragments() {
+                        trace!("ipv6 fragment offset {}", frag.offset());
+                        self.state |= Packet::STATE_IPV6_FRAGMENT;
+                        return;
+                    }
+                    next = frag.next_header();
+                    offset += IPv6FragmentHeader::LEN;
+                    left -= IPv6FragmentHeader::LEN;
+                }
+                _ => break,
+            }
+        }
+
+        self.decode_transport(next, offset, left);
+    }
+
+    fn decode_transport(&mut self, proto: IPProto, offset: usize, left: usize) {
         match proto {
             IPProto::TCP => {
                 self.state |= Packet::STATE_TCP;
-                self.decode_tcp(offset + header_len, ip_layer_len - header_len);
+                self.decode_tcp(offset, left);
             }
             IPProto::UDP => {
                 self.state |= Packet::STATE_UDP;
-                self.decode_udp(offset + header_len, ip_layer_len - header_len);
+                self.decode_udp(offset, left);
+                if self.valid() {
+                    self.decode_udp_tunnel(offset + UDPHeader::LEN);
+                }
+            }
+            IPProto::ICMP | IPProto::ICMPV6 => self.decode_icmp(offset, left),
+            IPProto::GRE => self.decode_gre(offset, left),
+            IPProto::IPIP => {
+                if self.enter_tunnel(TunnelType::IPIP, 0) {
+                    self.state |= Packet::STATE_IPV4;
+                    self.decode_ipv4(offset, left);
+                }
+            }
+            IPProto::IPV6 => {
+                if self.enter_tunnel(TunnelType::IPIP, 0) {
+                    self.state |= Packet::STATE_IPV6;
+                    self.decode_ipv6(offset, left);
+                }
             }
 
             _ => {
@@ -209,49 +747,332 @@ impl Packet {
         }
     }
 
+    fn decode_icmp(&mut self, offset: usize, left: usize) {
+        let icmp_type = match ICMPHeader::new(&self.buffer[offset..offset + left]) {
+            Some(icmp) => icmp.icmp_type(),
+            None => {
+                self.fail(DecodeError::ICMPTruncated);
+                return;
+            }
+        };
+        self.state |= Packet::STATE_ICMP;
+        self.transport = Some(offset);
+
+        let is_error = if self.state & Packet::STATE_IPV6 > 0 {
+            ICMPv6Type(icmp_type).is_error()
+        } else {
+            ICMPType(icmp_type).is_error()
+        };
+
+        let offset = offset + ICMPHeader::LEN;
+        let left = left - ICMPHeader::LEN;
+        self.set_payload(offset, left);
+        if is_error {
+            self.icmp_quoted = self.decode_quoted(offset, left);
+        }
+    }
+
+    // ip header and the first transport bytes of the datagram an error is about,
+    // often truncated so only what the tuple needs is checked
+    fn decode_quoted(&self, offset: usize, left: usize) -> Option<QuotedFlow> {
+        if left == 0 {
+            return None;
+        }
+        let data = &self.buffer[offset..offset + left];
+        let (proto, src_ip, dst_ip, header_len) = match data[0] >> 4 {
+            4 => {
+                let ip = IPv4Header::new(data)?;
+                let header_len = ip.header_len() as usize;
+                if header_len < IPv4Header::LEN || ip.is_fragment() && ip.frag_offset() > 0 {
+                    return None;
+                }
+                (
+                    ip.proto(),
+                    IpAddr::V4(ip.src()),
+                    IpAddr::V4(ip.dst()),
+                    header_len,
+                )
+            }
+            6 => {
+                let ip = IPv6Header::new(data)?;
+                let mut next = ip.next_header();
+                let mut header_len = IPv6Header::LEN;
+                // the transport header is behind the extension headers, like in decode_ipv6
+                loop {
+                    let data = data.get(header_len..)?;
+                    match next {
+                        IPProto::HOPOPTS | IPProto::ROUTING | IPProto::DSTOPTS => {
+                            let ext = IPv6ExtHeader::new(data)?;
+                            next = ext.next_header();
+                            header_len += ext.header_len();
+                        }
+                        IPProto::FRAGMENT => {
+                            let frag = IPv6FragmentHeader::new(data)?;
+                            if frag.offset() != 0 {
+                                return None;
+                            }
+                            next = frag.next_header();
+                            header_len += IPv6FragmentHeader::LEN;
+                        }
+                        _ => break,
+                    }
+                }
+                (next, IpAddr::V6(ip.src()), IpAddr::V6(ip.dst()), header_len)
+            }
+            _ => return None,
+        };
+
+        let mut quoted = QuotedFlow {
+            proto,
+            src_ip,
+            dst_ip,
+            src_port: 0,
+            dst_port: 0,
+        };
+        // tcp and udp both start with the ports
+        if (proto == IPProto::TCP || proto == IPProto::UDP) && left >= header_len + 4 {
+            quoted.src_port = inet::read_u16(data, header_len);
+            quoted.dst_port = inet::read_u16(data, header_len + 2);
+        }
+        return Some(quoted);
+    }
+
+    // forget the outer transport, the inner decoders fill the fields again
+    fn enter_tunnel(&mut self, kind: TunnelType, id: u32) -> bool {
+        if self.depth >= Packet::MAX_TUNNEL_DEPTH {
+            trace!("tunnel too deep, stay at {}", kind.to_string());
+            return false;
+        }
+        self.depth += 1;
+        self.tunnels.push(Tunnel {
+            kind,
+            src_ip: self.src_ip,
+            dst_ip: self.dst_ip,
+            id,
+        });
+
+        self.state &= !(Packet::STATE_IPV4
+            | Packet::STATE_IPV6
+            | Packet::STATE_TCP
+            | Packet::STATE_UDP
+            | Packet::STATE_PAYLOAD);
+        self.transport = None;
+        self.src_port = 0;
+        self.dst_port = 0;
+        self.payload = 0;
+        self.payload_len = 0;
+        return true;
+    }
+
+    // an ethernet frame carried with no tunnel header to record, counts toward the depth
+    fn enter_frame(&mut self) -> bool {
+        if self.depth >= Packet::MAX_TUNNEL_DEPTH {
+            trace!("frames nested too deep");
+            return false;
+        }
+        self.depth += 1;
+        return true;
+    }
+
+    // only the first failure is kept, it is the one that stopped the decoder
+    fn fail(&mut self, error: DecodeError) {
+        if self.error.is_none() {
+            self.error = Some(error);
+        }
+    }
+
+    fn set_payload(&mut self, offset: usize, len: usize) {
+        self.payload = offset;
+        self.payload_len = len;
+        if len > 0 {
+            self.state |= Packet::STATE_PAYLOAD;
+        }
+    }
+
+    // offset is the start of the udp payload
+    fn decode_udp_tunnel(&mut self, offset: usize) {
+        let left = self.payload_len;
+        match self.dst_port {
+            VXLAN_PORT => {
+                let vni = match VXLANHeader::new(&self.buffer[offset..offset + left]) {
+                    Some(ref vxlan) if vxlan.flags() & VXLANHeader::VNI_VALID > 0 => vxlan.vni(),
+                    _ => return,
+                };
+                if self.enter_tunnel(TunnelType::VXLAN, vni) {
+                    let header_len = VXLANHeader::LEN;
+                    self.decode_ethernet(offset + header_len, left - header_len);
+                }
+            }
+            GENEVE_PORT => {
+                let geneve = match GeneveHeader::new(&self.buffer[offset..offset + left]) {
+                    Some(geneve) => geneve,
+                    None => return,
+                };
+                let header_len = geneve.header_len();
+                if geneve.version() != 0 || left < header_len {
+                    return;
+                }
+                let eth_type = geneve.protocol();
+                let vni = geneve.vni();
+                if self.enter_tunnel(TunnelType::GENEVE, vni) {
+                    self.decode_eth_type(eth_type, offset + header_len, left - header_len);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn decode_gre(&mut self, offset: usize, left: usize) {
+        let gre = match GREHeader::new(&self.buffer[offset..offset + left]) {
+            Some(gre) => gre,
+            None => {
+                self.fail(DecodeError::GRETruncated);
+                return;
+            }
+        };
+        if gre.version() != 0 {
+            trace!("gre version {}", gre.version());
+            return;
+        }
+        let header_len = gre.header_len();
+        if left < header_len {
+            debug!("bad gre packet {} {}", left, header_len);
+            self.fail(DecodeError::GRETruncated);
+            return;
+        }
+
+        let flags = gre.flags();
+        let eth_type = gre.protocol();
+        let key = gre.key().unwrap_or(0);
+
+        let offset = offset + header_len;
+        let left = left - header_len;
+
+        match eth_type {
+            // type I has no sequence number and no erspan header
+            EthernetType::ERSPAN if flags & GREHeader::SEQUENCE == 0 => {
+                if self.enter_tunnel(TunnelType::ERSPAN, 0) {
+                    self.decode_ethernet(offset, left);
+                }
+            }
+            EthernetType::ERSPAN | EthernetType::ERSPAN_III => {
+                let mut erspan_len = ERSPANHeader::LEN;
+                if eth_type == EthernetType::ERSPAN_III {
+                    erspan_len = ERSPANHeader::LEN_III;
+                    // optional platform specific sub-header
+                    if left >= erspan_len && self.buffer[offset + 11] & 0x01 > 0 {
+                        erspan_len += 8;
+                    }
+                }
+                if left < erspan_len {
+                    self.fail(DecodeError::GRETruncated);
+                    return;
+                }
+                let session = match ERSPANHeader::new(&self.buffer[offset..offset + left]) {
+                    Some(erspan) => erspan.session_id() as u32,
+                    None => return,
+                };
+                if self.enter_tunnel(TunnelType::ERSPAN, session) {
+                    self.decode_ethernet(offset + erspan_len, left - erspan_len);
+                }
+            }
+            // transparent ethernet bridging, the tunnel already counts for the frame
+            EthernetType::BRIDGE => {
+                if self.enter_tunnel(TunnelType::GRE, key) {
+                    self.decode_ethernet(offset, left);
+                }
+            }
+            _ => {
+                if self.enter_tunnel(TunnelType::GRE, key) {
+                    self.decode_eth_type(eth_type, offset, left);
+                }
+            }
+        }
+    }
+
     fn decode_tcp(&mut self, offset: usize, left: usize) {
         assert!(self.state & Packet::STATE_TCP > 0);
-        let tcp = unsafe { &mut *(self.data.as_ptr().offset(offset as isize) as *mut TCPHeader) };
+        let tcp = match TCPHeader::new(&self.buffer[offset..offset + left]) {
+            Some(tcp) => tcp,
+            None => {
+                self.fail(DecodeError::TCPTruncated);
+                return;
+            }
+        };
 
-        self.tcp = tcp;
-        self.src_port = unsafe { inet::ntohs(tcp.src_port) };
-        self.dst_port = unsafe { inet::ntohs(tcp.dst_port) };
+        self.transport = Some(offset);
+        self.src_port = tcp.src_port();
+        self.dst_port = tcp.dst_port();
 
-        let header_len = unsafe { (*self.tcp).header_len() as usize };
-        if left < header_len {
+        let header_len = tcp.header_len() as usize;
+        if left < header_len || header_len < TCPHeader::LEN {
             debug!("bad tcp packet {} {}", left, header_len);
-            self.state |= Packet::BAD_PACKET;
+            self.fail(DecodeError::TCPDataOffset);
             return;
         }
 
-        self.payload_len = left - header_len;
-        if self.payload_len > 0 {
-            self.state |= Packet::STATE_PAYLOAD;
-            let offset = offset + header_len;
-            self.payload = unsafe { self.data.as_ptr().offset(offset as isize) as *const u8 };
+        let flags = tcp.flags();
+        if flags == 0 || flags & TCPHeader::SYN > 0 && flags & (TCPHeader::FIN | TCPHeader::RST) > 0
+        {
+            self.anomalies.push(Anomaly::TCPFlags);
+        }
+        if TCPOptions::parse(&tcp).malformed {
+            self.anomalies.push(Anomaly::TCPOptions);
+        }
+        if checksum::verify_enabled()
+            && !checksum::transport_valid(
+                &self.src_ip,
+                &self.dst_ip,
+                IPProto::TCP,
+                &self.buffer[offset..offset + left],
+                tcp.checksum(),
+            )
+        {
+            self.anomalies.push(Anomaly::TCPChecksum);
         }
+
+        self.set_payload(offset + header_len, left - header_len);
     }
 
     fn decode_udp(&mut self, offset: usize, left: usize) {
         assert!(self.state & Packet::STATE_UDP > 0);
-        let udp = unsafe { &mut *(self.data.as_ptr().offset(offset as isize) as *mut UDPHeader) };
+        let udp = match UDPHeader::new(&self.buffer[offset..offset + left]) {
+            Some(udp) => udp,
+            None => {
+                self.fail(DecodeError::UDPTruncated);
+                return;
+            }
+        };
 
-        self.udp = udp;
-        self.src_port = unsafe { inet::ntohs(udp.src_port) };
-        self.dst_port = unsafe { inet::ntohs(udp.dst_port) };
+        self.transport = Some(offset);
+        self.src_port = udp.src_port();
+        self.dst_port = udp.dst_port();
 
-        let total_len = unsafe { inet::htons(udp.len) as usize };
-        if left < total_len {
-            debug!("bad tcp packet {} {}", left, total_len);
-            self.state |= Packet::BAD_PACKET;
+        let total_len = udp.length() as usize;
+        if total_len < UDPHeader::LEN || left < total_len {
+            debug!("bad udp packet {} {}", left, total_len);
+            self.fail(DecodeError::UDPLength);
             return;
         }
-
-        self.payload_len = left - mem::size_of::<UDPHeader>();
-        if self.payload_len > 0 {
-            self.state |= Packet::STATE_PAYLOAD;
-            let offset = offset + mem::size_of::<UDPHeader>();
-            self.payload = unsafe { self.data.as_ptr().offset(offset as isize) as *const u8 };
+        if total_len != left {
+            self.anomalies.push(Anomaly::UDPLengthMismatch);
+        }
+        // no checksum at all is allowed over ipv4
+        let unset = udp.checksum() == 0 && self.state & Packet::STATE_IPV4 > 0;
+        if checksum::verify_enabled()
+            && !unset
+            && !checksum::transport_valid(
+                &self.src_ip,
+                &self.dst_ip,
+                IPProto::UDP,
+                &self.buffer[offset..offset + total_len],
+                udp.checksum(),
+            )
+        {
+            self.anomalies.push(Anomaly::UDPChecksum);
         }
+
+        // trailing bytes past the udp length are padding, not payload
+        self.set_payload(offset + UDPHeader::LEN, total_len - UDPHeader::LEN);
     }
 }
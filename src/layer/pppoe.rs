@@ -0,0 +1,55 @@
+use crate::inet;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PPPProto(pub u16);
+
+impl PPPProto {
+    pub const IP: PPPProto = PPPProto(0x0021);
+    pub const IPV6: PPPProto = PPPProto(0x0057);
+    pub const LCP: PPPProto = PPPProto(0xc021);
+}
+
+// session stage header, followed by the ppp protocol
+#[derive(Clone, Copy)]
+pub struct PPPoEHeader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> PPPoEHeader<'a> {
+    // ppp protocol included
+    pub const LEN: usize = 8;
+
+    pub fn new(data: &'a [u8]) -> Option<PPPoEHeader<'a>> {
+        if data.len() < PPPoEHeader::LEN {
+            return None;
+        }
+        Some(PPPoEHeader { data })
+    }
+
+    //version & type, both 1
+    #[inline]
+    pub fn version_type(&self) -> u8 {
+        return self.data[0];
+    }
+
+    #[inline]
+    pub fn code(&self) -> u8 {
+        return self.data[1];
+    }
+
+    #[inline]
+    pub fn session(&self) -> u16 {
+        return inet::read_u16(self.data, 2);
+    }
+
+    // ppp protocol included
+    #[inline]
+    pub fn length(&self) -> usize {
+        return inet::read_u16(self.data, 4) as usize;
+    }
+
+    #[inline]
+    pub fn protocol(&self) -> PPPProto {
+        return PPPProto(inet::read_u16(self.data, 6));
+    }
+}
@@ -0,0 +1,137 @@
+use crate::config::StreamConfig;
+use crate::layer::tcp::{Reassembled, ReassemblyMemory};
+use std::cmp;
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+enum Held {
+    Data(Vec<u8>),
+    Gap(u64),
+}
+
+// with ack ordering, what a reassembler hands on waits here until the other side
+// acknowledges it, so both directions of a stream go out in the order the ends
+// saw them; a request is released by the segment carrying its response
+pub struct AckQueue {
+    // with the time each chunk was handed on
+    chunks: VecDeque<(Held, u64)>,
+    // sequence number after the last chunk, and the sequence space the chunks cover
+    end_seq: u32,
+    len: u64,
+    // data bytes held, released unacknowledged past the flow memory limit or
+    // when the shared memory runs out
+    held: usize,
+    limit: usize,
+    memory: Arc<ReassemblyMemory>,
+    //nano second
+    timeout: u64,
+    // when an ack last released something
+    progress: u64,
+}
+
+impl AckQueue {
+    pub fn new(config: &StreamConfig, memory: Arc<ReassemblyMemory>) -> AckQueue {
+        AckQueue {
+            chunks: VecDeque::new(),
+            end_seq: 0,
+            len: 0,
+            held: 0,
+            limit: config.flow_memory_limit,
+            memory,
+            timeout: config.gap_timeout * 1000 * 1000 * 1000,
+            progress: 0,
+        }
+    }
+
+    pub fn push(&mut self, chunk: Reassembled, now: u64) {
+        match chunk {
+            Reassembled::Data(data) => {
+                self.len += data.len() as u64;
+                self.held += data.len();
+                self.memory.charge(data.len());
+                self.chunks.push_back((Held::Data(data.to_vec()), now));
+            }
+            Reassembled::Gap(len) => {
+                self.len += len;
+                self.chunks.push_back((Held::Gap(len), now));
+            }
+        }
+    }
+
+    // where the reassembler stands after handing chunks on, the chunks end there
+    pub fn set_end_seq(&mut self, seq: u32) {
+        self.end_seq = seq;
+    }
+
+    // the other side acknowledged everything before ack
+    pub fn ack(&mut self, ack: u32, now: u64, deliver: &mut FnMut(Reassembled)) {
+        let start = self.end_seq.wrapping_sub(self.len as u32);
+        let acked = ack.wrapping_sub(start);
+        // an old ack
+        if acked >= 1 << 31 || acked == 0 || self.chunks.is_empty() {
+            return;
+        }
+        let len = self.len;
+        self.release(u64::from(acked).min(len), deliver);
+        self.progress = now;
+    }
+
+    // nothing waits for the ack any more once over a limit, or when the oldest
+    // chunk waited for the timeout without the acks moving on, as when only one
+    // direction is captured
+    pub fn expire(&mut self, now: u64, deliver: &mut FnMut(Reassembled)) {
+        if let Some(&(_, since)) = self.chunks.front() {
+            if cmp::max(since, self.progress) + self.timeout <= now {
+                self.flush(deliver);
+                return;
+            }
+        }
+        while self.held > self.limit || self.held > 0 && !self.memory.fits(0) {
+            let front = match self.chunks.front() {
+                Some(&(Held::Data(ref data), _)) => data.len() as u64,
+                Some(&(Held::Gap(len), _)) => len,
+                None => break,
+            };
+            self.release(front, deliver);
+        }
+    }
+
+    pub fn flush(&mut self, deliver: &mut FnMut(Reassembled)) {
+        let len = self.len;
+        self.release(len, deliver);
+    }
+
+    // hands on the first n bytes of sequence space, a gap goes out whole once
+    // the ack reaches into it
+    fn release(&mut self, mut n: u64, deliver: &mut FnMut(Reassembled)) {
+        while n > 0 {
+            match self.chunks.pop_front() {
+                Some((Held::Data(mut data), since)) => {
+                    let len = data.len() as u64;
+                    if len > n {
+                        let rest = data.split_off(n as usize);
+                        self.chunks.push_front((Held::Data(rest), since));
+                    }
+                    let released = data.len() as u64;
+                    self.len -= released;
+                    self.held -= data.len();
+                    self.memory.release(data.len());
+                    n -= released;
+                    deliver(Reassembled::Data(&data));
+                }
+                Some((Held::Gap(len), _)) => {
+                    self.len -= len;
+                    n = n.saturating_sub(len);
+                    deliver(Reassembled::Gap(len));
+                }
+                None => break,
+            }
+        }
+    }
+}
+
+impl Drop for AckQueue {
+    fn drop(&mut self) {
+        self.memory.release(self.held);
+    }
+}
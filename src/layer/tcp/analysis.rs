@@ -0,0 +1,293 @@
+use crate::layer::tcp::{ReassemblyMemory, TCPHeader};
+use std::cmp;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Arc;
+
+// what one side of a stream did that a clean transfer would not
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TCPCounters {
+    // segments carrying only sequence space sent before
+    pub retransmissions: u64,
+    // segments filling a hole left by a later one
+    pub out_of_order: u64,
+    // times the advertised window dropped to zero
+    pub zero_windows: u64,
+    // pure acks repeating the last ack and window
+    pub duplicate_acks: u64,
+    // segments sent again with other content than the first copy, an evasion signal
+    pub conflicting_overlaps: u64,
+}
+
+impl fmt::Display for TCPCounters {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "retransmissions {} out_of_order {} zero_windows {} duplicate_acks {} conflicting_overlaps {}",
+            self.retransmissions,
+            self.out_of_order,
+            self.zero_windows,
+            self.duplicate_acks,
+            self.conflicting_overlaps
+        )
+    }
+}
+
+// sequence distance from next to seq, negative behind it
+#[inline]
+fn offset(seq: u32, next: u32) -> i64 {
+    i64::from(seq.wrapping_sub(next) as i32)
+}
+
+// the last bytes one side sent, whether handed on or still out of order, to
+// compare the copies sent again against; charged to the reassembly memory and
+// forgotten first when it runs out
+struct SentBytes {
+    // the highest offset sent and its sequence number, offsets do not wrap
+    end: u64,
+    end_seq: u32,
+    // by offset, never overlapping each other
+    pieces: BTreeMap<u64, Vec<u8>>,
+    len: usize,
+    memory: Arc<ReassemblyMemory>,
+}
+
+impl SentBytes {
+    // the oldest bytes are forgotten past this, copies of them go unchecked
+    const MAX_LEN: usize = 16 * 1024;
+
+    fn new(seq: u32, memory: Arc<ReassemblyMemory>) -> SentBytes {
+        SentBytes {
+            end: 0,
+            end_seq: seq,
+            pieces: BTreeMap::new(),
+            len: 0,
+            memory,
+        }
+    }
+
+    // the bytes of data differing from what was sent before at the same place
+    fn on_data(&mut self, seq: u32, data: &[u8]) -> u64 {
+        let start = self.end as i64 + i64::from(seq.wrapping_sub(self.end_seq) as i32);
+        let (start, data) = if start < 0 {
+            if (-start) as usize >= data.len() {
+                return 0;
+            }
+            (0, &data[(-start) as usize..])
+        } else {
+            (start as u64, data)
+        };
+        let end = start + data.len() as u64;
+
+        // compare with the pieces it overlaps, keep the parts none covers
+        let mut conflicting = 0;
+        let mut offset = start;
+        let mut parts = Vec::new();
+        for (&key, piece) in self.pieces.range(..end) {
+            let piece_end = key + piece.len() as u64;
+            if piece_end <= start {
+                continue;
+            }
+            let from = cmp::max(key, start);
+            let to = cmp::min(piece_end, end);
+            let old = &piece[(from - key) as usize..(to - key) as usize];
+            let new = &data[(from - start) as usize..(to - start) as usize];
+            conflicting += old.iter().zip(new).filter(|&(a, b)| a != b).count() as u64;
+            if from > offset {
+                parts.push((offset, from));
+            }
+            offset = to;
+        }
+        if end > offset {
+            parts.push((offset, end));
+        }
+
+        for (from, to) in parts {
+            let len = (to - from) as usize;
+            while !self.pieces.is_empty()
+                && (self.len + len > SentBytes::MAX_LEN || !self.memory.fits(len))
+            {
+                self.forget_oldest();
+            }
+            if len > SentBytes::MAX_LEN || !self.memory.fits(len) {
+                continue;
+            }
+            let part = data[(from - start) as usize..(to - start) as usize].to_vec();
+            self.memory.charge(len);
+            self.len += len;
+            self.pieces.insert(from, part);
+        }
+        if end > self.end {
+            self.end_seq = self.end_seq.wrapping_add((end - self.end) as u32);
+            self.end = end;
+        }
+        return conflicting;
+    }
+
+    fn forget_oldest(&mut self) {
+        let key = *self.pieces.keys().next().unwrap();
+        let piece = self.pieces.remove(&key).unwrap();
+        self.memory.release(piece.len());
+        self.len -= piece.len();
+    }
+}
+
+impl Drop for SentBytes {
+    fn drop(&mut self) {
+        self.memory.release(self.len);
+    }
+}
+
+// counts retransmissions, reordering, zero windows, duplicate acks and
+// conflicting overlaps in the segments one side sends, whether or not its
+// payload is reassembled
+pub struct TCPAnalyzer {
+    // sequence number after the highest one sent so far
+    next_seq: Option<u32>,
+    // ranges below it not seen yet, oldest first
+    holes: Vec<(u32, u32)>,
+    last_ack: Option<u32>,
+    last_window: u16,
+    zero_window: bool,
+    sent: Option<SentBytes>,
+    memory: Arc<ReassemblyMemory>,
+    counters: TCPCounters,
+}
+
+impl TCPAnalyzer {
+    // holes past this are forgotten, the segments filling them count as retransmissions
+    const MAX_HOLES: usize = 16;
+
+    pub fn new(memory: Arc<ReassemblyMemory>) -> TCPAnalyzer {
+        TCPAnalyzer {
+            next_seq: None,
+            holes: Vec::new(),
+            last_ack: None,
+            last_window: 0,
+            zero_window: false,
+            sent: None,
+            memory,
+            counters: TCPCounters::default(),
+        }
+    }
+
+    pub fn counters(&self) -> &TCPCounters {
+        &self.counters
+    }
+
+    // returns the bytes of the payload that differ from an earlier copy
+    pub fn on_segment(&mut self, tcp: &TCPHeader, payload: &[u8]) -> u64 {
+        let flags = tcp.flags();
+        if flags & TCPHeader::RST > 0 {
+            return 0;
+        }
+        let conflicting = self.compare(tcp, payload);
+        if conflicting > 0 {
+            self.counters.conflicting_overlaps += 1;
+        }
+        self.count(tcp, payload.len());
+        return conflicting;
+    }
+
+    fn compare(&mut self, tcp: &TCPHeader, payload: &[u8]) -> u64 {
+        // the payload starts after the syn
+        let mut seq = tcp.seq();
+        if tcp.flags() & TCPHeader::SYN > 0 {
+            seq = seq.wrapping_add(1);
+        }
+        let memory = &self.memory;
+        let sent = self
+            .sent
+            .get_or_insert_with(|| SentBytes::new(seq, memory.clone()));
+        // a keep-alive repeats the last byte sent, mostly with garbage
+        if payload.is_empty() || payload.len() == 1 && seq.wrapping_add(1) == sent.end_seq {
+            return 0;
+        }
+        return sent.on_data(seq, payload);
+    }
+
+    fn count(&mut self, tcp: &TCPHeader, payload_len: usize) {
+        let flags = tcp.flags();
+
+        let window = tcp.window();
+        if window == 0 && !self.zero_window && flags & TCPHeader::SYN == 0 {
+            self.counters.zero_windows += 1;
+        }
+        self.zero_window = window == 0;
+
+        // syn and fin take a sequence number each
+        let mut len = payload_len as u32;
+        if flags & TCPHeader::SYN > 0 {
+            len += 1;
+        }
+        if flags & TCPHeader::FIN > 0 {
+            len += 1;
+        }
+
+        if flags & TCPHeader::ACK > 0 {
+            if len == 0 && self.last_ack == Some(tcp.ack()) && window == self.last_window {
+                self.counters.duplicate_acks += 1;
+            }
+            self.last_ack = Some(tcp.ack());
+            self.last_window = window;
+        }
+        if len == 0 {
+            return;
+        }
+
+        let seq = tcp.seq();
+        let end = seq.wrapping_add(len);
+        let next = match self.next_seq {
+            Some(next) => next,
+            None => {
+                self.next_seq = Some(end);
+                return;
+            }
+        };
+        if offset(seq, next) >= 0 {
+            if seq != next {
+                if self.holes.len() == TCPAnalyzer::MAX_HOLES {
+                    self.holes.remove(0);
+                }
+                self.holes.push((next, seq));
+            }
+            self.next_seq = Some(end);
+            return;
+        }
+
+        // a keep-alive, one byte below what was sent
+        if len == 1 && flags & TCPHeader::FIN == 0 && end == next {
+            return;
+        }
+        if self.fill_holes(seq, end, next) {
+            self.counters.out_of_order += 1;
+        } else {
+            self.counters.retransmissions += 1;
+        }
+        if offset(end, next) > 0 {
+            self.next_seq = Some(end);
+        }
+    }
+
+    // true when some of seq..end was never sent before
+    fn fill_holes(&mut self, seq: u32, end: u32, next: u32) -> bool {
+        let (start, stop) = (offset(seq, next), offset(end, next));
+        let mut filled = false;
+        let mut holes = Vec::with_capacity(self.holes.len());
+        for &(from, to) in self.holes.iter() {
+            if offset(to, next) <= start || offset(from, next) >= stop {
+                holes.push((from, to));
+                continue;
+            }
+            filled = true;
+            if offset(from, next) < start {
+                holes.push((from, seq));
+            }
+            if offset(to, next) > stop {
+                holes.push((end, to));
+            }
+        }
+        self.holes = holes;
+        return filled;
+    }
+}
@@ -0,0 +1,215 @@
+use crate::layer::tcp::TCPHeader;
+
+// where a connection is, as seen from the wire between the two ends
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TCPState {
+    // nothing seen yet
+    Listen,
+    SynSent,
+    SynReceived,
+    Established,
+    // one side sent its fin, the other may still send data
+    ClientHalfClosed,
+    ServerHalfClosed,
+    // both fins sent, not both acknowledged
+    Closing,
+    // both fins acknowledged, late retransmissions still belong to the connection
+    TimeWait,
+    Closed,
+}
+
+impl TCPState {
+    pub fn to_string(self) -> &'static str {
+        match self {
+            TCPState::Listen => "listen",
+            TCPState::SynSent => "syn_sent",
+            TCPState::SynReceived => "syn_received",
+            TCPState::Established => "established",
+            TCPState::ClientHalfClosed => "client_half_closed",
+            TCPState::ServerHalfClosed => "server_half_closed",
+            TCPState::Closing => "closing",
+            TCPState::TimeWait => "time_wait",
+            TCPState::Closed => "closed",
+        }
+    }
+}
+
+// how a connection ended
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TCPOutcome {
+    // the server answered the syn with a rst
+    Refused,
+    Reset,
+    // fins from both sides
+    Closed,
+    // went idle before it ended
+    Timeout,
+}
+
+impl TCPOutcome {
+    pub fn to_string(self) -> &'static str {
+        match self {
+            TCPOutcome::Refused => "refused",
+            TCPOutcome::Reset => "reset",
+            TCPOutcome::Closed => "closed",
+            TCPOutcome::Timeout => "timeout",
+        }
+    }
+}
+
+// a ≥ b in sequence space
+#[inline]
+fn seq_ge(a: u32, b: u32) -> bool {
+    a.wrapping_sub(b) < 1 << 31
+}
+
+// the state machine of one connection, fed with the segments of both directions
+pub struct TCPConnection {
+    state: TCPState,
+    outcome: Option<TCPOutcome>,
+    // sequence number of each fin, and whether the other side acknowledged it
+    client_fin: Option<u32>,
+    server_fin: Option<u32>,
+    client_fin_acked: bool,
+    server_fin_acked: bool,
+    //nano second
+    time_wait_start: u64,
+}
+
+impl TCPConnection {
+    //nano second
+    pub const TIME_WAIT: u64 = 1000 * 1000 * 1000 * 10;
+
+    pub fn new() -> TCPConnection {
+        TCPConnection {
+            state: TCPState::Listen,
+            outcome: None,
+            client_fin: None,
+            server_fin: None,
+            client_fin_acked: false,
+            server_fin_acked: false,
+            time_wait_start: 0,
+        }
+    }
+
+    pub fn state(&self) -> TCPState {
+        self.state
+    }
+
+    pub fn outcome(&self) -> Option<TCPOutcome> {
+        self.outcome
+    }
+
+    // no segment is expected any more
+    pub fn is_closed(&self) -> bool {
+        self.state == TCPState::Closed
+    }
+
+    // closed, or done waiting for late segments
+    pub fn is_finished(&self, now: u64) -> bool {
+        match self.state {
+            TCPState::Closed => true,
+            TCPState::TimeWait => self.time_wait_start + TCPConnection::TIME_WAIT <= now,
+            _ => false,
+        }
+    }
+
+    // a new syn on the same addresses and ports starts another connection
+    pub fn is_reusable(&self) -> bool {
+        self.state == TCPState::TimeWait || self.state == TCPState::Closed
+    }
+
+    // the tracker gave up on an idle connection
+    pub fn on_timeout(&mut self) {
+        if self.outcome.is_none() {
+            self.outcome = Some(TCPOutcome::Timeout);
+        }
+        self.state = TCPState::Closed;
+    }
+
+    pub fn on_segment(&mut self, from_client: bool, tcp: &TCPHeader, payload_len: usize, now: u64) {
+        if self.state == TCPState::Closed {
+            return;
+        }
+        let flags = tcp.flags();
+
+        if flags & TCPHeader::RST > 0 {
+            let refused = !from_client
+                && (self.state == TCPState::SynSent || self.state == TCPState::SynReceived);
+            if self.outcome.is_none() {
+                self.outcome = Some(if refused {
+                    TCPOutcome::Refused
+                } else {
+                    TCPOutcome::Reset
+                });
+            }
+            self.state = TCPState::Closed;
+            return;
+        }
+
+        if flags & TCPHeader::SYN > 0 {
+            if from_client && self.state == TCPState::Listen {
+                self.state = TCPState::SynSent;
+            } else if !from_client && flags & TCPHeader::ACK > 0 && self.state == TCPState::SynSent
+            {
+                self.state = TCPState::SynReceived;
+            }
+        } else if self.state == TCPState::SynReceived && from_client
+            || self.state == TCPState::SynSent && payload_len > 0
+        {
+            // the ack completing the handshake, or data when it was missed
+            self.state = TCPState::Established;
+        } else if self.state == TCPState::Listen {
+            self.state = TCPState::Established;
+        }
+
+        if flags & TCPHeader::FIN > 0 {
+            let fin = tcp.seq().wrapping_add(payload_len as u32);
+            if from_client {
+                self.client_fin.get_or_insert(fin);
+            } else {
+                self.server_fin.get_or_insert(fin);
+            }
+        }
+
+        // the fin sent the other way consumed one sequence number
+        if flags & TCPHeader::ACK > 0 {
+            let fin = if from_client {
+                self.server_fin
+            } else {
+                self.client_fin
+            };
+            if let Some(fin) = fin {
+                if seq_ge(tcp.ack(), fin.wrapping_add(1)) {
+                    if from_client {
+                        self.server_fin_acked = true;
+                    } else {
+                        self.client_fin_acked = true;
+                    }
+                }
+            }
+        }
+
+        self.update_closing(now);
+    }
+
+    fn update_closing(&mut self, now: u64) {
+        if self.state == TCPState::TimeWait {
+            return;
+        }
+        match (self.client_fin, self.server_fin) {
+            (Some(_), Some(_)) => {
+                self.outcome = Some(TCPOutcome::Closed);
+                if self.client_fin_acked && self.server_fin_acked {
+                    self.state = TCPState::TimeWait;
+                    self.time_wait_start = now;
+                } else {
+                    self.state = TCPState::Closing;
+                }
+            }
+            (Some(_), None) => self.state = TCPState::ClientHalfClosed,
+            (None, Some(_)) => self.state = TCPState::ServerHalfClosed,
+            (None, None) => {}
+        }
+    }
+}
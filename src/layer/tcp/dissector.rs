@@ -7,9 +7,28 @@ use std::collections::HashMap;
 use std::rc::Rc;
 use std::sync::Arc;
 
+// the side of a stream that sent some data
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Client,
+    Server,
+}
+
+impl Direction {
+    pub fn to_string(self) -> &'static str {
+        match self {
+            Direction::Client => "client",
+            Direction::Server => "server",
+        }
+    }
+}
+
 pub trait TCPDissector {
     fn on_client_data(&mut self, data: &[u8]) -> Result<(), ()>;
     fn on_server_data(&mut self, data: &[u8]) -> Result<(), ()>;
+    // len bytes sent by one side were lost and the data that follows comes after
+    // them; an error gives up on the stream like a parse error does
+    fn on_gap(&mut self, direction: Direction, len: u64) -> Result<(), ()>;
 }
 
 pub struct DefaultDissector {}
@@ -27,6 +46,9 @@ impl TCPDissector for DefaultDissector {
     fn on_server_data(&mut self, _data: &[u8]) -> Result<(), ()> {
         Err(())
     }
+    fn on_gap(&mut self, _direction: Direction, _len: u64) -> Result<(), ()> {
+        Err(())
+    }
 }
 
 type DissectorAllocateCallback = Fn(Rc<Detector>, *const c_char) -> Rc<RefCell<TCPDissector>>;
@@ -1,5 +1,5 @@
 use crate::detector::Detector;
-use crate::layer::TCPDissector;
+use crate::layer::tcp::{Direction, TCPDissector};
 use config::Configure;
 use files;
 use gmime_sys;
@@ -335,6 +335,39 @@ extern "C" fn on_response_message_complete(parser: *const Parser) -> i32 {
     0
 }
 
+// the methods a request line may start with, to find one again after a gap
+const METHODS: [&[u8]; 9] = [
+    b"GET ",
+    b"POST ",
+    b"HEAD ",
+    b"PUT ",
+    b"DELETE ",
+    b"OPTIONS ",
+    b"CONNECT ",
+    b"PATCH ",
+    b"TRACE ",
+];
+
+// offset of the first line of data that starts a request, or a response from the
+// server; a line split over two chunks is missed
+pub fn message_start(data: &[u8], direction: Direction) -> Option<usize> {
+    let mut offset = 0;
+    loop {
+        let line = &data[offset..];
+        let found = match direction {
+            Direction::Client => METHODS.iter().any(|method| line.starts_with(method)),
+            Direction::Server => line.starts_with(b"HTTP/"),
+        };
+        if found {
+            return Some(offset);
+        }
+        match line.iter().position(|&c| c == b'\n') {
+            Some(end) => offset += end + 1,
+            None => return None,
+        }
+    }
+}
+
 pub struct HTTPDissector {
     url: String,
     parse_request: bool,
@@ -349,6 +382,9 @@ pub struct HTTPDissector {
     request_parser: *const Parser,
     response_parser: *const Parser,
     response_stream: *mut gmime_sys::GMimeStream,
+    // a gap cut the message short, data is dropped until the next one starts
+    request_lost: bool,
+    response_lost: bool,
 }
 
 impl HTTPDissector {
@@ -369,6 +405,8 @@ impl HTTPDissector {
             request_parser: ptr::null(),
             response_parser: ptr::null(),
             response_stream: ptr::null_mut() as *mut gmime_sys::GMimeStream,
+            request_lost: false,
+            response_lost: false,
         }));
 
         let this = http.as_ptr() as *const c_char;
@@ -388,6 +426,25 @@ impl HTTPDissector {
         return http;
     }
 
+    // the data from the start of the next message on, None while it is not there
+    fn resync<'a>(&mut self, direction: Direction, data: &'a [u8]) -> Option<&'a [u8]> {
+        let lost = match direction {
+            Direction::Client => &mut self.request_lost,
+            Direction::Server => &mut self.response_lost,
+        };
+        if !*lost {
+            return Some(data);
+        }
+        let start = message_start(data, direction)?;
+        *lost = false;
+        debug!(
+            "http {} resync, {} bytes dropped",
+            direction.to_string(),
+            start
+        );
+        return Some(&data[start..]);
+    }
+
     fn parse_stream(&mut self, stream: *mut gmime_sys::GMimeStream) {
         unsafe {
             gmime_sys::g_mime_stream_seek(stream, 0, 0);
@@ -456,6 +513,10 @@ impl Drop for HTTPDissector {
 
 impl TCPDissector for HTTPDissector {
     fn on_client_data(&mut self, data: &[u8]) -> Result<(), ()> {
+        let data = match self.resync(Direction::Client, data) {
+            Some(data) => data,
+            None => return Ok(()),
+        };
         unsafe {
             let n = http_parser_execute(
                 self.request_parser,
@@ -477,6 +538,10 @@ impl TCPDissector for HTTPDissector {
         }
     }
     fn on_server_data(&mut self, data: &[u8]) -> Result<(), ()> {
+        let data = match self.resync(Direction::Server, data) {
+            Some(data) => data,
+            None => return Ok(()),
+        };
         unsafe {
             let n = http_parser_execute(
                 self.response_parser,
@@ -500,4 +565,27 @@ impl TCPDissector for HTTPDissector {
             }
         }
     }
+
+    // the message in progress is abandoned, parsing starts again at the next one
+    fn on_gap(&mut self, direction: Direction, len: u64) -> Result<(), ()> {
+        debug!("http {} gap {}", direction.to_string(), len);
+        unsafe {
+            match direction {
+                Direction::Client => {
+                    http_parser_init(self.request_parser as *mut Parser, HttpParserType::Request);
+                    self.parse_request = false;
+                    self.request_lost = true;
+                }
+                Direction::Server => {
+                    http_parser_init(
+                        self.response_parser as *mut Parser,
+                        HttpParserType::Response,
+                    );
+                    self.parse_response = false;
+                    self.response_lost = true;
+                }
+            }
+        }
+        Ok(())
+    }
 }
@@ -1,40 +1,33 @@
+use crate::inet;
+use std::cmp;
+
+pub mod ack_queue;
+pub mod analysis;
+pub mod connection;
 pub mod dissector;
 pub mod http;
+pub mod options;
+pub mod reassembly;
+pub mod rtt;
 
+pub use self::ack_queue::AckQueue;
+pub use self::analysis::*;
+pub use self::connection::*;
 pub use self::dissector::*;
 pub use self::http::HTTPDissector;
+pub use self::options::*;
+pub use self::reassembly::*;
+pub use self::rtt::RttEstimator;
 
-#[repr(C, packed)]
-pub struct TCPHeader {
-    //source port
-    pub src_port: u16,
-
-    // destination port
-    pub dst_port: u16,
-
-    // sequence number
-    pub seq: u32,
-
-    // acknowledgement number
-    pub ack: u32,
-
-    // offset and reserved
-    pub off: u8,
-
-    //flags
-    pub flags: u8,
-
-    //pkt window
-    pub win: u16,
-
-    //checksum
-    pub checksum: u16,
-
-    //urgent pointer
-    pub urp: u16,
+// the fixed tcp header, options follow it up to header_len
+#[derive(Clone, Copy)]
+pub struct TCPHeader<'a> {
+    data: &'a [u8],
 }
 
-impl TCPHeader {
+impl<'a> TCPHeader<'a> {
+    pub const LEN: usize = 20;
+
     pub const FIN: u8 = 0x01;
     pub const SYN: u8 = 0x02;
     pub const RST: u8 = 0x04;
@@ -42,7 +35,63 @@ impl TCPHeader {
     pub const ACK: u8 = 0x10;
     pub const URG: u8 = 0x20;
 
+    // None when the slice cannot hold the fixed header
+    pub fn new(data: &'a [u8]) -> Option<TCPHeader<'a>> {
+        if data.len() < TCPHeader::LEN {
+            return None;
+        }
+        Some(TCPHeader { data })
+    }
+
+    #[inline]
+    pub fn src_port(&self) -> u16 {
+        return inet::read_u16(self.data, 0);
+    }
+
+    #[inline]
+    pub fn dst_port(&self) -> u16 {
+        return inet::read_u16(self.data, 2);
+    }
+
+    #[inline]
+    pub fn seq(&self) -> u32 {
+        return inet::read_u32(self.data, 4);
+    }
+
+    #[inline]
+    pub fn ack(&self) -> u32 {
+        return inet::read_u32(self.data, 8);
+    }
+
+    #[inline]
     pub fn header_len(&self) -> u8 {
-        return (self.off & 0xf0) >> 2;
+        return (self.data[12] & 0xf0) >> 2;
+    }
+
+    #[inline]
+    pub fn flags(&self) -> u8 {
+        return self.data[13];
+    }
+
+    #[inline]
+    pub fn window(&self) -> u16 {
+        return inet::read_u16(self.data, 14);
+    }
+
+    #[inline]
+    pub fn checksum(&self) -> u16 {
+        return inet::read_u16(self.data, 16);
+    }
+
+    #[inline]
+    pub fn urgent(&self) -> u16 {
+        return inet::read_u16(self.data, 18);
+    }
+
+    // the option bytes between the fixed header and header_len
+    pub fn options(&self) -> TCPOptionIter<'a> {
+        let end = cmp::min(self.header_len() as usize, self.data.len());
+        let start = cmp::min(TCPHeader::LEN, end);
+        return TCPOptionIter::new(&self.data[start..end]);
     }
 }
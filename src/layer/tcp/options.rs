@@ -0,0 +1,268 @@
+use crate::inet;
+use crate::layer::packet::Packet;
+use crate::layer::tcp::TCPHeader;
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TCPOptionKind(pub u8);
+
+impl TCPOptionKind {
+    pub const EOL: TCPOptionKind = TCPOptionKind(0);
+    pub const NOP: TCPOptionKind = TCPOptionKind(1);
+    pub const MSS: TCPOptionKind = TCPOptionKind(2);
+    pub const WINDOW_SCALE: TCPOptionKind = TCPOptionKind(3);
+    pub const SACK_PERMITTED: TCPOptionKind = TCPOptionKind(4);
+    pub const SACK: TCPOptionKind = TCPOptionKind(5);
+    pub const TIMESTAMP: TCPOptionKind = TCPOptionKind(8);
+
+    // the names p0f uses in its option layouts
+    pub fn to_string(self) -> &'static str {
+        match self {
+            TCPOptionKind::EOL => "eol",
+            TCPOptionKind::NOP => "nop",
+            TCPOptionKind::MSS => "mss",
+            TCPOptionKind::WINDOW_SCALE => "ws",
+            TCPOptionKind::SACK_PERMITTED => "sok",
+            TCPOptionKind::SACK => "sack",
+            TCPOptionKind::TIMESTAMP => "ts",
+            _ => "?",
+        }
+    }
+}
+
+// left and right edges of the blocks of a sack option
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SackBlocks<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Iterator for SackBlocks<'a> {
+    type Item = (u32, u32);
+
+    fn next(&mut self) -> Option<(u32, u32)> {
+        if self.data.len() < 8 {
+            return None;
+        }
+        let block = (inet::read_u32(self.data, 0), inet::read_u32(self.data, 4));
+        self.data = &self.data[8..];
+        return Some(block);
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TCPOption<'a> {
+    EndOfList,
+    Nop,
+    MSS(u16),
+    // shift count
+    WindowScale(u8),
+    SackPermitted,
+    Sack(SackBlocks<'a>),
+    Timestamp { value: u32, echo: u32 },
+    // unknown kinds, or known ones with the wrong length
+    Other(TCPOptionKind, &'a [u8]),
+}
+
+impl<'a> TCPOption<'a> {
+    pub fn kind(&self) -> TCPOptionKind {
+        match *self {
+            TCPOption::EndOfList => TCPOptionKind::EOL,
+            TCPOption::Nop => TCPOptionKind::NOP,
+            TCPOption::MSS(_) => TCPOptionKind::MSS,
+            TCPOption::WindowScale(_) => TCPOptionKind::WINDOW_SCALE,
+            TCPOption::SackPermitted => TCPOptionKind::SACK_PERMITTED,
+            TCPOption::Sack(_) => TCPOptionKind::SACK,
+            TCPOption::Timestamp { .. } => TCPOptionKind::TIMESTAMP,
+            TCPOption::Other(kind, _) => kind,
+        }
+    }
+}
+
+// walks the option list; stops at the end of list option, or at a length that
+// is below 2 or runs past the header, which marks the list malformed
+#[derive(Clone, Copy)]
+pub struct TCPOptionIter<'a> {
+    data: &'a [u8],
+    malformed: bool,
+}
+
+impl<'a> TCPOptionIter<'a> {
+    pub fn new(data: &'a [u8]) -> TCPOptionIter<'a> {
+        TCPOptionIter {
+            data,
+            malformed: false,
+        }
+    }
+
+    pub fn malformed(&self) -> bool {
+        self.malformed
+    }
+}
+
+impl<'a> Iterator for TCPOptionIter<'a> {
+    type Item = TCPOption<'a>;
+
+    fn next(&mut self) -> Option<TCPOption<'a>> {
+        if self.data.is_empty() {
+            return None;
+        }
+        let kind = TCPOptionKind(self.data[0]);
+        match kind {
+            // the rest is padding
+            TCPOptionKind::EOL => {
+                self.data = &[];
+                return Some(TCPOption::EndOfList);
+            }
+            TCPOptionKind::NOP => {
+                self.data = &self.data[1..];
+                return Some(TCPOption::Nop);
+            }
+            _ => {}
+        }
+
+        let len = if self.data.len() >= 2 {
+            self.data[1] as usize
+        } else {
+            0
+        };
+        if len < 2 || len > self.data.len() {
+            debug!("bad tcp option {} {} {}", kind.0, len, self.data.len());
+            self.malformed = true;
+            self.data = &[];
+            return None;
+        }
+        let body = &self.data[2..len];
+        self.data = &self.data[len..];
+
+        let option = match (kind, body.len()) {
+            (TCPOptionKind::MSS, 2) => TCPOption::MSS(inet::read_u16(body, 0)),
+            (TCPOptionKind::WINDOW_SCALE, 1) => TCPOption::WindowScale(body[0]),
+            (TCPOptionKind::SACK_PERMITTED, 0) => TCPOption::SackPermitted,
+            (TCPOptionKind::SACK, n) if n > 0 && n % 8 == 0 => {
+                TCPOption::Sack(SackBlocks { data: body })
+            }
+            (TCPOptionKind::TIMESTAMP, 8) => TCPOption::Timestamp {
+                value: inet::read_u32(body, 0),
+                echo: inet::read_u32(body, 4),
+            },
+            _ => TCPOption::Other(kind, body),
+        };
+        return Some(option);
+    }
+}
+
+// the options of one segment the trackers care about
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TCPOptions {
+    pub mss: Option<u16>,
+    pub window_scale: Option<u8>,
+    pub sack_permitted: bool,
+    // value and echo reply
+    pub timestamp: Option<(u32, u32)>,
+    pub malformed: bool,
+}
+
+impl TCPOptions {
+    pub fn parse(tcp: &TCPHeader) -> TCPOptions {
+        let mut options = TCPOptions::default();
+        let mut iter = tcp.options();
+        for option in &mut iter {
+            match option {
+                TCPOption::MSS(mss) => options.mss = Some(mss),
+                // rfc 7323 caps the shift at 14
+                TCPOption::WindowScale(shift) => options.window_scale = Some(shift.min(14)),
+                TCPOption::SackPermitted => options.sack_permitted = true,
+                TCPOption::Timestamp { value, echo } => options.timestamp = Some((value, echo)),
+                _ => {}
+            }
+        }
+        options.malformed = iter.malformed();
+        return options;
+    }
+}
+
+// what a syn or syn-ack gives away about the stack that sent it, laid out like a
+// p0f signature: version:ttl:mss:window,scale:layout:quirks
+#[derive(Clone, Debug, PartialEq)]
+pub struct SynFingerprint {
+    pub version: u8,
+    // the ttl seen, initial_ttl guesses what it started from
+    pub ttl: u8,
+    pub mss: Option<u16>,
+    pub window: u16,
+    pub window_scale: Option<u8>,
+    // option kinds in wire order
+    pub layout: Vec<TCPOptionKind>,
+    pub dont_fragment: bool,
+    // a timestamp option whose value is 0
+    pub zero_timestamp: bool,
+}
+
+impl SynFingerprint {
+    // None unless the packet is a tcp syn
+    pub fn from_packet(packet: &Packet) -> Option<SynFingerprint> {
+        let tcp = packet.tcp()?;
+        if tcp.flags() & TCPHeader::SYN == 0 {
+            return None;
+        }
+        let (version, ttl, dont_fragment) = match packet.ipv4() {
+            Some(ip) => (4, ip.ttl(), ip.dont_fragment()),
+            None => (6, packet.ipv6()?.hop_limit(), false),
+        };
+
+        let mut fingerprint = SynFingerprint {
+            version,
+            ttl,
+            mss: None,
+            window: tcp.window(),
+            window_scale: None,
+            layout: Vec::new(),
+            dont_fragment,
+            zero_timestamp: false,
+        };
+        for option in tcp.options() {
+            match option {
+                TCPOption::MSS(mss) => fingerprint.mss = Some(mss),
+                TCPOption::WindowScale(shift) => fingerprint.window_scale = Some(shift),
+                TCPOption::Timestamp { value, .. } => fingerprint.zero_timestamp = value == 0,
+                _ => {}
+            }
+            fingerprint.layout.push(option.kind());
+        }
+        return Some(fingerprint);
+    }
+
+    // the common initial values are 32, 64, 128 and 255
+    pub fn initial_ttl(&self) -> u8 {
+        match self.ttl {
+            0..=32 => 32,
+            33..=64 => 64,
+            65..=128 => 128,
+            _ => 255,
+        }
+    }
+}
+
+impl fmt::Display for SynFingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let layout: Vec<&str> = self.layout.iter().map(|kind| kind.to_string()).collect();
+        let mut quirks = Vec::new();
+        if self.dont_fragment {
+            quirks.push("df");
+        }
+        if self.zero_timestamp {
+            quirks.push("ts1-");
+        }
+        write!(f, "{}:{}:", self.version, self.initial_ttl())?;
+        match self.mss {
+            Some(mss) => write!(f, "{}:", mss)?,
+            None => write!(f, "*:")?,
+        }
+        write!(f, "{},", self.window)?;
+        match self.window_scale {
+            Some(shift) => write!(f, "{}", shift)?,
+            None => write!(f, "*")?,
+        }
+        write!(f, ":{}:{}", layout.join(","), quirks.join(","))
+    }
+}
@@ -0,0 +1,294 @@
+use crate::config::StreamConfig;
+use crate::layer::defrag::OverlapPolicy;
+use std::cmp;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+// what a reassembler hands on, in stream order
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Reassembled<'a> {
+    Data(&'a [u8]),
+    // bytes that never arrived and were given up on, the data after them follows
+    Gap(u64),
+}
+
+// bytes held by all the reassemblers and ack queues sharing it, the limit is
+// checked before buffering so it can be overrun by one segment per thread
+pub struct ReassemblyMemory {
+    limit: AtomicUsize,
+    used: AtomicUsize,
+}
+
+lazy_static! {
+    static ref GLOBAL: Arc<ReassemblyMemory> =
+        Arc::new(ReassemblyMemory::new(StreamConfig::new().memory_limit));
+}
+
+impl ReassemblyMemory {
+    pub fn new(limit: usize) -> ReassemblyMemory {
+        ReassemblyMemory {
+            limit: AtomicUsize::new(limit),
+            used: AtomicUsize::new(0),
+        }
+    }
+
+    // shared by the tcp streams of all the workers
+    pub fn global() -> Arc<ReassemblyMemory> {
+        GLOBAL.clone()
+    }
+
+    pub fn set_limit(&self, limit: usize) {
+        self.limit.store(limit, Ordering::Relaxed);
+    }
+
+    pub fn used(&self) -> usize {
+        self.used.load(Ordering::Relaxed)
+    }
+
+    pub fn fits(&self, len: usize) -> bool {
+        self.used() + len <= self.limit.load(Ordering::Relaxed)
+    }
+
+    pub fn charge(&self, len: usize) {
+        self.used.fetch_add(len, Ordering::Relaxed);
+    }
+
+    pub fn release(&self, len: usize) {
+        self.used.fetch_sub(len, Ordering::Relaxed);
+    }
+}
+
+struct Piece {
+    data: Vec<u8>,
+    // start and end of the segment the bytes came with, for the overlap policy
+    owner: (u64, u64),
+}
+
+// puts the payload of one direction of a tcp stream back in order; positions
+// are offsets from where it started, so sequence numbers may wrap
+pub struct Reassembler {
+    // the next byte to hand on, as a sequence number and as an offset
+    next_seq: u32,
+    next: u64,
+    // out of order pieces by offset, never overlapping each other
+    pending: BTreeMap<u64, Piece>,
+    buffered: usize,
+
+    policy: OverlapPolicy,
+    flow_memory_limit: usize,
+    memory: Arc<ReassemblyMemory>,
+    //nano second
+    gap_timeout: u64,
+    // since when the hole in front of the pieces is open
+    waiting_since: Option<u64>,
+}
+
+impl Reassembler {
+    // seq is the first byte expected
+    pub fn new(seq: u32, config: &StreamConfig, memory: Arc<ReassemblyMemory>) -> Reassembler {
+        Reassembler {
+            next_seq: seq,
+            next: 0,
+            pending: BTreeMap::new(),
+            buffered: 0,
+            policy: config.overlap_policy,
+            flow_memory_limit: config.flow_memory_limit,
+            memory,
+            gap_timeout: config.gap_timeout * 1000 * 1000 * 1000,
+            waiting_since: None,
+        }
+    }
+
+    pub fn next_seq(&self) -> u32 {
+        self.next_seq
+    }
+
+    // bytes held out of order
+    pub fn buffered(&self) -> usize {
+        self.buffered
+    }
+
+    // a syn seen late, after a mid-stream start, moves the start before anything
+    // was handed on or buffered
+    pub fn on_syn(&mut self, seq: u32) {
+        if self.next == 0 && self.pending.is_empty() {
+            self.next_seq = seq.wrapping_add(1);
+        }
+    }
+
+    // now is the time of the segment, in nano second
+    pub fn insert(&mut self, seq: u32, data: &[u8], now: u64, deliver: &mut FnMut(Reassembled)) {
+        let before = self.next;
+        self.put_segment(seq, data, deliver);
+
+        // a hole opened, or moved after the data handed on
+        if self.pending.is_empty() {
+            self.waiting_since = None;
+        } else if self.next != before || self.waiting_since.is_none() {
+            self.waiting_since = Some(now);
+        }
+        self.expire(now, deliver);
+    }
+
+    // gives up on the hole in front of the pieces once it stayed open for the gap
+    // timeout, the segments filling it are taken to be lost before the sensor
+    pub fn expire(&mut self, now: u64, deliver: &mut FnMut(Reassembled)) {
+        let since = match self.waiting_since {
+            Some(since) => since,
+            None => return,
+        };
+        if since + self.gap_timeout > now {
+            return;
+        }
+        if let Some(&key) = self.pending.keys().next() {
+            self.skip_to(key, deliver);
+        }
+        self.waiting_since = if self.pending.is_empty() {
+            None
+        } else {
+            Some(now)
+        };
+    }
+
+    // the stream is over: hand on the pieces left, the holes between them as gaps
+    pub fn flush(&mut self, deliver: &mut FnMut(Reassembled)) {
+        while let Some(&key) = self.pending.keys().next() {
+            self.skip_to(key, deliver);
+        }
+        self.waiting_since = None;
+    }
+
+    fn put_segment(&mut self, seq: u32, data: &[u8], deliver: &mut FnMut(Reassembled)) {
+        let mut start = self.next as i64 + i64::from(seq.wrapping_sub(self.next_seq) as i32);
+        let mut data = data;
+        loop {
+            // what was handed on already, retransmissions mostly
+            let next = self.next as i64;
+            if start + data.len() as i64 <= next {
+                return;
+            }
+            if start < next {
+                data = &data[(next - start) as usize..];
+                start = next;
+            }
+            if start as u64 == self.next || self.fits(data.len()) {
+                break;
+            }
+            // over a memory limit: give up on the first hole, the one before the
+            // oldest piece or the one before this segment
+            let hole_end = match self.pending.keys().next() {
+                Some(&key) if key < start as u64 => key,
+                _ => start as u64,
+            };
+            self.skip_to(hole_end, deliver);
+        }
+
+        let start = start as u64;
+        let end = start + data.len() as u64;
+        if start == self.next && self.pending.range(..end).next().is_none() {
+            // in order and clear of the pieces, nothing to copy
+            self.hand_on(data, deliver);
+        } else {
+            self.store(start, data);
+        }
+        self.deliver_pending(deliver);
+    }
+
+    fn fits(&self, len: usize) -> bool {
+        self.buffered + len <= self.flow_memory_limit && self.memory.fits(len)
+    }
+
+    fn store(&mut self, start: u64, data: &[u8]) {
+        let end = start + data.len() as u64;
+        let owner = (start, end);
+
+        // pieces never overlap each other, the first one ending before the
+        // segment ends the walk
+        let overlapping: Vec<u64> = self
+            .pending
+            .range(..end)
+            .rev()
+            .take_while(|&(&key, ref piece)| key + piece.data.len() as u64 > start)
+            .map(|(&key, _)| key)
+            .collect();
+
+        // parts of the segment kept by older pieces
+        let mut kept = Vec::new();
+        for key in overlapping {
+            let piece = self.take(key);
+            let piece_end = key + piece.data.len() as u64;
+            let from = cmp::max(key, start);
+            let to = cmp::min(piece_end, end);
+
+            if self.policy.new_wins(owner, piece.owner) {
+                if key < start {
+                    let head = piece.data[..(start - key) as usize].to_vec();
+                    self.put(key, head, piece.owner);
+                }
+                if piece_end > end {
+                    let tail = piece.data[(end - key) as usize..].to_vec();
+                    self.put(end, tail, piece.owner);
+                }
+            } else {
+                kept.push((from, to));
+                self.put(key, piece.data, piece.owner);
+            }
+        }
+
+        // the rest of the segment fills the space around them
+        kept.sort();
+        let mut offset = start;
+        for &(from, to) in kept.iter().chain([(end, end)].iter()) {
+            if from > offset {
+                let part = data[(offset - start) as usize..(from - start) as usize].to_vec();
+                self.put(offset, part, owner);
+            }
+            offset = to;
+        }
+    }
+
+    fn put(&mut self, key: u64, data: Vec<u8>, owner: (u64, u64)) {
+        self.buffered += data.len();
+        self.memory.charge(data.len());
+        self.pending.insert(key, Piece { data, owner });
+    }
+
+    fn take(&mut self, key: u64) -> Piece {
+        let piece = self.pending.remove(&key).unwrap();
+        self.buffered -= piece.data.len();
+        self.memory.release(piece.data.len());
+        return piece;
+    }
+
+    fn hand_on(&mut self, data: &[u8], deliver: &mut FnMut(Reassembled)) {
+        self.next += data.len() as u64;
+        self.next_seq = self.next_seq.wrapping_add(data.len() as u32);
+        deliver(Reassembled::Data(data));
+    }
+
+    fn deliver_pending(&mut self, deliver: &mut FnMut(Reassembled)) {
+        while let Some(&key) = self.pending.keys().next() {
+            if key != self.next {
+                break;
+            }
+            let piece = self.take(key);
+            self.hand_on(&piece.data, deliver);
+        }
+    }
+
+    fn skip_to(&mut self, offset: u64, deliver: &mut FnMut(Reassembled)) {
+        let len = offset - self.next;
+        trace!("tcp gap {} at {}", len, self.next_seq);
+        self.next = offset;
+        self.next_seq = self.next_seq.wrapping_add(len as u32);
+        deliver(Reassembled::Gap(len));
+        self.deliver_pending(deliver);
+    }
+}
+
+impl Drop for Reassembler {
+    fn drop(&mut self) {
+        self.memory.release(self.buffered);
+    }
+}
@@ -0,0 +1,57 @@
+// round trip from the sensor to one end of a stream and back, seen passively:
+// a timestamp value sent towards that end and the first segment echoing it, or
+// the handshake; samples are smoothed like rfc 6298
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RttEstimator {
+    // value sent towards the end being measured and when it passed the sensor
+    pending: Option<(u32, u64)>,
+    //nano second
+    srtt: Option<u64>,
+    samples: u64,
+}
+
+impl RttEstimator {
+    pub fn new() -> RttEstimator {
+        RttEstimator::default()
+    }
+
+    // a timestamp value heading to the end being measured, the first time it is seen
+    pub fn on_value(&mut self, value: u32, now: u64) {
+        match self.pending {
+            Some((pending, _)) if pending == value => {}
+            // an unanswered value is kept, retransmissions would shorten the sample
+            Some(_) => {}
+            None => self.pending = Some((value, now)),
+        }
+    }
+
+    // an echo coming back from it
+    pub fn on_echo(&mut self, echo: u32, now: u64) {
+        if let Some((value, sent)) = self.pending {
+            if value == echo {
+                self.pending = None;
+                self.sample(now.saturating_sub(sent));
+            } else if echo.wrapping_sub(value) < 1 << 31 {
+                // a later value came back, ours was never echoed
+                self.pending = None;
+            }
+        }
+    }
+
+    pub fn sample(&mut self, rtt: u64) {
+        self.samples += 1;
+        self.srtt = Some(match self.srtt {
+            Some(srtt) => srtt - srtt / 8 + rtt / 8,
+            None => rtt,
+        });
+    }
+
+    // smoothed, nano second
+    pub fn rtt(&self) -> Option<u64> {
+        self.srtt
+    }
+
+    pub fn samples(&self) -> u64 {
+        self.samples
+    }
+}
@@ -1,86 +1,95 @@
-use crate::inet;
+use config::StreamConfig;
 use layer::packet::Packet;
-use layer::tcp::TCPHeader;
-use libc::c_char;
-use std::mem;
-use std::slice;
+use layer::tcp::{AckQueue, Reassembled, Reassembler, ReassemblyMemory, TCPHeader};
 use std::sync::Arc;
 
-#[link(name = "layerscpp")]
-extern "C" {
-    fn new_tcp_data_tracker(_seq: u32) -> *const c_char;
-    fn tcp_data_tracker_set_callback(
-        _tracker: *const c_char,
-        flow: *const c_char,
-        _cb: extern "C" fn(*const c_char, *const c_char, u32),
-    );
-
-    fn tcp_data_tracker_update_seq(_tracker: *const c_char, _seq: u32);
-    fn tcp_data_tracker_process_data(
-        _tracker: *const c_char,
-        _seq: u32,
-        _data: *const c_char,
-        _len: u32,
-    );
-    fn free_tcp_data_tracker(_tracker: *const c_char);
-}
-
-type DataCallback = Fn(&[u8]);
+type DataCallback = Fn(Reassembled);
 
+// one direction of a tcp stream, its payload handed on in order
 pub struct TcpFlow {
     on_data_callback: Box<DataCallback>,
-    tracker_: *const c_char,
-}
-
-extern "C" fn on_data_callback(flow: *const c_char, data: *const c_char, len: u32) {
-    unsafe {
-        let payload = slice::from_raw_parts(data as *const u8, len as usize);
-        let flow = mem::transmute::<*const c_char, *const TcpFlow>(flow);
-        (*(*flow).on_data_callback)(payload);
-    }
+    reassembler: Reassembler,
+    // with ack ordering, what the other side has not acknowledged yet
+    unacked: Option<AckQueue>,
 }
 
 impl TcpFlow {
-    pub fn new(packet: &Arc<Packet>, callback: Box<DataCallback>) -> Box<TcpFlow> {
-        unsafe {
-            let flow = Box::new(TcpFlow {
-                on_data_callback: callback,
-                tracker_: new_tcp_data_tracker(inet::ntohl((*packet.tcp).seq) + 1),
-            });
-
-            let this = mem::transmute::<*const TcpFlow, *const c_char>(&*flow);
-            tcp_data_tracker_set_callback(flow.tracker_, this, on_data_callback);
-
-            return flow;
+    // data is expected from the first segment of the direction on, after the syn
+    // or, picked up mid-stream, at its own sequence number
+    pub fn new(
+        packet: &Arc<Packet>,
+        config: &StreamConfig,
+        callback: Box<DataCallback>,
+    ) -> TcpFlow {
+        let seq = match packet.tcp() {
+            Some(ref tcp) if tcp.flags() & TCPHeader::SYN > 0 => tcp.seq().wrapping_add(1),
+            Some(ref tcp) => tcp.seq(),
+            None => 0,
+        };
+        TcpFlow {
+            on_data_callback: callback,
+            reassembler: Reassembler::new(seq, config, ReassemblyMemory::global()),
+            unacked: if config.ack_ordered {
+                Some(AckQueue::new(config, ReassemblyMemory::global()))
+            } else {
+                None
+            },
         }
     }
 
     pub fn process_packet(&mut self, packet: &Arc<Packet>) {
-        unsafe {
-            if (*packet.tcp).flags & TCPHeader::SYN > 0 {
-                let seq = inet::ntohl((*packet.tcp).seq);
-                tcp_data_tracker_update_seq(self.tracker_, seq + 1);
-            }
+        let tcp = match packet.tcp() {
+            Some(tcp) => tcp,
+            None => return,
+        };
+        if tcp.flags() & TCPHeader::SYN > 0 {
+            self.reassembler.on_syn(tcp.seq());
         }
 
         if packet.state & Packet::STATE_PAYLOAD > 0 {
-            let payload = packet.payload_slice();
-            unsafe {
-                tcp_data_tracker_process_data(
-                    self.tracker_,
-                    inet::ntohl((*packet.tcp).seq),
-                    payload.as_ptr() as *const c_char,
-                    payload.len() as u32,
-                );
-            }
+            let now = packet.timestamp;
+            self.reassemble(now, |reassembler, deliver| {
+                reassembler.insert(tcp.seq(), packet.payload_slice(), now, deliver)
+            });
+        }
+    }
+
+    // the other side acknowledged up to ack, releases what waited for it
+    pub fn on_ack(&mut self, ack: u32, now: u64) {
+        let callback = &self.on_data_callback;
+        if let Some(ref mut queue) = self.unacked {
+            queue.ack(ack, now, &mut |chunk| callback(chunk));
         }
     }
-}
 
-impl Drop for TcpFlow {
-    fn drop(&mut self) {
-        unsafe {
-            free_tcp_data_tracker(self.tracker_);
+    // gives up on a hole open for too long, now in nano second
+    pub fn expire(&mut self, now: u64) {
+        self.reassemble(now, |reassembler, deliver| reassembler.expire(now, deliver));
+    }
+
+    // hands on what is still buffered, past the holes, acknowledged or not
+    pub fn flush(&mut self) {
+        self.reassemble(0, |reassembler, deliver| reassembler.flush(deliver));
+        let callback = &self.on_data_callback;
+        if let Some(ref mut queue) = self.unacked {
+            queue.flush(&mut |chunk| callback(chunk));
+        }
+    }
+
+    // runs f with where the reassembler hands its chunks: the callback, or the
+    // queue waiting for the ack
+    fn reassemble<F>(&mut self, now: u64, f: F)
+    where
+        F: FnOnce(&mut Reassembler, &mut FnMut(Reassembled)),
+    {
+        let callback = &self.on_data_callback;
+        match self.unacked {
+            Some(ref mut queue) => {
+                f(&mut self.reassembler, &mut |chunk| queue.push(chunk, now));
+                queue.set_end_seq(self.reassembler.next_seq());
+                queue.expire(now, &mut |chunk| callback(chunk));
+            }
+            None => f(&mut self.reassembler, &mut |chunk| callback(chunk)),
         }
     }
 }
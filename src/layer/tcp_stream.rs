@@ -1,28 +1,41 @@
+use config::StreamConfig;
 use detector;
-use inet;
+use event::{self, Event};
+use flow::{self, CloseReason, FlowCounters, FlowRecord, TCPFlowRecord};
+use layer::icmp::ICMPError;
+use layer::ip::{ipv4_host_order, IPProto};
 use layer::packet::Packet;
 use layer::stream_state;
-use layer::tcp::TCPHeader;
-use layer::tcp::{TCPDissector, TCPDissectorAllocator};
+use layer::tcp::{Direction, TCPDissector, TCPDissectorAllocator};
+use layer::tcp::{Reassembled, RttEstimator, SynFingerprint, TCPHeader, TCPOptions};
+use layer::tcp::{ReassemblyMemory, TCPAnalyzer, TCPCounters};
+use layer::tcp::{TCPConnection, TCPOutcome, TCPState};
 use layer::TcpFlow;
 use libc::c_char;
+use stats::WorkerStats;
 use std::cell::RefCell;
 use std::ptr;
+use std::net::IpAddr;
 use std::rc::Rc;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use std::vec::Vec;
 
 pub struct TCPStream {
     state: u32,
+    start_timestamp: u64,
     last_timestamp: u64,
+    connection: TCPConnection,
+
+    //ingress interface of the first packet
+    interface: Arc<str>,
 
     //host order
     client_port: u16,
     server_port: u16,
 
-    //net order
-    client: u32,
-    server: u32,
+    client: IpAddr,
+    server: IpAddr,
 
     //ndpi
     detector: Rc<detector::Detector>,
@@ -34,24 +47,77 @@ pub struct TCPStream {
 
     pending_packets: Rc<RefCell<Vec<Arc<Packet>>>>,
 
-    client_flow: Option<Box<TcpFlow>>,
-    server_flow: Option<Box<TcpFlow>>,
+    stats: Arc<WorkerStats>,
+    //overlap policy and memory limit of the flows
+    config: StreamConfig,
+
+    client_flow: Option<TcpFlow>,
+    server_flow: Option<TcpFlow>,
 
     dissector: Rc<RefCell<TCPDissector>>,
+
+    //last icmp error quoting the stream
+    icmp_error: Option<ICMPError>,
+
+    //options of the syn and syn-ack
+    client_options: TCPOptions,
+    server_options: TCPOptions,
+    client_fingerprint: Option<SynFingerprint>,
+    server_fingerprint: Option<SynFingerprint>,
+
+    //sensor to each end and back
+    client_rtt: RttEstimator,
+    server_rtt: RttEstimator,
+    //nano second, 0 until seen
+    syn_time: u64,
+    syn_ack_time: u64,
+
+    //retransmissions, reordering, ... of what each side sent
+    client_analyzer: TCPAnalyzer,
+    server_analyzer: TCPAnalyzer,
+
+    //packets and bytes each side sent
+    from_client: FlowCounters,
+    from_server: FlowCounters,
 }
 
 impl TCPStream {
     const MAX_DETECT_TIMES: u8 = 10;
 
-    pub fn new(packet: Arc<Packet>, detector: Rc<detector::Detector>) -> Option<Box<TCPStream>> {
-        if unsafe { (*packet.tcp).flags & TCPHeader::SYN == 0 } {
-            return None;
+    // a syn opens a stream, and with midstream so does a data segment of a
+    // connection whose start was missed
+    pub fn new(
+        packet: Arc<Packet>,
+        detector: Rc<detector::Detector>,
+        stats: Arc<WorkerStats>,
+        config: &StreamConfig,
+    ) -> Option<Box<TCPStream>> {
+        let flags = match packet.tcp() {
+            Some(tcp) => tcp.flags(),
+            None => return None,
+        };
+        if flags & TCPHeader::SYN == 0 {
+            if !config.midstream || flags & TCPHeader::RST > 0 || packet.payload_len() == 0 {
+                return None;
+            }
+            trace!("pick up stream mid-stream");
         }
+        let sender = (packet.src_ip, packet.src_port);
+        let receiver = (packet.dst_ip, packet.dst_port);
+        let ((client, client_port), (server, server_port)) = if TCPStream::is_client_side(&packet) {
+            (sender, receiver)
+        } else {
+            (receiver, sender)
+        };
 
+        stats.tcp_streams_total.fetch_add(1, Ordering::Relaxed);
         let stream = Box::new(TCPStream {
             state: stream_state::STATE_PROTOCOL_DETECTING,
 
+            start_timestamp: packet.timestamp,
             last_timestamp: packet.timestamp,
+            connection: TCPConnection::new(),
+            interface: packet.interface.clone(),
 
             detector,
             flow: ptr::null(),
@@ -60,11 +126,11 @@ impl TCPStream {
             detect_times: 0,
             proto: detector::Proto::new(),
 
-            client_port: packet.src_port,
-            server_port: packet.dst_port,
+            client_port,
+            server_port,
 
-            client: packet.src_ip,
-            server: packet.dst_ip,
+            client,
+            server,
 
             pending_packets: Rc::new(RefCell::new(Vec::with_capacity(
                 TCPStream::MAX_DETECT_TIMES as usize,
@@ -73,18 +139,217 @@ impl TCPStream {
             client_flow: None,
             server_flow: None,
             dissector: TCPDissectorAllocator::default(),
+
+            icmp_error: None,
+
+            client_options: TCPOptions::default(),
+            server_options: TCPOptions::default(),
+            client_fingerprint: None,
+            server_fingerprint: None,
+
+            client_rtt: RttEstimator::new(),
+            server_rtt: RttEstimator::new(),
+            syn_time: 0,
+            syn_ack_time: 0,
+
+            client_analyzer: TCPAnalyzer::new(ReassemblyMemory::global()),
+            server_analyzer: TCPAnalyzer::new(ReassemblyMemory::global()),
+
+            from_client: FlowCounters::default(),
+            from_server: FlowCounters::default(),
+
+            stats,
+            config: config.clone(),
         });
 
         trace!("{}", stream_state::state_to_string(stream.state));
         return Some(stream);
     }
 
+    // whether a packet picked up mid-stream comes from the client: a syn-ack comes
+    // from the server, otherwise the lower port is taken as the service
+    pub fn is_client_side(packet: &Packet) -> bool {
+        let flags = packet.tcp().map_or(0, |tcp| tcp.flags());
+        if flags & TCPHeader::SYN > 0 {
+            return flags & TCPHeader::ACK == 0;
+        }
+        return packet.src_port >= packet.dst_port;
+    }
+
     pub fn last_seen(&self) -> u64 {
         self.last_timestamp
     }
 
+    pub fn connection_state(&self) -> TCPState {
+        self.connection.state()
+    }
+
+    // None while the connection is going on
+    pub fn outcome(&self) -> Option<TCPOutcome> {
+        self.connection.outcome()
+    }
+
+    // closed, or past time wait
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.connection.is_finished(now)
+    }
+
+    // a syn reusing the ports of a connection that ended
+    pub fn is_reused_by(&self, packet: &Arc<Packet>) -> bool {
+        let flags = packet.tcp().map_or(0, |tcp| tcp.flags());
+        return flags & TCPHeader::SYN > 0
+            && flags & TCPHeader::ACK == 0
+            && self.connection.is_reusable();
+    }
+
+    // nothing seen for too long, the connection is taken to have timed out
+    pub fn on_timeout(&mut self) {
+        self.connection.on_timeout();
+    }
+
+    pub fn interface(&self) -> &str {
+        &self.interface
+    }
+
+    pub fn icmp_error(&self) -> Option<&ICMPError> {
+        self.icmp_error.as_ref()
+    }
+
+    pub fn client_options(&self) -> &TCPOptions {
+        &self.client_options
+    }
+
+    pub fn server_options(&self) -> &TCPOptions {
+        &self.server_options
+    }
+
+    pub fn client_fingerprint(&self) -> Option<&SynFingerprint> {
+        self.client_fingerprint.as_ref()
+    }
+
+    pub fn server_fingerprint(&self) -> Option<&SynFingerprint> {
+        self.server_fingerprint.as_ref()
+    }
+
+    // shift applied to the windows the client advertises, scaling is only in
+    // effect when both syns carried the option
+    pub fn client_window_scale(&self) -> u8 {
+        match (
+            self.client_options.window_scale,
+            self.server_options.window_scale,
+        ) {
+            (Some(shift), Some(_)) => shift,
+            _ => 0,
+        }
+    }
+
+    pub fn server_window_scale(&self) -> u8 {
+        match (
+            self.client_options.window_scale,
+            self.server_options.window_scale,
+        ) {
+            (Some(_), Some(shift)) => shift,
+            _ => 0,
+        }
+    }
+
+    pub fn client_rtt(&self) -> &RttEstimator {
+        &self.client_rtt
+    }
+
+    pub fn server_rtt(&self) -> &RttEstimator {
+        &self.server_rtt
+    }
+
+    pub fn client_counters(&self) -> &TCPCounters {
+        self.client_analyzer.counters()
+    }
+
+    pub fn server_counters(&self) -> &TCPCounters {
+        self.server_analyzer.counters()
+    }
+
+    // the tracker drops the stream: detection ends, what the flows still buffer
+    // goes to the dissector and the record to the flow sinks
+    pub fn on_close(&mut self, reason: CloseReason) {
+        self.detect_give_up();
+        if self.state & stream_state::STATE_STREAM_SKIP == 0 {
+            self.flush_flows();
+        }
+        if flow::enabled() {
+            flow::emit(&self.flow_record(reason));
+        }
+    }
+
+    pub fn flow_record(&self, reason: CloseReason) -> FlowRecord {
+        FlowRecord {
+            ip_proto: IPProto::TCP,
+            interface: self.interface.clone(),
+            client: self.client,
+            client_port: self.client_port,
+            server: self.server,
+            server_port: self.server_port,
+            start: self.start_timestamp,
+            end: self.last_timestamp,
+            from_client: self.from_client,
+            from_server: self.from_server,
+            protocol: self.detector.protocol_name(&self.proto),
+            category: self.detector.category_name(&self.proto),
+            reason,
+            icmp_error: self.icmp_error.clone(),
+            tcp: Some(TCPFlowRecord {
+                state: self.connection.state(),
+                outcome: self.connection.outcome(),
+                client_fingerprint: self.client_fingerprint.clone(),
+                server_fingerprint: self.server_fingerprint.clone(),
+                client_rtt: self.client_rtt.rtt(),
+                server_rtt: self.server_rtt.rtt(),
+                client_counters: *self.client_analyzer.counters(),
+                server_counters: *self.server_analyzer.counters(),
+            }),
+        }
+    }
+
+    pub fn on_icmp_error(&mut self, packet: &Arc<Packet>) {
+        self.icmp_error = packet.icmp_error();
+        debug!(
+            "{}:{} -> {}:{} {}",
+            self.client,
+            self.client_port,
+            self.server,
+            self.server_port,
+            self.icmp_error.as_ref().unwrap()
+        );
+    }
+
     pub fn handle_packet(&mut self, packet: &Arc<Packet>) {
         self.last_timestamp = packet.timestamp;
+        let is_client = self.is_client_flow(packet);
+        if is_client {
+            self.from_client.on_packet(packet);
+        } else {
+            self.from_server.on_packet(packet);
+        }
+        self.update_options(packet);
+        if let Some(tcp) = packet.tcp() {
+            self.connection
+                .on_segment(is_client, &tcp, packet.payload_len(), packet.timestamp);
+            // compared here, not in the flows, so streams nothing was detected on
+            // are checked too
+            let payload = if packet.state & Packet::STATE_PAYLOAD > 0 {
+                packet.payload_slice()
+            } else {
+                &[]
+            };
+            let conflicting = if is_client {
+                self.client_analyzer.on_segment(&tcp, payload)
+            } else {
+                self.server_analyzer.on_segment(&tcp, payload)
+            };
+            if conflicting > 0 {
+                self.on_conflicting_overlap(packet, conflicting);
+            }
+        }
 
         if self.state
             & (stream_state::STATE_STREAM_SKIP
@@ -109,24 +374,66 @@ impl TCPStream {
             self.detect_protocol(packet);
         } else if self.state & stream_state::STATE_PROTOCOL_SUCCESS > 0 {
             self.dispatch_packet(packet);
+            // a hole in the other direction may have waited long enough
+            self.expire_flows(packet.timestamp);
         } else {
             unreachable!()
         }
 
-        unsafe {
-            if (*packet.tcp).flags & (TCPHeader::FIN | TCPHeader::RST) > 0 {
-                self.state |= stream_state::STATE_STREAM_FINISHED;
-                trace!(
-                    "stream finished:{}",
-                    stream_state::state_to_string(self.state)
-                );
+        // trailing data after a fin still goes to the dissector, only a closed
+        // connection finishes the stream
+        if self.connection.is_closed() {
+            self.flush_flows();
+            self.state |= stream_state::STATE_STREAM_FINISHED;
+            trace!(
+                "stream finished:{}",
+                stream_state::state_to_string(self.state)
+            );
+        }
+    }
+
+    // syn options, fingerprints and round trip samples, whatever the stream state
+    fn update_options(&mut self, packet: &Arc<Packet>) {
+        let tcp = match packet.tcp() {
+            Some(tcp) => tcp,
+            None => return,
+        };
+        let options = TCPOptions::parse(&tcp);
+        let is_client = self.is_client_flow(packet);
+        let tm = packet.timestamp;
+
+        if tcp.flags() & TCPHeader::SYN > 0 {
+            if is_client {
+                self.client_options = options;
+                self.client_fingerprint = SynFingerprint::from_packet(packet);
+                self.syn_time = tm;
+            } else {
+                self.server_options = options;
+                self.server_fingerprint = SynFingerprint::from_packet(packet);
+                if self.syn_time > 0 && self.syn_ack_time == 0 {
+                    self.server_rtt.sample(tm.saturating_sub(self.syn_time));
+                }
+                self.syn_ack_time = tm;
+            }
+        } else if is_client && self.syn_ack_time > 0 && self.client_rtt.samples() == 0 {
+            // the ack completing the handshake
+            self.client_rtt.sample(tm.saturating_sub(self.syn_ack_time));
+        }
+
+        if let Some((value, echo)) = options.timestamp {
+            if is_client {
+                self.server_rtt.on_value(value, tm);
+                self.client_rtt.on_echo(echo, tm);
+            } else {
+                self.client_rtt.on_value(value, tm);
+                self.server_rtt.on_echo(echo, tm);
             }
         }
     }
 
     #[inline]
     pub fn is_finished(&self) -> bool {
-        self.state & stream_state::STATE_STREAM_FINISHED > 0
+        self.state & stream_state::STATE_STREAM_FINISHED > 0 || self.connection.is_closed()
     }
 
     #[inline]
@@ -138,7 +445,7 @@ impl TCPStream {
         if self.is_client_flow(packet) {
             self.proto = self.detector.detect(
                 self.flow,
-                packet.ipv4 as *const c_char,
+                packet.ip_layer_slice().as_ptr() as *const c_char,
                 packet.ip_layer_len as u16,
                 packet.timestamp,
                 self.client_id,
@@ -147,7 +454,7 @@ impl TCPStream {
         } else {
             self.proto = self.detector.detect(
                 self.flow,
-                packet.ipv4 as *const c_char,
+                packet.ip_layer_slice().as_ptr() as *const c_char,
                 packet.ip_layer_len as u16,
                 packet.timestamp,
                 self.server_id,
@@ -171,7 +478,8 @@ impl TCPStream {
     }
 
     fn detect_give_up(&mut self) {
-        if self.state & stream_state::STATE_PROTOCOL_FINISHED > 0 {
+        // only while detecting, and once ndpi saw a packet
+        if self.state & stream_state::STATE_PROTOCOL_DETECTING == 0 || self.flow == ptr::null() {
             return;
         }
         self.proto = self.detector.detect_give_up(self.flow, 1);
@@ -182,9 +490,9 @@ impl TCPStream {
 
         self.proto = self.detector.guess_undetected_protocol(
             self.flow,
-            unsafe { inet::ntohl(self.client) },
+            ipv4_host_order(&self.client),
             self.client_port,
-            unsafe { inet::ntohl(self.server) },
+            ipv4_host_order(&self.server),
             self.server_port,
         );
         if self.proto.success() {
@@ -197,8 +505,10 @@ impl TCPStream {
     fn on_detect_success(&mut self) {
         self.state &= !stream_state::STATE_PROTOCOL_ALL;
         self.state |= stream_state::STATE_PROTOCOL_SUCCESS;
+        self.stats.detect_success.fetch_add(1, Ordering::Relaxed);
         trace!(
-            "detect success {},{}",
+            "detect success {},{},{}",
+            self.interface,
             self.detector.protocol_name(&self.proto),
             stream_state::state_to_string(self.state)
         );
@@ -218,6 +528,20 @@ impl TCPStream {
         let flow;
         let this = self as *const TCPStream;
         let is_client = self.is_client_flow(packet);
+
+        // with ack ordering, the data this segment acknowledges goes first
+        if let Some(tcp) = packet.tcp() {
+            if tcp.flags() & TCPHeader::ACK > 0 {
+                let other = if is_client {
+                    &mut self.server_flow
+                } else {
+                    &mut self.client_flow
+                };
+                if let Some(ref mut other) = *other {
+                    other.on_ack(tcp.ack(), packet.timestamp);
+                }
+            }
+        }
         if is_client {
             flow = &mut self.client_flow;
         } else {
@@ -230,27 +554,58 @@ impl TCPStream {
             None => {
                 let mut f;
                 let dissector = self.dissector.clone();
+                let stats = self.stats.clone();
 
                 if is_client {
-                    let cb = move |data: &[u8]| {
-                        if let Err(_) = dissector.borrow_mut().on_client_data(data) {
-                            unsafe {
-                                let this = this as *mut TCPStream;
-                                &(*this).set_skip();
+                    let cb = move |chunk: Reassembled<'_>| match chunk {
+                        Reassembled::Data(data) => {
+                            stats
+                                .dissect_bytes
+                                .fetch_add(data.len() as u64, Ordering::Relaxed);
+                            if let Err(_) = dissector.borrow_mut().on_client_data(data) {
+                                stats.dissect_errors.fetch_add(1, Ordering::Relaxed);
+                                unsafe {
+                                    let this = this as *mut TCPStream;
+                                    &(*this).set_skip();
+                                }
+                            }
+                        }
+                        Reassembled::Gap(len) => {
+                            stats.tcp_gaps.fetch_add(1, Ordering::Relaxed);
+                            if let Err(_) = dissector.borrow_mut().on_gap(Direction::Client, len) {
+                                unsafe {
+                                    let this = this as *mut TCPStream;
+                                    (*this).set_skip();
+                                }
                             }
                         }
                     };
-                    f = TcpFlow::new(packet, Box::new(cb));
+                    f = TcpFlow::new(packet, &self.config, Box::new(cb));
                 } else {
-                    let cb = move |data: &[u8]| {
-                        if let Err(_) = dissector.borrow_mut().on_server_data(data) {
-                            unsafe {
-                                let this = this as *mut TCPStream;
-                                &(*this).set_skip();
+                    let cb = move |chunk: Reassembled<'_>| match chunk {
+                        Reassembled::Data(data) => {
+                            stats
+                                .dissect_bytes
+                                .fetch_add(data.len() as u64, Ordering::Relaxed);
+                            if let Err(_) = dissector.borrow_mut().on_server_data(data) {
+                                stats.dissect_errors.fetch_add(1, Ordering::Relaxed);
+                                unsafe {
+                                    let this = this as *mut TCPStream;
+                                    &(*this).set_skip();
+                                }
+                            }
+                        }
+                        Reassembled::Gap(len) => {
+                            stats.tcp_gaps.fetch_add(1, Ordering::Relaxed);
+                            if let Err(_) = dissector.borrow_mut().on_gap(Direction::Server, len) {
+                                unsafe {
+                                    let this = this as *mut TCPStream;
+                                    (*this).set_skip();
+                                }
                             }
                         }
                     };
-                    f = TcpFlow::new(packet, Box::new(cb));
+                    f = TcpFlow::new(packet, &self.config, Box::new(cb));
                 }
                 f.process_packet(packet);
 
@@ -262,11 +617,43 @@ impl TCPStream {
         }
     }
 
+    fn on_conflicting_overlap(&self, packet: &Arc<Packet>, bytes: u64) {
+        event::emit(Event::TCPOverlap {
+            timestamp: packet.timestamp,
+            interface: self.interface.clone(),
+            src_ip: packet.src_ip,
+            src_port: packet.src_port,
+            dst_ip: packet.dst_ip,
+            dst_port: packet.dst_port,
+            bytes,
+        });
+    }
+
+    fn expire_flows(&mut self, now: u64) {
+        if let Some(ref mut flow) = self.client_flow {
+            flow.expire(now);
+        }
+        if let Some(ref mut flow) = self.server_flow {
+            flow.expire(now);
+        }
+    }
+
+    fn flush_flows(&mut self) {
+        if let Some(ref mut flow) = self.client_flow {
+            flow.flush();
+        }
+        if let Some(ref mut flow) = self.server_flow {
+            flow.flush();
+        }
+    }
+
     fn on_detect_failed(&mut self) {
         self.state &= !stream_state::STATE_PROTOCOL_ALL;
         self.state |= stream_state::STATE_PROTOCOL_FAILED;
+        self.stats.detect_failed.fetch_add(1, Ordering::Relaxed);
         trace!(
-            "detect failed {}",
+            "detect failed {},{}",
+            self.interface,
             stream_state::state_to_string(self.state)
         );
         self.pending_packets.borrow_mut().clear();
@@ -277,7 +664,19 @@ impl TCPStream {
 impl Drop for TCPStream {
     fn drop(&mut self) {
         self.detect_give_up();
-        trace!("stream clean up");
+        debug!(
+            "{}:{} -> {}:{} {} {}, client {}, server {}",
+            self.client,
+            self.client_port,
+            self.server,
+            self.server_port,
+            self.connection.state().to_string(),
+            self.connection
+                .outcome()
+                .map_or("unfinished", |outcome| outcome.to_string()),
+            self.client_analyzer.counters(),
+            self.server_analyzer.counters()
+        );
 
         unsafe {
             if self.flow != ptr::null() {
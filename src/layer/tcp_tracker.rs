@@ -1,24 +1,33 @@
+use config::StreamConfig;
 use detector::Detector;
+use flow::CloseReason;
 use layer::ip::StreamID;
 use layer::packet::Packet;
 use layer::IPProto;
 use layer::TCPStream;
+use stats::WorkerStats;
 use std::collections::HashMap;
+use std::net::IpAddr;
 use std::rc::Rc;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
 
 pub struct TCPTracker {
     streams: HashMap<StreamID, Box<TCPStream>>,
     detector: Rc<Detector>,
     last_cleanup: u64,
+    stats: Arc<WorkerStats>,
+    config: StreamConfig,
 }
 
 impl TCPTracker {
-    //micro second
-    const STREAM_CLEANUP_DURATION: u64 = 1000 * 1000 * 30;
+    //nano second
+    const STREAM_CLEANUP_DURATION: u64 = 1000 * 1000 * 1000 * 30;
 
-    pub fn new() -> TCPTracker {
+    pub fn new(stats: Arc<WorkerStats>, config: &StreamConfig) -> TCPTracker {
         TCPTracker {
+            stats,
+            config: config.clone(),
             last_cleanup: 0,
             streams: HashMap::new(),
             detector: Rc::new(Detector::new(IPProto::TCP)),
@@ -26,7 +35,18 @@ impl TCPTracker {
     }
 
     pub fn on_packet(&mut self, packet: &Arc<Packet>) {
-        let id = StreamID::new(
+        if self.config.drop_bad_checksum && packet.bad_checksum() {
+            trace!(
+                "drop bad checksum {}:{}",
+                packet.src_ip_str(),
+                packet.src_port
+            );
+            self.stats.bad_checksum.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+
+        let id = self.stream_id(
+            packet,
             packet.src_ip,
             packet.dst_ip,
             packet.src_port,
@@ -37,6 +57,17 @@ impl TCPTracker {
         let mut find = false;
         let tm = packet.timestamp;
 
+        let reused = match self.streams.get(&id) {
+            Some(stream) => stream.is_reused_by(packet),
+            None => false,
+        };
+        if reused {
+            trace!("new connection on the ports of a closed one");
+            if let Some(mut stream) = self.streams.remove(&id) {
+                stream.on_close(CloseReason::Reused);
+            }
+        }
+
         {
             let mut result = self.streams.get_mut(&id);
 
@@ -51,13 +82,20 @@ impl TCPTracker {
         }
 
         if !find {
-            let stream = TCPStream::new(packet.clone(), self.detector.clone());
+            let stream = TCPStream::new(
+                packet.clone(),
+                self.detector.clone(),
+                self.stats.clone(),
+                &self.config,
+            );
             match stream {
                 Some(mut stream) => {
                     stream.handle_packet(packet);
                     finished = stream.is_finished();
 
-                    if !finished {
+                    if finished {
+                        stream.on_close(CloseReason::Finished);
+                    } else {
                         self.streams.insert(id, stream);
                     }
                 }
@@ -66,10 +104,52 @@ impl TCPTracker {
         }
 
         if finished {
-            self.streams.remove(&id);
+            if let Some(mut stream) = self.streams.remove(&id) {
+                stream.on_close(CloseReason::Finished);
+            }
         } else {
             self.cleanup_stream(tm);
         }
+        self.update_stats();
+    }
+
+    // an icmp error quoting one of our streams, false when the stream is unknown
+    pub fn on_icmp_error(&mut self, packet: &Arc<Packet>) -> bool {
+        let quoted = match packet.icmp_quoted {
+            Some(quoted) => quoted,
+            None => return false,
+        };
+        let id = self.stream_id(
+            packet,
+            quoted.src_ip,
+            quoted.dst_ip,
+            quoted.src_port,
+            quoted.dst_port,
+        );
+        match self.streams.get_mut(&id) {
+            Some(stream) => {
+                stream.on_icmp_error(packet);
+                self.stats.icmp_errors.fetch_add(1, Ordering::Relaxed);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn stream_id(
+        &self,
+        packet: &Packet,
+        src_ip: IpAddr,
+        dst_ip: IpAddr,
+        src_port: u16,
+        dst_port: u16,
+    ) -> StreamID {
+        let vlan = if self.config.track_vlan {
+            packet.vlan_key()
+        } else {
+            [0, 0]
+        };
+        return StreamID::with_vlan(vlan, src_ip, dst_ip, src_port, dst_port);
     }
 
     pub fn cleanup_stream(&mut self, tm: u64) -> usize {
@@ -80,12 +160,39 @@ impl TCPTracker {
         let before = self.streams.len();
 
         self.streams.retain(|_k, stream| -> bool {
-            stream.last_seen() + TCPTracker::STREAM_CLEANUP_DURATION > tm
+            if stream.is_expired(tm) {
+                stream.on_close(CloseReason::Finished);
+                return false;
+            }
+            if stream.last_seen() + TCPTracker::STREAM_CLEANUP_DURATION <= tm {
+                stream.on_timeout();
+                stream.on_close(CloseReason::Idle);
+                return false;
+            }
+            true
         });
 
         let after = self.streams.len();
         debug!("tcp stream cleanup {}/{}", before - after, before);
         self.last_cleanup = tm;
-        return after - before;
+        self.update_stats();
+        return before - after;
+    }
+
+    pub fn flush(&mut self) -> usize {
+        let n = self.streams.len();
+        for stream in self.streams.values_mut() {
+            stream.on_close(CloseReason::Shutdown);
+        }
+        self.streams.clear();
+        debug!("tcp stream flush {}", n);
+        self.update_stats();
+        return n;
+    }
+
+    fn update_stats(&self) {
+        self.stats
+            .tcp_streams
+            .store(self.streams.len() as u64, Ordering::Relaxed);
     }
 }
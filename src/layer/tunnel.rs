@@ -0,0 +1,218 @@
+use crate::inet;
+use layer::EthernetType;
+use std::fmt;
+use std::net::IpAddr;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TunnelType {
+    GRE,
+    ERSPAN,
+    VXLAN,
+    GENEVE,
+    // ipv4 or ipv6 directly inside ip
+    IPIP,
+}
+
+impl TunnelType {
+    pub fn to_string(self) -> &'static str {
+        match self {
+            TunnelType::GRE => "gre",
+            TunnelType::ERSPAN => "erspan",
+            TunnelType::VXLAN => "vxlan",
+            TunnelType::GENEVE => "geneve",
+            TunnelType::IPIP => "ipip",
+        }
+    }
+}
+
+// outer layer of a decapsulated packet, outermost first on Packet::tunnels
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Tunnel {
+    pub kind: TunnelType,
+    pub src_ip: IpAddr,
+    pub dst_ip: IpAddr,
+    // gre key, erspan session or vni, 0 when absent
+    pub id: u32,
+}
+
+impl fmt::Display for Tunnel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}({}->{} id {})",
+            self.kind.to_string(),
+            self.src_ip,
+            self.dst_ip,
+            self.id
+        )
+    }
+}
+
+pub const VXLAN_PORT: u16 = 4789;
+pub const GENEVE_PORT: u16 = 6081;
+
+#[derive(Clone, Copy)]
+pub struct GREHeader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> GREHeader<'a> {
+    // without the optional fields
+    pub const LEN: usize = 4;
+
+    pub const CHECKSUM: u16 = 0x8000;
+    pub const KEY: u16 = 0x2000;
+    pub const SEQUENCE: u16 = 0x1000;
+
+    pub fn new(data: &'a [u8]) -> Option<GREHeader<'a>> {
+        if data.len() < GREHeader::LEN {
+            return None;
+        }
+        Some(GREHeader { data })
+    }
+
+    //checksum, key, sequence flags & version
+    #[inline]
+    pub fn flags(&self) -> u16 {
+        return inet::read_u16(self.data, 0);
+    }
+
+    #[inline]
+    pub fn version(&self) -> u16 {
+        return self.flags() & 0x7;
+    }
+
+    #[inline]
+    pub fn protocol(&self) -> EthernetType {
+        return EthernetType(inet::read_u16(self.data, 2));
+    }
+
+    // including the optional fields
+    #[inline]
+    pub fn header_len(&self) -> usize {
+        let flags = self.flags();
+        let mut len = GREHeader::LEN;
+        if flags & GREHeader::CHECKSUM > 0 {
+            len += 4;
+        }
+        if flags & GREHeader::KEY > 0 {
+            len += 4;
+        }
+        if flags & GREHeader::SEQUENCE > 0 {
+            len += 4;
+        }
+        return len;
+    }
+
+    // None without the key flag, or when the slice stops before it
+    pub fn key(&self) -> Option<u32> {
+        let flags = self.flags();
+        if flags & GREHeader::KEY == 0 {
+            return None;
+        }
+        let offset = if flags & GREHeader::CHECKSUM > 0 {
+            8
+        } else {
+            4
+        };
+        if self.data.len() < offset + 4 {
+            return None;
+        }
+        return Some(inet::read_u32(self.data, offset));
+    }
+}
+
+// ERSPAN type II, the type III header is 12 bytes with the session at the same place
+#[derive(Clone, Copy)]
+pub struct ERSPANHeader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> ERSPANHeader<'a> {
+    pub const LEN: usize = 8;
+    pub const LEN_III: usize = 12;
+
+    pub fn new(data: &'a [u8]) -> Option<ERSPANHeader<'a>> {
+        if data.len() < ERSPANHeader::LEN {
+            return None;
+        }
+        Some(ERSPANHeader { data })
+    }
+
+    #[inline]
+    pub fn version(&self) -> u8 {
+        return self.data[0] >> 4;
+    }
+
+    #[inline]
+    pub fn session_id(&self) -> u16 {
+        return inet::read_u16(self.data, 2) & 0x3ff;
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct VXLANHeader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> VXLANHeader<'a> {
+    pub const LEN: usize = 8;
+
+    pub const VNI_VALID: u8 = 0x08;
+
+    pub fn new(data: &'a [u8]) -> Option<VXLANHeader<'a>> {
+        if data.len() < VXLANHeader::LEN {
+            return None;
+        }
+        Some(VXLANHeader { data })
+    }
+
+    #[inline]
+    pub fn flags(&self) -> u8 {
+        return self.data[0];
+    }
+
+    // vni in the upper 24 bits
+    #[inline]
+    pub fn vni(&self) -> u32 {
+        return inet::read_u32(self.data, 4) >> 8;
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct GeneveHeader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> GeneveHeader<'a> {
+    // without the options
+    pub const LEN: usize = 8;
+
+    pub fn new(data: &'a [u8]) -> Option<GeneveHeader<'a>> {
+        if data.len() < GeneveHeader::LEN {
+            return None;
+        }
+        Some(GeneveHeader { data })
+    }
+
+    #[inline]
+    pub fn version(&self) -> u8 {
+        return self.data[0] >> 6;
+    }
+
+    // options length in 4 bytes units
+    #[inline]
+    pub fn header_len(&self) -> usize {
+        return GeneveHeader::LEN + (self.data[0] & 0x3f) as usize * 4;
+    }
+
+    #[inline]
+    pub fn protocol(&self) -> EthernetType {
+        return EthernetType(inet::read_u16(self.data, 2));
+    }
+
+    #[inline]
+    pub fn vni(&self) -> u32 {
+        return inet::read_u32(self.data, 4) >> 8;
+    }
+}
@@ -1,11 +1,43 @@
+use crate::inet;
+
 pub mod dissector;
 pub mod dns;
 pub use self::dissector::*;
 
-#[repr(C, packed)]
-pub struct UDPHeader {
-    pub src_port: u16,
-    pub dst_port: u16,
-    pub len: u16,
-    pub checksum: u16,
+#[derive(Clone, Copy)]
+pub struct UDPHeader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> UDPHeader<'a> {
+    pub const LEN: usize = 8;
+
+    // None when the slice cannot hold the header
+    pub fn new(data: &'a [u8]) -> Option<UDPHeader<'a>> {
+        if data.len() < UDPHeader::LEN {
+            return None;
+        }
+        Some(UDPHeader { data })
+    }
+
+    #[inline]
+    pub fn src_port(&self) -> u16 {
+        return inet::read_u16(self.data, 0);
+    }
+
+    #[inline]
+    pub fn dst_port(&self) -> u16 {
+        return inet::read_u16(self.data, 2);
+    }
+
+    // header and data
+    #[inline]
+    pub fn length(&self) -> u16 {
+        return inet::read_u16(self.data, 4);
+    }
+
+    #[inline]
+    pub fn checksum(&self) -> u16 {
+        return inet::read_u16(self.data, 6);
+    }
 }
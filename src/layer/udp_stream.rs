@@ -1,27 +1,35 @@
 use detector;
-use inet;
+use flow::{self, CloseReason, FlowCounters, FlowRecord};
+use layer::icmp::ICMPError;
+use layer::ip::{ipv4_host_order, IPProto};
 use layer::packet::Packet;
 use layer::stream_state;
 use layer::udp::dissector::UDPDissector;
 use layer::udp::DefaultDissector;
 use libc::c_char;
+use stats::WorkerStats;
 use std::cell::RefCell;
 use std::ptr;
+use std::net::IpAddr;
 use std::rc::Rc;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use std::vec::Vec;
 
 pub struct UDPStream {
     state: u32,
+    start_timestamp: u64,
     last_timestamp: u64,
 
+    //ingress interface of the first packet
+    interface: Arc<str>,
+
     //host order
     client_port: u16,
     server_port: u16,
 
-    //net order
-    client: u32,
-    server: u32,
+    client: IpAddr,
+    server: IpAddr,
 
     //ndpi
     detector: Rc<detector::Detector>,
@@ -33,17 +41,33 @@ pub struct UDPStream {
 
     pending_packets: Rc<RefCell<Vec<Arc<Packet>>>>,
 
+    stats: Arc<WorkerStats>,
+
     dissector: Rc<RefCell<UDPDissector>>,
+
+    //last icmp error quoting the stream
+    icmp_error: Option<ICMPError>,
+
+    //packets and bytes each side sent
+    from_client: FlowCounters,
+    from_server: FlowCounters,
 }
 
 impl UDPStream {
     const MAX_DETECT_TIMES: u8 = 10;
 
-    pub fn new(packet: Arc<Packet>, detector: Rc<detector::Detector>) -> Box<UDPStream> {
+    pub fn new(
+        packet: Arc<Packet>,
+        detector: Rc<detector::Detector>,
+        stats: Arc<WorkerStats>,
+    ) -> Box<UDPStream> {
+        stats.udp_streams_total.fetch_add(1, Ordering::Relaxed);
         Box::new(UDPStream {
             state: stream_state::STATE_PROTOCOL_DETECTING,
 
+            start_timestamp: packet.timestamp,
             last_timestamp: packet.timestamp,
+            interface: packet.interface.clone(),
 
             detector,
             flow: ptr::null(),
@@ -63,13 +87,74 @@ impl UDPStream {
             ))),
 
             dissector: DefaultDissector::default(),
+
+            icmp_error: None,
+
+            from_client: FlowCounters::default(),
+            from_server: FlowCounters::default(),
+
+            stats,
         })
     }
 }
 
 impl UDPStream {
+    pub fn interface(&self) -> &str {
+        &self.interface
+    }
+
+    pub fn icmp_error(&self) -> Option<&ICMPError> {
+        self.icmp_error.as_ref()
+    }
+
+    // the tracker drops the stream: detection ends and the record goes to the
+    // flow sinks
+    pub fn on_close(&mut self, reason: CloseReason) {
+        self.detect_give_up();
+        if flow::enabled() {
+            flow::emit(&self.flow_record(reason));
+        }
+    }
+
+    pub fn flow_record(&self, reason: CloseReason) -> FlowRecord {
+        FlowRecord {
+            ip_proto: IPProto::UDP,
+            interface: self.interface.clone(),
+            client: self.client,
+            client_port: self.client_port,
+            server: self.server,
+            server_port: self.server_port,
+            start: self.start_timestamp,
+            end: self.last_timestamp,
+            from_client: self.from_client,
+            from_server: self.from_server,
+            protocol: self.detector.protocol_name(&self.proto),
+            category: self.detector.category_name(&self.proto),
+            reason,
+            icmp_error: self.icmp_error.clone(),
+            tcp: None,
+        }
+    }
+
+    pub fn on_icmp_error(&mut self, packet: &Arc<Packet>) {
+        self.icmp_error = packet.icmp_error();
+        debug!(
+            "{}:{} -> {}:{} {}",
+            self.client,
+            self.client_port,
+            self.server,
+            self.server_port,
+            self.icmp_error.as_ref().unwrap()
+        );
+    }
+
     pub fn handle_packet(&mut self, packet: &Arc<Packet>) {
         self.last_timestamp = packet.timestamp;
+        if self.is_client_flow(packet) {
+            self.from_client.on_packet(packet);
+        } else {
+            self.from_server.on_packet(packet);
+        }
 
         if self.state
             & (stream_state::STATE_STREAM_SKIP
@@ -118,7 +203,7 @@ impl UDPStream {
         if self.is_client_flow(packet) {
             self.proto = self.detector.detect(
                 self.flow,
-                packet.ipv4 as *const c_char,
+                packet.ip_layer_slice().as_ptr() as *const c_char,
                 packet.ip_layer_len as u16,
                 packet.timestamp,
                 self.client_id,
@@ -127,7 +212,7 @@ impl UDPStream {
         } else {
             self.proto = self.detector.detect(
                 self.flow,
-                packet.ipv4 as *const c_char,
+                packet.ip_layer_slice().as_ptr() as *const c_char,
                 packet.ip_layer_len as u16,
                 packet.timestamp,
                 self.server_id,
@@ -148,6 +233,9 @@ impl UDPStream {
     fn dispatch_packet(&mut self, packet: &Arc<Packet>) {
         let is_client = self.is_client_flow(packet);
         let result;
+        self.stats
+            .dissect_bytes
+            .fetch_add(packet.payload_len() as u64, Ordering::Relaxed);
         if is_client {
             result = self.dissector.borrow_mut().on_client_packet(packet);
         } else {
@@ -160,13 +248,15 @@ impl UDPStream {
             }
             Err(_) => {
                 debug!("set skip");
+                self.stats.dissect_errors.fetch_add(1, Ordering::Relaxed);
                 self.set_skip();
             }
         }
     }
 
     fn detect_give_up(&mut self) {
-        if self.state & stream_state::STATE_PROTOCOL_FINISHED > 0 {
+        // only while detecting, and once ndpi saw a packet
+        if self.state & stream_state::STATE_PROTOCOL_DETECTING == 0 || self.flow == ptr::null() {
             return;
         }
         self.proto = self.detector.detect_give_up(self.flow, 1);
@@ -177,9 +267,9 @@ impl UDPStream {
 
         self.proto = self.detector.guess_undetected_protocol(
             self.flow,
-            unsafe { inet::ntohl(self.client) },
+            ipv4_host_order(&self.client),
             self.client_port,
-            unsafe { inet::ntohl(self.server) },
+            ipv4_host_order(&self.server),
             self.server_port,
         );
         if self.proto.success() {
@@ -192,8 +282,10 @@ impl UDPStream {
     fn on_detect_success(&mut self) {
         self.state &= !stream_state::STATE_PROTOCOL_ALL;
         self.state |= stream_state::STATE_PROTOCOL_SUCCESS;
+        self.stats.detect_success.fetch_add(1, Ordering::Relaxed);
         trace!(
-            "detect success {},{}",
+            "detect success {},{},{}",
+            self.interface,
             self.detector.protocol_name(&self.proto),
             stream_state::state_to_string(self.state)
         );
@@ -212,8 +304,10 @@ impl UDPStream {
     fn on_detect_failed(&mut self) {
         self.state &= !stream_state::STATE_PROTOCOL_ALL;
         self.state |= stream_state::STATE_PROTOCOL_FAILED;
+        self.stats.detect_failed.fetch_add(1, Ordering::Relaxed);
         trace!(
-            "detect failed {}",
+            "detect failed {},{}",
+            self.interface,
             stream_state::state_to_string(self.state)
         );
         self.pending_packets.borrow_mut().clear();
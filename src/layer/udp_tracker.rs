@@ -1,24 +1,33 @@
+use config::StreamConfig;
 use detector::Detector;
+use flow::CloseReason;
 use layer::ip::IPProto;
 use layer::ip::StreamID;
 use layer::packet::Packet;
 use layer::udp_stream::UDPStream;
+use stats::WorkerStats;
 use std::collections::HashMap;
+use std::net::IpAddr;
 use std::rc::Rc;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
 
 pub struct UDPTracker {
     streams: HashMap<StreamID, Box<UDPStream>>,
     detector: Rc<Detector>,
     last_cleanup: u64,
+    stats: Arc<WorkerStats>,
+    config: StreamConfig,
 }
 
 impl UDPTracker {
-    //micro second
-    const STREAM_CLEANUP_DURATION: u64 = 1000 * 1000 * 30;
+    //nano second
+    const STREAM_CLEANUP_DURATION: u64 = 1000 * 1000 * 1000 * 30;
 
-    pub fn new() -> UDPTracker {
+    pub fn new(stats: Arc<WorkerStats>, config: &StreamConfig) -> UDPTracker {
         UDPTracker {
+            stats,
+            config: config.clone(),
             streams: HashMap::new(),
             detector: Rc::new(Detector::new(IPProto::UDP)),
             last_cleanup: 0,
@@ -26,7 +35,18 @@ impl UDPTracker {
     }
 
     pub fn on_packet(&mut self, packet: &Arc<Packet>) {
-        let id = StreamID::new(
+        if self.config.drop_bad_checksum && packet.bad_checksum() {
+            trace!(
+                "drop bad checksum {}:{}",
+                packet.src_ip_str(),
+                packet.src_port
+            );
+            self.stats.bad_checksum.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+
+        let id = self.stream_id(
+            packet,
             packet.src_ip,
             packet.dst_ip,
             packet.src_port,
@@ -50,20 +70,64 @@ impl UDPTracker {
         }
 
         if !find {
-            let mut stream = UDPStream::new(packet.clone(), self.detector.clone());
+            let mut stream = UDPStream::new(packet.clone(), self.detector.clone(), self.stats.clone());
             stream.handle_packet(packet);
             finished = stream.is_finished();
 
-            if !finished {
+            if finished {
+                stream.on_close(CloseReason::Finished);
+            } else {
                 self.streams.insert(id, stream);
             }
         }
 
         if finished {
-            self.streams.remove(&id);
+            if let Some(mut stream) = self.streams.remove(&id) {
+                stream.on_close(CloseReason::Finished);
+            }
         } else {
             self.cleanup_stream(tm);
         }
+        self.update_stats();
+    }
+
+    // an icmp error quoting one of our streams, false when the stream is unknown
+    pub fn on_icmp_error(&mut self, packet: &Arc<Packet>) -> bool {
+        let quoted = match packet.icmp_quoted {
+            Some(quoted) => quoted,
+            None => return false,
+        };
+        let id = self.stream_id(
+            packet,
+            quoted.src_ip,
+            quoted.dst_ip,
+            quoted.src_port,
+            quoted.dst_port,
+        );
+        match self.streams.get_mut(&id) {
+            Some(stream) => {
+                stream.on_icmp_error(packet);
+                self.stats.icmp_errors.fetch_add(1, Ordering::Relaxed);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn stream_id(
+        &self,
+        packet: &Packet,
+        src_ip: IpAddr,
+        dst_ip: IpAddr,
+        src_port: u16,
+        dst_port: u16,
+    ) -> StreamID {
+        let vlan = if self.config.track_vlan {
+            packet.vlan_key()
+        } else {
+            [0, 0]
+        };
+        return StreamID::with_vlan(vlan, src_ip, dst_ip, src_port, dst_port);
     }
 
     pub fn cleanup_stream(&mut self, tm: u64) -> usize {
@@ -74,12 +138,34 @@ impl UDPTracker {
         let before = self.streams.len();
 
         self.streams.retain(|_k, stream| -> bool {
-            stream.last_seen() + UDPTracker::STREAM_CLEANUP_DURATION > tm
+            if stream.last_seen() + UDPTracker::STREAM_CLEANUP_DURATION <= tm {
+                stream.on_close(CloseReason::Idle);
+                return false;
+            }
+            true
         });
 
         let after = self.streams.len();
         debug!("udp stream cleanup {}/{}", before - after, before);
         self.last_cleanup = tm;
+        self.update_stats();
         return before - after;
     }
+
+    pub fn flush(&mut self) -> usize {
+        let n = self.streams.len();
+        for stream in self.streams.values_mut() {
+            stream.on_close(CloseReason::Shutdown);
+        }
+        self.streams.clear();
+        debug!("udp stream flush {}", n);
+        self.update_stats();
+        return n;
+    }
+
+    fn update_stats(&self) {
+        self.stats
+            .udp_streams
+            .store(self.streams.len() as u64, Ordering::Relaxed);
+    }
 }
@@ -1,5 +1,34 @@
-#[repr(C, packed)]
-pub struct VlanHeader {
-    pub vlan_id: u16,
-    pub eth_type: u16,
+use crate::inet;
+use layer::EthernetType;
+
+#[derive(Clone, Copy)]
+pub struct VlanHeader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> VlanHeader<'a> {
+    pub const LEN: usize = 4;
+
+    pub fn new(data: &'a [u8]) -> Option<VlanHeader<'a>> {
+        if data.len() < VlanHeader::LEN {
+            return None;
+        }
+        Some(VlanHeader { data })
+    }
+
+    #[inline]
+    pub fn vid(&self) -> u16 {
+        return inet::read_u16(self.data, 0) & 0x0fff;
+    }
+
+    #[inline]
+    pub fn priority(&self) -> u8 {
+        return (inet::read_u16(self.data, 0) >> 13) as u8;
+    }
+
+    // of what follows the tag
+    #[inline]
+    pub fn eth_type(&self) -> EthernetType {
+        return EthernetType(inet::read_u16(self.data, 2));
+    }
 }
@@ -14,7 +14,11 @@ extern crate glib_sys;
 pub mod config;
 pub mod daq;
 pub mod detector;
+pub mod event;
 pub mod files;
+pub mod flow;
 pub mod inet;
 pub mod layer;
 pub mod mime;
+pub mod sink;
+pub mod stats;
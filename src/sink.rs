@@ -0,0 +1,64 @@
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex, RwLock};
+
+// the sinks registered for one kind of record, events or flows
+pub struct Sinks<T: ?Sized> {
+    sinks: RwLock<Vec<Arc<T>>>,
+}
+
+impl<T: ?Sized> Sinks<T> {
+    pub fn new() -> Sinks<T> {
+        Sinks {
+            sinks: RwLock::new(Vec::new()),
+        }
+    }
+
+    pub fn register(&self, sink: Arc<T>) {
+        self.sinks.write().unwrap().push(sink);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sinks.read().unwrap().is_empty()
+    }
+
+    pub fn for_each<F: FnMut(&T)>(&self, mut f: F) {
+        for sink in self.sinks.read().unwrap().iter() {
+            f(sink);
+        }
+    }
+}
+
+// records into the application log
+pub struct LogSink;
+
+// one line per record, prefixed with its time in seconds
+pub struct FileSink {
+    file: Mutex<File>,
+}
+
+impl FileSink {
+    pub fn new(path: &str) -> Result<FileSink, String> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|err| format!("open {} error {}", path, err))?;
+        Ok(FileSink {
+            file: Mutex::new(file),
+        })
+    }
+
+    // timestamp in nano second
+    pub fn write_line(&self, timestamp: u64, record: &fmt::Display) -> io::Result<()> {
+        let line = format!(
+            "{}.{:09} {}\n",
+            timestamp / 1000000000,
+            timestamp % 1000000000,
+            record
+        );
+        let mut file = self.file.lock().unwrap();
+        file.write_all(line.as_bytes())
+    }
+}
@@ -0,0 +1,238 @@
+use daq::PacketSource;
+use layer::DecodeError;
+use std::fmt::Write;
+use std::fs::OpenOptions;
+use std::io::Write as IoWrite;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// counters of one dispatcher worker, written by the worker (and the dispatcher
+// for the queue side), read by the stats writer
+pub struct WorkerStats {
+    // packets put on the worker queue, or read from its rings in fanout mode
+    pub enqueued: AtomicU64,
+    // packets taken by the trackers
+    pub processed: AtomicU64,
+
+    // streams currently tracked and streams created so far
+    pub tcp_streams: AtomicU64,
+    pub tcp_streams_total: AtomicU64,
+    pub udp_streams: AtomicU64,
+    pub udp_streams_total: AtomicU64,
+    pub icmp_flows: AtomicU64,
+    pub icmp_flows_total: AtomicU64,
+    // icmp errors matched with the tcp or udp stream they quote
+    pub icmp_errors: AtomicU64,
+    // tcp and udp packets dropped before the streams for a bad checksum
+    pub bad_checksum: AtomicU64,
+    // addresses in the arp binding table
+    pub arp_bindings: AtomicU64,
+
+    pub detect_success: AtomicU64,
+    pub detect_failed: AtomicU64,
+
+    // payload bytes handed to the dissectors and dissector errors
+    pub dissect_bytes: AtomicU64,
+    pub dissect_errors: AtomicU64,
+    // holes in tcp payload the reassemblers gave up on
+    pub tcp_gaps: AtomicU64,
+
+    // ipv4 fragments seen, datagrams rebuilt and datagrams given up on
+    pub fragments: AtomicU64,
+    pub reassembled: AtomicU64,
+    pub defrag_dropped: AtomicU64,
+    // ipv6 fragments, left out of the streams
+    pub ipv6_fragments: AtomicU64,
+}
+
+impl WorkerStats {
+    pub fn new() -> WorkerStats {
+        WorkerStats {
+            enqueued: AtomicU64::new(0),
+            processed: AtomicU64::new(0),
+            tcp_streams: AtomicU64::new(0),
+            tcp_streams_total: AtomicU64::new(0),
+            udp_streams: AtomicU64::new(0),
+            udp_streams_total: AtomicU64::new(0),
+            icmp_flows: AtomicU64::new(0),
+            icmp_flows_total: AtomicU64::new(0),
+            icmp_errors: AtomicU64::new(0),
+            bad_checksum: AtomicU64::new(0),
+            arp_bindings: AtomicU64::new(0),
+            detect_success: AtomicU64::new(0),
+            detect_failed: AtomicU64::new(0),
+            dissect_bytes: AtomicU64::new(0),
+            dissect_errors: AtomicU64::new(0),
+            tcp_gaps: AtomicU64::new(0),
+            fragments: AtomicU64::new(0),
+            reassembled: AtomicU64::new(0),
+            defrag_dropped: AtomicU64::new(0),
+            ipv6_fragments: AtomicU64::new(0),
+        }
+    }
+
+    pub fn queue_depth(&self) -> u64 {
+        let enqueued = self.enqueued.load(Ordering::Relaxed);
+        let processed = self.processed.load(Ordering::Relaxed);
+        return enqueued.saturating_sub(processed);
+    }
+}
+
+struct Registry {
+    sources: Mutex<Vec<Arc<PacketSource>>>,
+    workers: Mutex<Vec<Arc<WorkerStats>>>,
+    // frames the sources could not decode, by DecodeError::index
+    decode_errors: Vec<AtomicU64>,
+    dump: AtomicBool,
+    running: AtomicBool,
+}
+
+lazy_static! {
+    static ref REGISTRY: Registry = Registry {
+        sources: Mutex::new(Vec::new()),
+        workers: Mutex::new(Vec::new()),
+        decode_errors: (0..DecodeError::COUNT).map(|_| AtomicU64::new(0)).collect(),
+        dump: AtomicBool::new(false),
+        running: AtomicBool::new(false),
+    };
+}
+
+pub const STATS_FILE: &str = "stats.log";
+
+pub fn register_source(source: Arc<PacketSource>) {
+    REGISTRY.sources.lock().unwrap().push(source);
+}
+
+pub fn register_worker() -> Arc<WorkerStats> {
+    let stats = Arc::new(WorkerStats::new());
+    REGISTRY.workers.lock().unwrap().push(stats.clone());
+    return stats;
+}
+
+pub fn count_decode_error(error: DecodeError) {
+    REGISTRY.decode_errors[error.index()].fetch_add(1, Ordering::Relaxed);
+}
+
+// every reason with its count so far
+pub fn decode_errors() -> Vec<(DecodeError, u64)> {
+    DecodeError::ALL
+        .iter()
+        .map(|error| {
+            (
+                *error,
+                REGISTRY.decode_errors[error.index()].load(Ordering::Relaxed),
+            )
+        })
+        .collect()
+}
+
+// only touches an atomic, safe to call from a signal handler
+pub fn request_dump() {
+    REGISTRY.dump.store(true, Ordering::SeqCst);
+}
+
+// a snapshot of all counters, one line per source and per worker
+pub fn report() -> String {
+    let mut out = String::new();
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    writeln!(out, "[{}]", now.as_secs()).unwrap();
+
+    for source in REGISTRY.sources.lock().unwrap().iter() {
+        let stats = source.stats();
+        writeln!(
+            out,
+            "source {} received {} dropped {} if_dropped {}",
+            source.name(),
+            stats.received,
+            stats.dropped,
+            stats.if_dropped
+        )
+        .unwrap();
+    }
+
+    // the reasons seen so far after the total
+    let errors = decode_errors();
+    let total: u64 = errors.iter().map(|&(_, n)| n).sum();
+    write!(out, "decode_errors {}", total).unwrap();
+    for &(error, n) in errors.iter().filter(|&&(_, n)| n > 0) {
+        write!(out, " {} {}", error.to_string(), n).unwrap();
+    }
+    writeln!(out).unwrap();
+
+    for (i, worker) in REGISTRY.workers.lock().unwrap().iter().enumerate() {
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        writeln!(
+            out,
+            "worker {} queue {} processed {} tcp_streams {}/{} udp_streams {}/{} \
+             icmp_flows {}/{} icmp_errors {} bad_checksum {} arp_bindings {} detect_success {} detect_failed {} dissect_bytes {} dissect_errors {} tcp_gaps {} \
+             fragments {} reassembled {} defrag_dropped {} ipv6_fragments {}",
+            i,
+            worker.queue_depth(),
+            load(&worker.processed),
+            load(&worker.tcp_streams),
+            load(&worker.tcp_streams_total),
+            load(&worker.udp_streams),
+            load(&worker.udp_streams_total),
+            load(&worker.icmp_flows),
+            load(&worker.icmp_flows_total),
+            load(&worker.icmp_errors),
+            load(&worker.bad_checksum),
+            load(&worker.arp_bindings),
+            load(&worker.detect_success),
+            load(&worker.detect_failed),
+            load(&worker.dissect_bytes),
+            load(&worker.dissect_errors),
+            load(&worker.tcp_gaps),
+            load(&worker.fragments),
+            load(&worker.reassembled),
+            load(&worker.defrag_dropped),
+            load(&worker.ipv6_fragments)
+        )
+        .unwrap();
+    }
+    return out;
+}
+
+// append a report to the stats log in the current directory (the workspace)
+pub fn write_report() {
+    let result = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(STATS_FILE)
+        .and_then(|mut file| file.write_all(report().as_bytes()));
+    if let Err(err) = result {
+        error!("write {} error {}", STATS_FILE, err);
+    }
+}
+
+// write a report every interval seconds (0 disables) and whenever one is requested
+pub fn start(interval: u64) {
+    if REGISTRY.running.swap(true, Ordering::SeqCst) {
+        return;
+    }
+
+    let cb = move || {
+        let mut elapsed = 0;
+        while REGISTRY.running.load(Ordering::SeqCst) {
+            thread::sleep(Duration::new(1, 0));
+            elapsed += 1;
+
+            let requested = REGISTRY.dump.swap(false, Ordering::SeqCst);
+            if requested || (interval > 0 && elapsed >= interval) {
+                write_report();
+                elapsed = 0;
+            }
+        }
+    };
+    thread::spawn(cb);
+    debug!("stats interval = {}", interval);
+}
+
+// stop the writer and record the final counters
+pub fn stop() {
+    if REGISTRY.running.swap(false, Ordering::SeqCst) {
+        write_report();
+    }
+}
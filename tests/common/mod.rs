@@ -0,0 +1,107 @@
+// packet builders shared by the tests, each file uses some of them
+#![allow(dead_code)]
+
+// ipv4 src -> dst carrying payload, ttl 64 and df, the checksum left 0
+pub fn ipv4(src: [u8; 4], dst: [u8; 4], proto: u8, payload: &[u8]) -> Vec<u8> {
+    let total = 20 + payload.len();
+    let mut ip = vec![
+        0x45,
+        0x00,
+        (total >> 8) as u8,
+        total as u8,
+        0x00,
+        0x01,
+        0x40,
+        0x00,
+        0x40,
+        proto,
+        0x00,
+        0x00,
+    ];
+    ip.extend_from_slice(&src);
+    ip.extend_from_slice(&dst);
+    ip.extend_from_slice(payload);
+    ip
+}
+
+// ipv6 2001:db8::src -> 2001:db8::dst carrying payload, hop limit 64
+pub fn ipv6(src: u8, dst: u8, next: u8, payload: &[u8]) -> Vec<u8> {
+    let len = payload.len();
+    let mut ip = vec![0x60, 0, 0, 0, (len >> 8) as u8, len as u8, next, 64];
+    ip.extend_from_slice(&[0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, src]);
+    ip.extend_from_slice(&[0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, dst]);
+    ip.extend_from_slice(payload);
+    ip
+}
+
+// the checksum left 0
+pub fn udp(src_port: u16, dst_port: u16, payload: &[u8]) -> Vec<u8> {
+    let len = 8 + payload.len();
+    let mut udp = vec![
+        (src_port >> 8) as u8,
+        src_port as u8,
+        (dst_port >> 8) as u8,
+        dst_port as u8,
+        (len >> 8) as u8,
+        len as u8,
+        0,
+        0,
+    ];
+    udp.extend_from_slice(payload);
+    udp
+}
+
+// a tcp header without options, the checksum left 0
+pub fn tcp(
+    src_port: u16,
+    dst_port: u16,
+    flags: u8,
+    seq: u32,
+    ack: u32,
+    window: u16,
+    payload: &[u8],
+) -> Vec<u8> {
+    let mut tcp = vec![
+        (src_port >> 8) as u8,
+        src_port as u8,
+        (dst_port >> 8) as u8,
+        dst_port as u8,
+        (seq >> 24) as u8,
+        (seq >> 16) as u8,
+        (seq >> 8) as u8,
+        seq as u8,
+        (ack >> 24) as u8,
+        (ack >> 16) as u8,
+        (ack >> 8) as u8,
+        ack as u8,
+        0x50,
+        flags,
+        (window >> 8) as u8,
+        window as u8,
+        0,
+        0,
+        0,
+        0,
+    ];
+    tcp.extend_from_slice(payload);
+    tcp
+}
+
+// 00:01:02:03:04:06 -> 00:01:02:03:04:05
+pub fn ethernet(eth_type: u16, payload: &[u8]) -> Vec<u8> {
+    let mut eth = vec![0, 1, 2, 3, 4, 5, 0, 1, 2, 3, 4, 6];
+    eth.push((eth_type >> 8) as u8);
+    eth.push(eth_type as u8);
+    eth.extend_from_slice(payload);
+    eth
+}
+
+// an 802.1q tag with priority 1, eth_type is the type after it
+pub fn vlan(vid: u16, eth_type: u16) -> Vec<u8> {
+    vec![
+        (vid >> 8) as u8 | 0x20,
+        vid as u8,
+        (eth_type >> 8) as u8,
+        eth_type as u8,
+    ]
+}
@@ -0,0 +1,51 @@
+extern crate layers;
+
+use layers::config::InterfaceConfig;
+use layers::daq::afpacket;
+use layers::daq::PacketSource;
+use layers::layer::packet::Packet;
+use std::collections::HashMap;
+use std::net::UdpSocket;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// needs CAP_NET_RAW: sudo cargo test --test test_afpacket -- --ignored
+#[test]
+#[ignore]
+fn test_fanout_loopback() {
+    let group = afpacket::init_fanout(&InterfaceConfig::new("lo"), 0, 2, "udp").unwrap();
+    let rings = group.rings();
+
+    let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let server_port = server.local_addr().unwrap().port();
+
+    let mut clients = Vec::new();
+    for _i in 0..16 {
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        for _j in 0..4 {
+            client.send_to(b"fanout", ("127.0.0.1", server_port)).unwrap();
+        }
+        clients.push(client.local_addr().unwrap().port());
+    }
+
+    // client port -> rings the flow was seen on
+    let mut seen: HashMap<u16, Vec<usize>> = HashMap::new();
+    // partially filled blocks are retired after the interface timeout
+    let deadline = Instant::now() + Duration::from_secs(3);
+    while Instant::now() < deadline {
+        for (i, ring) in rings.iter().enumerate() {
+            ring.poll(100, &mut |packet: Arc<Packet>| {
+                if packet.state & Packet::STATE_UDP > 0 && packet.dst_port == server_port {
+                    seen.entry(packet.src_port).or_insert_with(Vec::new).push(i);
+                }
+            });
+        }
+    }
+
+    for port in clients.iter() {
+        let rings = seen.get(port).expect("flow not captured");
+        assert!(rings.len() >= 4);
+        assert!(rings.iter().all(|i| *i == rings[0]));
+    }
+    assert!(group.stats().received > 0);
+}
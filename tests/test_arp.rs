@@ -0,0 +1,211 @@
+extern crate layers;
+
+use layers::config::ArpConfig;
+use layers::event::{Event, EventSink, FileSink};
+use layers::layer::dispatcher;
+use layers::layer::packet::Packet;
+use layers::layer::{ARPTable, BindingKey, LinkType};
+use layers::stats::WorkerStats;
+use std::env;
+use std::fs;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+const SECOND: u64 = 1000 * 1000 * 1000;
+
+// ethernet arp, sender ip at mac, target ip
+fn arp(operation: u8, mac: [u8; 6], sender: [u8; 4], target: [u8; 4]) -> Vec<u8> {
+    let mut data = vec![0xff; 6];
+    data.extend_from_slice(&mac);
+    data.extend_from_slice(&[0x08, 0x06]);
+    data.extend_from_slice(&[0x00, 0x01, 0x08, 0x00, 6, 4, 0x00, operation]);
+    data.extend_from_slice(&mac);
+    data.extend_from_slice(&sender);
+    data.extend_from_slice(&[0; 6]);
+    data.extend_from_slice(&target);
+    data
+}
+
+fn decode(data: &[u8], timestamp: u64) -> Arc<Packet> {
+    decode_on("test", data, timestamp)
+}
+
+fn decode_on(interface: &str, data: &[u8], timestamp: u64) -> Arc<Packet> {
+    let interface: Arc<str> = Arc::from(interface);
+    Packet::new(&interface, LinkType::EN10MB, timestamp, data)
+}
+
+// the frame with an 802.1q tag
+fn tagged(vid: u16, data: &[u8]) -> Vec<u8> {
+    let mut frame = data[..12].to_vec();
+    frame.extend_from_slice(&[0x81, 0x00, (vid >> 8) as u8, vid as u8]);
+    frame.extend_from_slice(&data[12..]);
+    frame
+}
+
+fn key(interface: &str, vlan: u16, ip: &str) -> BindingKey {
+    BindingKey {
+        interface: Arc::from(interface),
+        vlan: [vlan, 0],
+        ip: ip.parse().unwrap(),
+    }
+}
+
+const MAC_A: [u8; 6] = [0, 0x11, 0x22, 0x33, 0x44, 0x55];
+const MAC_B: [u8; 6] = [0, 0x66, 0x77, 0x88, 0x99, 0xaa];
+
+#[test]
+fn test_arp_decode() {
+    let packet = decode(&arp(1, MAC_A, [10, 0, 0, 1], [10, 0, 0, 2]), 0);
+    assert!(packet.valid());
+    assert_eq!(packet.state & Packet::STATE_ARP, Packet::STATE_ARP);
+    assert_eq!(packet.src_ip_str(), "10.0.0.1");
+    assert_eq!(packet.dst_ip_str(), "10.0.0.2");
+    assert_eq!(packet.src_mac(), "00:11:22:33:44:55");
+    assert_eq!(packet.arp().unwrap().operation(), 1);
+    assert!(!packet.arp().unwrap().is_gratuitous());
+
+    let packet = decode(&arp(1, MAC_A, [10, 0, 0, 1], [10, 0, 0, 1]), 0);
+    assert!(packet.arp().unwrap().is_gratuitous());
+
+    let data = arp(1, MAC_A, [10, 0, 0, 1], [10, 0, 0, 2]);
+    assert!(!decode(&data[..30], 0).valid());
+}
+
+#[test]
+fn test_arp_bindings() {
+    let stats = Arc::new(WorkerStats::new());
+    let mut table = ARPTable::new(stats.clone(), &ArpConfig::new());
+
+    let event = table.on_packet(&decode(&arp(1, MAC_A, [10, 0, 0, 1], [10, 0, 0, 2]), 0));
+    match event {
+        Some(Event::ArpNew { ref ip, mac, .. }) => {
+            assert_eq!(ip.to_string(), "10.0.0.1");
+            assert_eq!(mac, MAC_A);
+        }
+        _ => panic!("{:?}", event),
+    }
+    assert_eq!(stats.arp_bindings.load(Ordering::Relaxed), 1);
+    assert!(table.on_packet(&decode(&arp(2, MAC_A, [10, 0, 0, 1], [10, 0, 0, 2]), 1)).is_none());
+
+    let event = table.on_packet(&decode(&arp(2, MAC_B, [10, 0, 0, 1], [10, 0, 0, 2]), 2));
+    match event {
+        Some(Event::ArpChanged { old_mac, new_mac, .. }) => {
+            assert_eq!(old_mac, MAC_A);
+            assert_eq!(new_mac, MAC_B);
+        }
+        _ => panic!("{:?}", event),
+    }
+    assert_eq!(table.lookup(&key("test", 0, "10.0.0.1")), Some(MAC_B));
+    assert_eq!(
+        event.unwrap().to_string(),
+        "arp_changed test 10.0.0.1 was-at 00:11:22:33:44:55 is-at 00:66:77:88:99:aa"
+    );
+
+    // probes do not bind 0.0.0.0
+    assert!(table.on_packet(&decode(&arp(1, MAC_A, [0; 4], [10, 0, 0, 9]), 3)).is_none());
+    assert_eq!(table.len(), 1);
+}
+
+#[test]
+fn test_arp_storm() {
+    let mut config = ArpConfig::new();
+    config.storm_threshold = 3;
+    config.max_bindings = 1;
+    let mut table = ARPTable::new(Arc::new(WorkerStats::new()), &config);
+    let announce = arp(1, MAC_A, [10, 0, 0, 1], [10, 0, 0, 1]);
+
+    let mut storms = 0;
+    for i in 0..10 {
+        if let Some(Event::ArpStorm { count, .. }) = table.on_packet(&decode(&announce, i)) {
+            assert_eq!(count, 4);
+            storms += 1;
+        }
+    }
+    // once per window
+    assert_eq!(storms, 1);
+
+    let mut storms = 0;
+    for i in 0..5 {
+        let timestamp = 20 * SECOND + i;
+        if let Some(Event::ArpStorm { .. }) = table.on_packet(&decode(&announce, timestamp)) {
+            storms += 1;
+        }
+    }
+    assert_eq!(storms, 1);
+
+    // the table is full, the older binding makes room
+    let event = table.on_packet(&decode(&arp(1, MAC_B, [10, 0, 0, 2], [10, 0, 0, 1]), 30 * SECOND));
+    assert!(event.unwrap().name() == "arp_new");
+    assert_eq!(table.len(), 1);
+    assert!(table.lookup(&key("test", 0, "10.0.0.1")).is_none());
+}
+
+#[test]
+fn test_arp_segments() {
+    let mut table = ARPTable::new(Arc::new(WorkerStats::new()), &ArpConfig::new());
+    let a = arp(1, MAC_A, [10, 0, 0, 1], [10, 0, 0, 2]);
+    let b = arp(1, MAC_B, [10, 0, 0, 1], [10, 0, 0, 2]);
+
+    // the same private address behind two interfaces and two vlans is no spoofing
+    for round in 0..2 {
+        let packets = [
+            decode_on("eth0", &a, round),
+            decode_on("eth1", &b, round),
+            decode_on("eth0", &tagged(10, &b), round),
+            decode_on("eth0", &tagged(20, &a), round),
+        ];
+        for packet in packets.iter() {
+            let event = table.on_packet(packet);
+            match event {
+                Some(Event::ArpNew { .. }) if round == 0 => {}
+                None if round == 1 => {}
+                _ => panic!("{:?}", event),
+            }
+        }
+    }
+    assert_eq!(table.len(), 4);
+    assert_eq!(table.lookup(&key("eth0", 0, "10.0.0.1")), Some(MAC_A));
+    assert_eq!(table.lookup(&key("eth1", 0, "10.0.0.1")), Some(MAC_B));
+    assert_eq!(table.lookup(&key("eth0", 10, "10.0.0.1")), Some(MAC_B));
+    assert_ne!(
+        key("eth0", 10, "10.0.0.1").route_hash(),
+        key("eth0", 20, "10.0.0.1").route_hash()
+    );
+}
+
+#[test]
+fn test_arp_worker() {
+    // whatever the target or mac, the frames of a binding meet in one table
+    let first = decode(&arp(1, MAC_A, [10, 0, 0, 1], [10, 0, 0, 2]), 0);
+    let frames = [
+        arp(1, MAC_A, [10, 0, 0, 1], [10, 0, 0, 9]),
+        arp(2, MAC_B, [10, 0, 0, 1], [10, 0, 0, 3]),
+        arp(1, MAC_A, [10, 0, 0, 1], [10, 0, 0, 1]),
+    ];
+    for frame in frames.iter() {
+        let packet = decode(frame, 0);
+        for workers in 1..9 {
+            assert_eq!(
+                dispatcher::worker_index(&packet, workers),
+                dispatcher::worker_index(&first, workers)
+            );
+        }
+    }
+}
+
+#[test]
+fn test_file_sink() {
+    let path = env::temp_dir().join("layers_test_events.log");
+    let _ = fs::remove_file(&path);
+    let sink = FileSink::new(path.to_str().unwrap()).unwrap();
+    sink.on_event(&Event::ArpNew {
+        timestamp: 3 * SECOND + 5,
+        interface: Arc::from("eth0"),
+        ip: "10.0.0.1".parse().unwrap(),
+        mac: MAC_A,
+    });
+    let content = fs::read_to_string(&path).unwrap();
+    assert_eq!(content, "3.000000005 arp_new eth0 10.0.0.1 is-at 00:11:22:33:44:55\n");
+    let _ = fs::remove_file(&path);
+}
@@ -0,0 +1,138 @@
+extern crate layers;
+
+mod common;
+
+use layers::layer::checksum;
+use layers::layer::packet::Packet;
+use layers::layer::{Anomaly, IPProto, LinkType, TCPHeader};
+use std::net::IpAddr;
+use std::sync::Arc;
+
+// ipv4 10.0.0.1 -> 10.0.0.2 around the transport segment, both checksums filled in
+fn ipv4(proto: u8, segment: &[u8], checksum_at: usize) -> Vec<u8> {
+    let mut ip = common::ipv4([10, 0, 0, 1], [10, 0, 0, 2], proto, segment);
+    let sum = checksum::checksum(&ip[..20]);
+    ip[10] = (sum >> 8) as u8;
+    ip[11] = sum as u8;
+
+    let src: IpAddr = "10.0.0.1".parse().unwrap();
+    let dst: IpAddr = "10.0.0.2".parse().unwrap();
+    let pseudo = checksum::pseudo_header(&src, &dst, IPProto(proto), segment.len());
+    let sum = !checksum::fold(checksum::sum(segment, pseudo));
+    ip[20 + checksum_at] = (sum >> 8) as u8;
+    ip[20 + checksum_at + 1] = sum as u8;
+    ip
+}
+
+// 1234 -> 80 ack with a payload
+fn tcp(payload: &[u8]) -> Vec<u8> {
+    common::tcp(1234, 80, TCPHeader::ACK, 1000, 1, 0xffff, payload)
+}
+
+fn udp(payload: &[u8]) -> Vec<u8> {
+    common::udp(1234, 53, payload)
+}
+
+fn decode(data: &[u8]) -> Arc<Packet> {
+    let interface: Arc<str> = Arc::from("test");
+    Packet::new(&interface, LinkType::RAW, 0, data)
+}
+
+#[test]
+fn test_checksum() {
+    let header = [
+        0x45, 0x00, 0x00, 0x73, 0x00, 0x00, 0x40, 0x00, 0x40, 0x11, 0x00, 0x00, 0xc0, 0xa8, 0x00,
+        0x01, 0xc0, 0xa8, 0x00, 0xc7,
+    ];
+    assert_eq!(checksum::checksum(&header), 0xb861);
+
+    let mut header = header.to_vec();
+    header[10] = 0xb8;
+    header[11] = 0x61;
+    assert!(checksum::ipv4_header_valid(&header));
+    header[8] = 63;
+    assert!(!checksum::ipv4_header_valid(&header));
+
+    // odd length, the last byte is padded
+    assert_eq!(checksum::sum(&[0x01, 0x02, 0x03], 0), 0x0102 + 0x0300);
+}
+
+#[test]
+fn test_verify_tcp_udp() {
+    checksum::enable_verify(true);
+
+    let data = ipv4(6, &tcp(b"hello"), 16);
+    let packet = decode(&data);
+    assert!(packet.valid());
+    assert!(!packet.bad_checksum());
+
+    let mut bad = data.clone();
+    *bad.last_mut().unwrap() ^= 0xff;
+    let packet = decode(&bad);
+    assert!(packet.valid());
+    assert!(packet.bad_checksum());
+    assert_eq!(packet.anomalies, vec![Anomaly::TCPChecksum]);
+
+    let mut bad = data.clone();
+    bad[8] = 63;
+    assert_eq!(decode(&bad).anomalies, vec![Anomaly::IPv4Checksum]);
+
+    let data = ipv4(17, &udp(b"ping"), 6);
+    assert!(!decode(&data).bad_checksum());
+
+    let mut bad = data.clone();
+    bad[28] = b'P';
+    assert_eq!(decode(&bad).anomalies, vec![Anomaly::UDPChecksum]);
+
+    // no udp checksum at all
+    bad[26] = 0;
+    bad[27] = 0;
+    assert!(!decode(&bad).bad_checksum());
+}
+
+#[test]
+fn test_verify_offloaded() {
+    checksum::enable_verify(true);
+
+    // the sender left the pseudo header sum for the nic to complete
+    let segment = tcp(b"hello");
+    let mut data = ipv4(6, &segment, 16);
+    let src: IpAddr = "10.0.0.1".parse().unwrap();
+    let dst: IpAddr = "10.0.0.2".parse().unwrap();
+    let partial = checksum::fold(checksum::pseudo_header(
+        &src,
+        &dst,
+        IPProto::TCP,
+        segment.len(),
+    ));
+    data[36] = (partial >> 8) as u8;
+    data[37] = partial as u8;
+    assert!(!decode(&data).bad_checksum());
+
+    data[37] ^= 1;
+    assert!(decode(&data).bad_checksum());
+}
+
+#[test]
+fn test_verify_ipv6_udp() {
+    checksum::enable_verify(true);
+
+    let src: IpAddr = "2001:db8::1".parse().unwrap();
+    let dst: IpAddr = "2001:db8::2".parse().unwrap();
+    let mut segment = udp(b"ping");
+    let pseudo = checksum::pseudo_header(&src, &dst, IPProto::UDP, segment.len());
+    let sum = !checksum::fold(checksum::sum(&segment, pseudo));
+    segment[6] = (sum >> 8) as u8;
+    segment[7] = sum as u8;
+
+    let mut ip = vec![0x60, 0, 0, 0, 0, segment.len() as u8, 17, 64];
+    ip.extend_from_slice(&[0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+    ip.extend_from_slice(&[0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2]);
+    ip.extend_from_slice(&segment);
+    assert!(!decode(&ip).bad_checksum());
+
+    // zero is not allowed over ipv6
+    ip[46] = 0;
+    ip[47] = 0;
+    assert_eq!(decode(&ip).anomalies, vec![Anomaly::UDPChecksum]);
+}
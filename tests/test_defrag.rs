@@ -0,0 +1,135 @@
+extern crate layers;
+
+mod common;
+
+use common::{ethernet, ipv4, vlan};
+use layers::config::DefragConfig;
+use layers::layer::defrag::{Defragmenter, OverlapPolicy};
+use layers::layer::packet::Packet;
+use layers::layer::LinkType;
+use std::sync::Arc;
+
+const SECOND: u64 = 1000 * 1000 * 1000;
+
+// one ipv4 fragment 10.0.0.1 -> 10.0.0.2 of datagram id 7, offset in bytes
+fn fragment_ip(offset: usize, more: bool, data: &[u8]) -> Vec<u8> {
+    let flag_offset = (offset / 8) as u16 | if more { 0x2000 } else { 0 };
+    let mut ip = ipv4([10, 0, 0, 1], [10, 0, 0, 2], 17, data);
+    ip[5] = 0x07;
+    ip[6] = (flag_offset >> 8) as u8;
+    ip[7] = flag_offset as u8;
+    ip
+}
+
+fn fragment(offset: usize, more: bool, data: &[u8]) -> Arc<Packet> {
+    let interface: Arc<str> = Arc::from("test");
+    Packet::new(&interface, LinkType::RAW, 0, &fragment_ip(offset, more, data))
+}
+
+// the same fragment in an ethernet frame tagged with vid
+fn vlan_fragment(vid: u16, offset: usize, more: bool, data: &[u8]) -> Arc<Packet> {
+    let mut tagged = vlan(vid, 0x0800);
+    tagged.extend_from_slice(&fragment_ip(offset, more, data));
+    let frame = ethernet(0x8100, &tagged);
+    let interface: Arc<str> = Arc::from("test");
+    Packet::new(&interface, LinkType::EN10MB, 0, &frame)
+}
+
+fn defragmenter(policy: OverlapPolicy) -> Defragmenter {
+    let mut config = DefragConfig::new();
+    config.policy = policy;
+    Defragmenter::new(&config)
+}
+
+#[test]
+fn test_fragment_state() {
+    let udp = common::udp(1234, 53, &[b'a'; 24]);
+    let packet = fragment(0, true, &udp[..16]);
+    assert!(packet.valid());
+    assert_eq!(packet.state & Packet::STATE_FRAGMENT, Packet::STATE_FRAGMENT);
+    assert_eq!(packet.state & Packet::STATE_UDP, 0);
+}
+
+#[test]
+fn test_reassemble_out_of_order() {
+    let payload: Vec<u8> = (0..40u8).collect();
+    let udp = common::udp(1234, 53, &payload);
+    let mut defrag = defragmenter(OverlapPolicy::First);
+
+    assert!(defrag.on_fragment(&fragment(32, false, &udp[32..])).is_none());
+    assert!(defrag.on_fragment(&fragment(0, true, &udp[..16])).is_none());
+    let whole = defrag.on_fragment(&fragment(16, true, &udp[16..32])).unwrap();
+
+    assert!(whole.valid());
+    assert_eq!(whole.state & Packet::STATE_FRAGMENT, 0);
+    assert_eq!(whole.state & Packet::STATE_UDP, Packet::STATE_UDP);
+    assert_eq!(whole.dst_port, 53);
+    assert_eq!(whole.payload_slice(), &payload[..]);
+    assert_eq!(&*whole.interface, "test");
+    assert_eq!(defrag.memory(), 0);
+    assert_eq!(defrag.stats().reassembled, 1);
+}
+
+#[test]
+fn test_overlap_policy() {
+    let udp = common::udp(1234, 53, &[b'a'; 24]);
+    let overlap = [b'b'; 8];
+
+    // the forged fragment comes first, the original one at the same offset last
+    let expect = [
+        (OverlapPolicy::First, b'b'),
+        (OverlapPolicy::Last, b'a'),
+        (OverlapPolicy::Bsd, b'b'),
+        (OverlapPolicy::Linux, b'a'),
+        (OverlapPolicy::Windows, b'b'),
+    ];
+    for &(policy, byte) in expect.iter() {
+        let mut defrag = defragmenter(policy);
+        assert!(defrag.on_fragment(&fragment(16, true, &overlap)).is_none());
+        assert!(defrag.on_fragment(&fragment(0, true, &udp[..16])).is_none());
+        let whole = defrag.on_fragment(&fragment(16, false, &udp[16..])).unwrap();
+        assert_eq!(whole.payload_slice()[8], byte, "{:?}", policy);
+        assert_eq!(defrag.stats().overlaps, 8);
+    }
+}
+
+#[test]
+fn test_timeout_and_memory_limit() {
+    let udp = common::udp(1234, 53, &[b'a'; 24]);
+    let mut defrag = defragmenter(OverlapPolicy::First);
+    assert!(defrag.on_fragment(&fragment(0, true, &udp[..16])).is_none());
+    assert_eq!(defrag.memory(), 16);
+    assert_eq!(defrag.cleanup(60 * SECOND), 1);
+    assert_eq!(defrag.memory(), 0);
+    assert_eq!(defrag.stats().dropped, 1);
+
+    let mut config = DefragConfig::new();
+    config.memory_limit = 20;
+    let mut defrag = Defragmenter::new(&config);
+    assert!(defrag.on_fragment(&fragment(0, true, &udp[..16])).is_none());
+    // the pending datagram is evicted to make room
+    assert!(defrag.on_fragment(&fragment(16, true, &udp[16..])).is_none());
+    assert_eq!(defrag.memory(), 16);
+    assert_eq!(defrag.stats().dropped, 1);
+}
+
+#[test]
+fn test_vlans_kept_apart() {
+    let a = common::udp(1234, 53, &[b'a'; 24]);
+    let b = common::udp(1234, 53, &[b'b'; 24]);
+    let mut defrag = defragmenter(OverlapPolicy::First);
+
+    // the same addresses and id on two vlans are two datagrams
+    assert!(defrag.on_fragment(&vlan_fragment(10, 0, true, &a[..16])).is_none());
+    assert!(defrag.on_fragment(&vlan_fragment(20, 0, true, &b[..16])).is_none());
+    let whole = defrag.on_fragment(&vlan_fragment(20, 16, false, &b[16..])).unwrap();
+    assert_eq!(whole.payload_slice(), &[b'b'; 24][..]);
+    // and the tags stay with the rebuilt datagram
+    assert_eq!(whole.vlan_ids, vec![20]);
+    assert_eq!(whole.vlan_key(), [20, 0]);
+
+    let whole = defrag.on_fragment(&vlan_fragment(10, 16, false, &a[16..])).unwrap();
+    assert_eq!(whole.payload_slice(), &[b'a'; 24][..]);
+    assert_eq!(whole.vlan_key(), [10, 0]);
+    assert_eq!(defrag.stats().overlaps, 0);
+}
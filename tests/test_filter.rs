@@ -0,0 +1,12 @@
+extern crate layers;
+
+use layers::daq::pcap;
+
+#[test]
+fn test_compile_filter() {
+    let program = pcap::compile_filter(1, 65535, "tcp port 80").unwrap();
+    assert!(program.insns.len() > 0);
+
+    let err = pcap::compile_filter(1, 65535, "tcp port ((").err().unwrap();
+    assert!(err.starts_with("invalid bpf filter"));
+}
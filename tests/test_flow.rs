@@ -0,0 +1,142 @@
+extern crate layers;
+
+mod common;
+
+use layers::flow::{self, CloseReason, FlowCounters, FlowRecord, FlowSink, TCPFlowRecord};
+use layers::layer::icmp::ICMPError;
+use layers::layer::packet::Packet;
+use layers::layer::{IPProto, LinkType, TCPCounters, TCPHeader, TCPOutcome, TCPState};
+use std::env;
+use std::fs;
+use std::sync::{Arc, Mutex};
+
+const SECOND: u64 = 1000 * 1000 * 1000;
+
+// ipv4 10.0.0.1 -> 10.0.0.2 carrying the given transport header and payload
+fn ipv4(proto: u8, transport: &[u8]) -> Arc<Packet> {
+    let data = common::ipv4([10, 0, 0, 1], [10, 0, 0, 2], proto, transport);
+    let interface: Arc<str> = Arc::from("test");
+    Packet::new(&interface, LinkType::RAW, 0, &data)
+}
+
+fn tcp(flags: u8, payload: &[u8]) -> Arc<Packet> {
+    ipv4(6, &common::tcp(1234, 80, flags, 1, 0, 0x1000, payload))
+}
+
+fn udp(payload: &[u8]) -> Arc<Packet> {
+    ipv4(17, &common::udp(1234, 53, payload))
+}
+
+fn record(reason: CloseReason) -> FlowRecord {
+    let mut from_client = FlowCounters::default();
+    from_client.on_packet(&udp(b"query"));
+    let mut from_server = FlowCounters::default();
+    from_server.on_packet(&udp(b"answer"));
+    from_server.on_packet(&udp(b"answer"));
+    FlowRecord {
+        ip_proto: IPProto::UDP,
+        interface: Arc::from("eth0"),
+        client: "10.0.0.1".parse().unwrap(),
+        client_port: 1234,
+        server: "10.0.0.2".parse().unwrap(),
+        server_port: 53,
+        start: 3 * SECOND,
+        end: 4 * SECOND + 500,
+        from_client,
+        from_server,
+        protocol: "DNS".to_string(),
+        category: "Network".to_string(),
+        reason,
+        icmp_error: None,
+        tcp: None,
+    }
+}
+
+#[test]
+fn test_counters() {
+    let mut counters = FlowCounters::default();
+    counters.on_packet(&tcp(TCPHeader::SYN, &[]));
+    counters.on_packet(&tcp(TCPHeader::ACK | TCPHeader::PUSH, b"hello"));
+    assert_eq!(counters.packets, 2);
+    assert_eq!(counters.bytes, 40 + 45);
+    assert_eq!(flow::tcp_flags_to_string(counters.tcp_flags), "SP.");
+
+    let mut counters = FlowCounters::default();
+    counters.on_packet(&udp(b"ping"));
+    assert_eq!(counters.bytes, 32);
+    assert_eq!(flow::tcp_flags_to_string(counters.tcp_flags), "-");
+}
+
+#[test]
+fn test_record() {
+    let udp = record(CloseReason::Idle);
+    assert_eq!(udp.duration(), SECOND + 500);
+    assert_eq!(
+        udp.to_string(),
+        "udp eth0 10.0.0.1:1234 -> 10.0.0.2:53 protocol DNS category Network \
+         start 3.000000000 duration 1.000000500 reason idle \
+         client packets 1 bytes 33 server packets 2 bytes 68 icmp_error -"
+    );
+
+    let mut tcp = record(CloseReason::Finished);
+    tcp.ip_proto = IPProto::TCP;
+    tcp.icmp_error = Some(ICMPError {
+        icmp_type: 3,
+        code: 3,
+        ipv6: false,
+        reporter: "10.0.0.2".parse().unwrap(),
+        timestamp: 4 * SECOND,
+    });
+    tcp.from_client.tcp_flags = TCPHeader::SYN | TCPHeader::ACK | TCPHeader::FIN;
+    tcp.from_server.tcp_flags = TCPHeader::SYN | TCPHeader::ACK | TCPHeader::RST;
+    tcp.tcp = Some(TCPFlowRecord {
+        state: TCPState::Closed,
+        outcome: Some(TCPOutcome::Reset),
+        client_fingerprint: None,
+        server_fingerprint: None,
+        client_rtt: Some(1500 * 1000),
+        server_rtt: None,
+        client_counters: TCPCounters::default(),
+        server_counters: TCPCounters {
+            retransmissions: 2,
+            ..TCPCounters::default()
+        },
+    });
+    assert!(tcp.to_string().ends_with(
+        "reason finished client packets 1 bytes 33 server packets 2 bytes 68 \
+         icmp_error unreachable code 3 from 10.0.0.2 client_flags SF. server_flags SR. state closed outcome reset \
+         client_rtt_us 1500 server_rtt_us - client_fingerprint - server_fingerprint - \
+         client retransmissions 0 out_of_order 0 zero_windows 0 duplicate_acks 0 conflicting_overlaps 0 \
+         server retransmissions 2 out_of_order 0 zero_windows 0 duplicate_acks 0 conflicting_overlaps 0"
+    ));
+}
+
+struct Collect(Mutex<Vec<FlowRecord>>);
+
+impl FlowSink for Collect {
+    fn on_flow(&self, record: &FlowRecord) {
+        self.0.lock().unwrap().push(record.clone());
+    }
+}
+
+#[test]
+fn test_sinks() {
+    let sink = Arc::new(Collect(Mutex::new(Vec::new())));
+    flow::register_sink(sink.clone());
+    assert!(flow::enabled());
+    flow::emit(&record(CloseReason::Shutdown));
+    let records = sink.0.lock().unwrap();
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].reason, CloseReason::Shutdown);
+
+    let path = env::temp_dir().join("layers_test_flows.log");
+    let _ = fs::remove_file(&path);
+    let file = flow::FileSink::new(path.to_str().unwrap()).unwrap();
+    file.on_flow(&record(CloseReason::Reused));
+    let content = fs::read_to_string(&path).unwrap();
+    assert!(content.starts_with("4.000000500 udp eth0 10.0.0.1:1234 -> 10.0.0.2:53"));
+    assert!(content.ends_with(
+        "reason reused client packets 1 bytes 33 server packets 2 bytes 68 icmp_error -\n"
+    ));
+    let _ = fs::remove_file(&path);
+}
@@ -0,0 +1,26 @@
+extern crate layers;
+
+use layers::layer::tcp::http::message_start;
+use layers::layer::tcp::Direction;
+
+#[test]
+fn test_message_start() {
+    // the rest of a body cut by a gap, then the next request
+    let data = b"ody of the last one\r\nGET /index.html HTTP/1.1\r\nHost: a\r\n\r\n";
+    assert_eq!(message_start(data, Direction::Client), Some(21));
+    assert_eq!(
+        message_start(b"POST / HTTP/1.1\r\n", Direction::Client),
+        Some(0)
+    );
+    assert_eq!(
+        message_start(b"xGET / HTTP/1.1\r\n", Direction::Client),
+        None
+    );
+    // a method name alone is not a request line
+    assert_eq!(message_start(b"\nGETTER\n", Direction::Client), None);
+
+    let data = b"\r\n0\r\n\r\nHTTP/1.1 200 OK\r\n";
+    assert_eq!(message_start(data, Direction::Server), Some(7));
+    assert_eq!(message_start(data, Direction::Client), None);
+    assert_eq!(message_start(b"", Direction::Server), None);
+}
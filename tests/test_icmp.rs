@@ -0,0 +1,174 @@
+extern crate layers;
+
+mod common;
+
+use common::{ipv4, ipv6, udp};
+use layers::config::StreamConfig;
+use layers::layer::dispatcher;
+use layers::layer::packet::Packet;
+use layers::layer::{ICMPTracker, IPProto, LinkType};
+use layers::stats::WorkerStats;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+const ICMP: u8 = 1;
+const TCP: u8 = 6;
+const UDP: u8 = 17;
+const ICMPV6: u8 = 58;
+
+fn icmp(icmp_type: u8, code: u8, id: u16, sequence: u16, data: &[u8]) -> Vec<u8> {
+    let mut icmp = vec![
+        icmp_type,
+        code,
+        0,
+        0,
+        (id >> 8) as u8,
+        id as u8,
+        (sequence >> 8) as u8,
+        sequence as u8,
+    ];
+    icmp.extend_from_slice(data);
+    icmp
+}
+
+fn decode(data: &[u8], timestamp: u64) -> Arc<Packet> {
+    let interface: Arc<str> = Arc::from("test");
+    Packet::new(&interface, LinkType::RAW, timestamp, data)
+}
+
+fn echo(request: bool, sequence: u16, timestamp: u64) -> Arc<Packet> {
+    let (icmp_type, src, dst) = if request {
+        (8, [10, 0, 0, 1], [10, 0, 0, 2])
+    } else {
+        (0, [10, 0, 0, 2], [10, 0, 0, 1])
+    };
+    let data = ipv4(src, dst, ICMP, &icmp(icmp_type, 0, 77, sequence, b"abcd"));
+    decode(&data, timestamp)
+}
+
+#[test]
+fn test_icmp_echo() {
+    let packet = echo(true, 1, 0);
+    assert!(packet.valid());
+    assert_eq!(packet.state & Packet::STATE_ICMP, Packet::STATE_ICMP);
+    assert!(packet.is_echo_request());
+    assert!(!packet.is_echo_reply());
+    assert_eq!(packet.icmp_type_str(), "echo_request");
+    assert_eq!(packet.icmp().unwrap().id(), 77);
+    assert_eq!(packet.icmp().unwrap().sequence(), 1);
+    assert_eq!(packet.payload_slice(), b"abcd");
+    assert!(packet.icmp_quoted.is_none());
+
+    let data = ipv6(1, 2, ICMPV6, &icmp(129, 0, 5, 6, &[]));
+    let packet = decode(&data, 0);
+    assert!(packet.is_echo_reply());
+
+    // header cut off
+    let data = ipv4([10, 0, 0, 1], [10, 0, 0, 2], ICMP, &[8, 0, 0, 0]);
+    assert!(!decode(&data, 0).valid());
+}
+
+#[test]
+fn test_icmp_error_quote() {
+    // port unreachable for udp 10.0.0.1:1234 -> 10.0.0.2:53, quoting 8 bytes of udp
+    let quoted = ipv4(
+        [10, 0, 0, 1],
+        [10, 0, 0, 2],
+        UDP,
+        &udp(1234, 53, b"ping")[..8],
+    );
+    let data = ipv4([10, 0, 0, 2], [10, 0, 0, 1], ICMP, &icmp(3, 3, 0, 0, &quoted));
+    let packet = decode(&data, 5);
+    assert!(packet.valid());
+    let flow = packet.icmp_quoted.unwrap();
+    assert_eq!(flow.proto, IPProto::UDP);
+    assert_eq!(flow.src_ip.to_string(), "10.0.0.1");
+    assert_eq!(flow.dst_ip.to_string(), "10.0.0.2");
+    assert_eq!(flow.src_port, 1234);
+    assert_eq!(flow.dst_port, 53);
+
+    let error = packet.icmp_error().unwrap();
+    assert_eq!(error.reporter.to_string(), "10.0.0.2");
+    assert_eq!(error.timestamp, 5);
+    assert_eq!(error.to_string(), "unreachable code 3 from 10.0.0.2");
+
+    // icmpv6 time exceeded quoting tcp, truncated after the ports
+    let quoted = ipv6(1, 2, TCP, &[0x9c, 0x40, 0x00, 0x50]);
+    let data = ipv6(9, 1, ICMPV6, &icmp(3, 0, 0, 0, &quoted));
+    let packet = decode(&data, 0);
+    let flow = packet.icmp_quoted.unwrap();
+    assert_eq!(flow.proto, IPProto::TCP);
+    assert_eq!(flow.src_ip.to_string(), "2001:db8::1");
+    assert_eq!(flow.src_port, 40000);
+    assert_eq!(flow.dst_port, 80);
+    assert_eq!(packet.icmp_error().unwrap().type_string(), "time_exceeded");
+
+    // the same behind a hop-by-hop and a destination options header
+    let mut ext = vec![60, 0, 1, 4, 0, 0, 0, 0];
+    ext.extend_from_slice(&[TCP, 1, 1, 12, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    ext.extend_from_slice(&[0x9c, 0x40, 0x00, 0x50]);
+    let quoted = ipv6(1, 2, 0, &ext);
+    let data = ipv6(9, 1, ICMPV6, &icmp(1, 4, 0, 0, &quoted));
+    let flow = decode(&data, 0).icmp_quoted.unwrap();
+    assert_eq!(flow.proto, IPProto::TCP);
+    assert_eq!(flow.src_port, 40000);
+    assert_eq!(flow.dst_port, 80);
+
+    // cut inside the extension headers, the transport is unknown
+    let quoted = ipv6(1, 2, 0, &ext[..12]);
+    let data = ipv6(9, 1, ICMPV6, &icmp(1, 4, 0, 0, &quoted));
+    assert!(decode(&data, 0).icmp_quoted.is_none());
+
+    // a quote too short for an ip header
+    let data = ipv4([10, 0, 0, 2], [10, 0, 0, 1], ICMP, &icmp(11, 0, 0, 0, &[0x45, 0]));
+    assert!(decode(&data, 0).icmp_quoted.is_none());
+}
+
+#[test]
+fn test_echo_tracker() {
+    let stats = Arc::new(WorkerStats::new());
+    let mut tracker = ICMPTracker::new(stats.clone(), &StreamConfig::new());
+    let ms = 1000 * 1000;
+
+    tracker.on_packet(&echo(true, 1, 0));
+    tracker.on_packet(&echo(false, 1, 3 * ms));
+    tracker.on_packet(&echo(true, 2, 1000 * ms));
+    {
+        let flows = tracker.flows();
+        assert_eq!(flows.len(), 1);
+        let flow = flows[0];
+        assert_eq!(flow.client.to_string(), "10.0.0.1");
+        assert_eq!(flow.server.to_string(), "10.0.0.2");
+        assert_eq!(flow.id, 77);
+        assert_eq!(flow.requests, 2);
+        assert_eq!(flow.replies, 1);
+        assert_eq!(flow.rtt, Some(3 * ms));
+    }
+    assert_eq!(stats.icmp_flows.load(Ordering::Relaxed), 1);
+    assert_eq!(stats.icmp_flows_total.load(Ordering::Relaxed), 1);
+
+    // errors and other types are not echo flows
+    let data = ipv4([10, 0, 0, 2], [10, 0, 0, 1], ICMP, &icmp(5, 1, 0, 0, &[]));
+    tracker.on_packet(&decode(&data, 0));
+    assert_eq!(tracker.flows().len(), 1);
+
+    assert_eq!(tracker.flush(), 1);
+    assert_eq!(stats.icmp_flows.load(Ordering::Relaxed), 0);
+}
+
+#[test]
+fn test_icmp_error_worker() {
+    // the error comes from a router, the worker is picked by the flow it quotes
+    let quoted = ipv4([10, 0, 0, 1], [10, 0, 0, 2], UDP, &udp(1234, 53, b"ping"));
+    let stream = decode(&quoted, 0);
+    for &reporter in [[10, 0, 0, 2], [192, 168, 7, 7], [172, 16, 0, 1]].iter() {
+        let data = ipv4(reporter, [10, 0, 0, 1], ICMP, &icmp(3, 3, 0, 0, &quoted));
+        let error = decode(&data, 0);
+        for workers in 1..9 {
+            assert_eq!(
+                dispatcher::worker_index(&error, workers),
+                dispatcher::worker_index(&stream, workers)
+            );
+        }
+    }
+}
@@ -0,0 +1,95 @@
+extern crate layers;
+
+mod common;
+
+use common::{ipv6, udp};
+use layers::layer::packet::Packet;
+use layers::layer::{LinkType, StreamID};
+use std::net::IpAddr;
+use std::sync::Arc;
+
+const HOPOPTS: u8 = 0;
+const FRAGMENT: u8 = 44;
+const DSTOPTS: u8 = 60;
+const UDP: u8 = 17;
+
+// ipv6 2001:db8::1 -> 2001:db8::2, extension headers then udp 1234 -> 53 "ping"
+fn datagram(exts: &[(u8, Vec<u8>)]) -> Vec<u8> {
+    let mut payload = Vec::new();
+    for &(_, ref ext) in exts.iter() {
+        payload.extend_from_slice(ext);
+    }
+    payload.extend_from_slice(&udp(1234, 53, b"ping"));
+
+    let next = if exts.is_empty() { UDP } else { exts[0].0 };
+    ipv6(1, 2, next, &payload)
+}
+
+// 8 bytes options header pointing at next
+fn options(next: u8) -> Vec<u8> {
+    vec![next, 0, 1, 4, 0, 0, 0, 0]
+}
+
+fn fragment(next: u8, offset: u16, more: bool) -> Vec<u8> {
+    let value = offset | more as u16;
+    vec![next, 0, (value >> 8) as u8, value as u8, 0, 0, 0, 7]
+}
+
+fn decode(data: &[u8]) -> Arc<Packet> {
+    let interface: Arc<str> = Arc::from("test");
+    Packet::new(&interface, LinkType::RAW, 0, data)
+}
+
+#[test]
+fn test_ipv6_udp() {
+    let packet = decode(&datagram(&[]));
+    assert!(packet.valid());
+    assert_eq!(packet.state & Packet::STATE_IPV6, Packet::STATE_IPV6);
+    assert_eq!(packet.state & Packet::STATE_UDP, Packet::STATE_UDP);
+    assert_eq!(packet.src_ip_str(), "2001:db8::1");
+    assert_eq!(packet.dst_ip_str(), "2001:db8::2");
+    assert_eq!(packet.src_port, 1234);
+    assert_eq!(packet.dst_port, 53);
+    assert_eq!(packet.ip_layer_len, 40 + 12);
+    assert_eq!(packet.payload_slice(), b"ping");
+}
+
+#[test]
+fn test_ipv6_extension_headers() {
+    let exts = vec![
+        (HOPOPTS, options(DSTOPTS)),
+        (DSTOPTS, options(FRAGMENT)),
+        (FRAGMENT, fragment(UDP, 0, false)),
+    ];
+    let packet = decode(&datagram(&exts));
+    assert!(packet.valid());
+    assert_eq!(packet.dst_port, 53);
+    assert_eq!(packet.payload_slice(), b"ping");
+    // an atomic fragment is the whole datagram
+    assert_eq!(packet.state & Packet::STATE_IPV6_FRAGMENT, 0);
+
+    // fragments do not carry a usable transport header
+    let packet = decode(&datagram(&[(FRAGMENT, fragment(UDP, 0, true))]));
+    assert!(packet.valid());
+    assert_eq!(packet.state & Packet::STATE_UDP, 0);
+    assert!(packet.state & Packet::STATE_IPV6_FRAGMENT > 0);
+
+    let packet = decode(&datagram(&[(FRAGMENT, fragment(UDP, 1448, false))]));
+    assert_eq!(packet.state & Packet::STATE_UDP, 0);
+    assert!(packet.state & Packet::STATE_IPV6_FRAGMENT > 0);
+
+    // extension header longer than the datagram
+    let mut data = datagram(&[(HOPOPTS, options(UDP))]);
+    data[41] = 8;
+    assert!(!decode(&data).valid());
+}
+
+#[test]
+fn test_stream_id_v6() {
+    let a: IpAddr = "2001:db8::1".parse().unwrap();
+    let b: IpAddr = "2001:db8::2".parse().unwrap();
+    let v4: IpAddr = "10.0.0.1".parse().unwrap();
+
+    assert_eq!(StreamID::new(a, b, 1234, 53), StreamID::new(b, a, 53, 1234));
+    assert!(StreamID::new(a, b, 1234, 53) != StreamID::new(v4, b, 1234, 53));
+}
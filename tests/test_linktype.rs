@@ -0,0 +1,66 @@
+extern crate layers;
+
+mod common;
+
+use layers::layer::packet::Packet;
+use layers::layer::LinkType;
+use std::sync::Arc;
+
+// ipv4 10.0.0.1:1234 -> 10.0.0.2:53 udp "ping"
+fn ip_datagram() -> Vec<u8> {
+    let udp = common::udp(1234, 53, b"ping");
+    common::ipv4([10, 0, 0, 1], [10, 0, 0, 2], 17, &udp)
+}
+
+fn decode(linktype: LinkType, header: &[u8]) -> Arc<Packet> {
+    let mut frame = header.to_vec();
+    frame.extend_from_slice(&ip_datagram());
+    let interface: Arc<str> = Arc::from("test");
+    Packet::new(&interface, linktype, 0, &frame)
+}
+
+fn assert_udp(packet: &Arc<Packet>) {
+    assert!(packet.valid());
+    assert_eq!(packet.state & Packet::STATE_UDP, Packet::STATE_UDP);
+    assert_eq!(packet.src_port, 1234);
+    assert_eq!(packet.dst_port, 53);
+    assert_eq!(packet.payload_slice(), b"ping");
+}
+
+#[test]
+fn test_linux_sll() {
+    let sll = [
+        0x00, 0x00, 0x00, 0x01, 0x00, 0x06, 0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x00, 0x00, 0x08,
+        0x00,
+    ];
+    assert_udp(&decode(LinkType::LINUX_SLL, &sll));
+
+    let sll2 = [
+        0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x00, 0x01, 0x00, 0x06, 0x00, 0x11, 0x22,
+        0x33, 0x44, 0x55, 0x00, 0x00,
+    ];
+    assert_udp(&decode(LinkType::LINUX_SLL2, &sll2));
+}
+
+#[test]
+fn test_raw_and_loopback() {
+    assert_udp(&decode(LinkType::RAW, &[]));
+    assert_udp(&decode(LinkType::LINKTYPE_RAW, &[]));
+
+    let packet = decode(LinkType::RAW, &[]);
+    assert!(packet.ethernet().is_none());
+    assert_eq!(packet.src_mac(), "");
+
+    // written on little and big endian hosts
+    assert_udp(&decode(LinkType::NULL, &[0x02, 0x00, 0x00, 0x00]));
+    assert_udp(&decode(LinkType::NULL, &[0x00, 0x00, 0x00, 0x02]));
+    assert_udp(&decode(LinkType::LOOP, &[0x00, 0x00, 0x00, 0x02]));
+}
+
+#[test]
+fn test_short_link_header() {
+    let interface: Arc<str> = Arc::from("test");
+    let data = [0u8; 8];
+    let packet = Packet::new(&interface, LinkType::LINUX_SLL, 0, &data);
+    assert!(!packet.valid());
+}
@@ -0,0 +1,213 @@
+extern crate layers;
+
+mod common;
+
+use layers::daq::{MemorySource, PacketSource};
+use layers::event::{self, Event, EventSink};
+use layers::layer::packet::Packet;
+use layers::layer::{Anomaly, BufferPool, DecodeError, LinkType, TCPHeader};
+use layers::stats;
+use std::sync::{Arc, Mutex};
+
+// ipv4 10.0.0.1 -> 10.0.0.2 carrying the tcp segment
+fn ipv4_tcp(tcp: &[u8]) -> Vec<u8> {
+    common::ipv4([10, 0, 0, 1], [10, 0, 0, 2], 6, tcp)
+}
+
+// 1234 -> 53 with the length field as given
+fn udp(len: u16, payload: &[u8]) -> Vec<u8> {
+    let mut udp = common::udp(1234, 53, payload);
+    udp[4] = (len >> 8) as u8;
+    udp[5] = len as u8;
+    udp
+}
+
+// 1234 -> 80, seq 1000, syn ack, header words as given
+fn tcp(words: u8, payload: &[u8]) -> Vec<u8> {
+    let flags = TCPHeader::SYN | TCPHeader::ACK;
+    let mut tcp = common::tcp(1234, 80, flags, 1000, 0, 0xffff, payload);
+    tcp[12] = words << 4;
+    tcp
+}
+
+fn decode(data: &[u8]) -> Arc<Packet> {
+    let interface: Arc<str> = Arc::from("test");
+    Packet::new(&interface, LinkType::RAW, 0, data)
+}
+
+#[test]
+fn test_header_views() {
+    let data = ipv4_tcp(&tcp(5, b"hello"));
+    let packet = decode(&data);
+    assert!(packet.valid());
+    assert_eq!(packet.data(), &data[..]);
+    assert!(packet.ethernet().is_none());
+    assert!(packet.udp().is_none());
+
+    let ip = packet.ipv4().unwrap();
+    assert_eq!(ip.total_length() as usize, data.len());
+    assert_eq!(ip.ttl(), 64);
+    assert_eq!(ip.src().to_string(), "10.0.0.1");
+
+    let tcp = packet.tcp().unwrap();
+    assert_eq!(tcp.src_port(), 1234);
+    assert_eq!(tcp.dst_port(), 80);
+    assert_eq!(tcp.seq(), 1000);
+    assert_eq!(tcp.flags(), TCPHeader::SYN | TCPHeader::ACK);
+    assert_eq!(tcp.window(), 0xffff);
+    assert_eq!(packet.payload_slice(), b"hello");
+    assert_eq!(packet.ip_layer_slice(), &data[..]);
+}
+
+#[test]
+fn test_truncated_tcp() {
+    // ip length covers only part of the tcp header
+    let packet = decode(&ipv4_tcp(&tcp(5, &[])[..12]));
+    assert!(!packet.valid());
+    assert_eq!(packet.error, Some(DecodeError::TCPTruncated));
+    assert!(packet.tcp().is_none());
+
+    // data offset below the fixed header
+    let packet = decode(&ipv4_tcp(&tcp(2, b"hello")));
+    assert_eq!(packet.error, Some(DecodeError::TCPDataOffset));
+
+    // data offset past the end of the segment
+    let packet = decode(&ipv4_tcp(&tcp(15, b"hello")));
+    assert_eq!(packet.error, Some(DecodeError::TCPDataOffset));
+
+    assert!(TCPHeader::new(&[0u8; 19]).is_none());
+}
+
+#[test]
+fn test_buffer_pool() {
+    let interface: Arc<str> = Arc::from("test");
+    let pool = BufferPool::new(2);
+    let data = ipv4_tcp(&tcp(5, b"hello"));
+
+    let a = Packet::with_pool(&interface, LinkType::RAW, 0, &data, &pool);
+    let b = Packet::with_pool(&interface, LinkType::RAW, 0, &data, &pool);
+    let c = Packet::with_pool(&interface, LinkType::RAW, 0, &data, &pool);
+    assert_eq!(a.payload_slice(), b"hello");
+    assert_eq!(pool.free_len(), 0);
+
+    // buffers come back with the last reference, beyond the limit they are freed
+    let held = a.clone();
+    drop(a);
+    assert_eq!(pool.free_len(), 0);
+    drop(held);
+    drop(b);
+    drop(c);
+    assert_eq!(pool.free_len(), 2);
+
+    let d = Packet::with_pool(&interface, LinkType::RAW, 0, &data, &pool);
+    assert_eq!(pool.free_len(), 1);
+    assert!(d.valid());
+    assert_eq!(d.data(), &data[..]);
+}
+
+#[test]
+fn test_ip_decode_errors() {
+    let data = ipv4_tcp(&tcp(5, b"hello"));
+    assert_eq!(decode(&data).error, None);
+
+    // ihl of 4 words
+    let mut bad = data.clone();
+    bad[0] = 0x44;
+    assert_eq!(decode(&bad).error, Some(DecodeError::IPv4HeaderLen));
+
+    // total length shorter than the header
+    let mut bad = data.clone();
+    bad[3] = 16;
+    assert_eq!(decode(&bad).error, Some(DecodeError::IPLength));
+
+    // cut by the snap length
+    assert_eq!(decode(&data[..30]).error, Some(DecodeError::IPTruncated));
+    assert_eq!(decode(&data[..10]).error, Some(DecodeError::IPTruncated));
+
+    let interface: Arc<str> = Arc::from("test");
+    let packet = Packet::new(&interface, LinkType(9999), 0, &data);
+    assert_eq!(packet.error, Some(DecodeError::UnsupportedLinkType));
+}
+
+#[test]
+fn test_anomalies() {
+    let packet = decode(&ipv4_tcp(&tcp(5, b"hello")));
+    assert!(packet.anomalies.is_empty());
+
+    let mut data = ipv4_tcp(&tcp(5, &[]));
+    data[8] = 0;
+    data[6] |= 0x80;
+    // syn fin
+    data[33] = 0x03;
+    let packet = decode(&data);
+    assert!(packet.valid());
+    assert_eq!(
+        packet.anomalies,
+        vec![
+            Anomaly::TTLZero,
+            Anomaly::IPv4ReservedFlag,
+            Anomaly::TCPFlags
+        ]
+    );
+
+    // udp length shorter than the ip payload
+    let mut data = ipv4_tcp(&udp(10, b"ping"));
+    data[9] = 0x11;
+    let packet = decode(&data);
+    assert!(packet.valid());
+    assert_eq!(packet.anomalies, vec![Anomaly::UDPLengthMismatch]);
+    // the bytes past the udp length are not payload
+    assert_eq!(packet.payload_slice(), b"pi");
+
+    // longer is an error
+    let mut data = ipv4_tcp(&udp(40, b"ping"));
+    data[9] = 0x11;
+    assert_eq!(decode(&data).error, Some(DecodeError::UDPLength));
+
+    // so is one shorter than the udp header
+    let mut data = ipv4_tcp(&udp(4, b"ping"));
+    data[9] = 0x11;
+    assert_eq!(decode(&data).error, Some(DecodeError::UDPLength));
+}
+
+struct Collect(Mutex<Vec<Event>>);
+
+impl EventSink for Collect {
+    fn on_event(&self, event: &Event) {
+        self.0.lock().unwrap().push(event.clone());
+    }
+}
+
+#[test]
+fn test_decode_error_stats_and_events() {
+    let sink = Arc::new(Collect(Mutex::new(Vec::new())));
+    event::register_sink(sink.clone());
+    event::enable_anomalies(true);
+
+    let count = |reason: DecodeError| {
+        stats::decode_errors()
+            .iter()
+            .find(|&&(error, _)| error == reason)
+            .map_or(0, |&(_, n)| n)
+    };
+    let before = count(DecodeError::TCPDataOffset);
+
+    let source = MemorySource::with_linktype("mem0", LinkType::RAW);
+    source.push_frame(1, &ipv4_tcp(&tcp(2, b"hello")));
+    let mut data = ipv4_tcp(&tcp(5, b"hello"));
+    data[8] = 0;
+    source.push_frame(2, &data);
+
+    let delivered = Mutex::new(0);
+    source.run(&|_packet: Arc<Packet>| *delivered.lock().unwrap() += 1);
+    event::enable_anomalies(false);
+
+    assert_eq!(*delivered.lock().unwrap(), 1);
+    assert!(count(DecodeError::TCPDataOffset) > before);
+    assert!(stats::report().contains("tcp_bad_offset"));
+
+    let events = sink.0.lock().unwrap();
+    let names: Vec<String> = events.iter().map(|event| event.to_string()).collect();
+    assert!(names.contains(&"decode_error mem0 tcp_bad_offset".to_string()));
+    assert!(names.contains(&"anomaly mem0 10.0.0.1 -> 10.0.0.2 ttl_zero".to_string()));
+}
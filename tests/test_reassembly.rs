@@ -0,0 +1,316 @@
+extern crate layers;
+
+use layers::config::StreamConfig;
+use layers::layer::defrag::OverlapPolicy;
+use layers::layer::{AckQueue, Reassembled, Reassembler, ReassemblyMemory};
+use std::sync::Arc;
+
+const SECOND: u64 = 1000 * 1000 * 1000;
+
+// what came out of a reassembler, gaps as their length in brackets
+struct Output {
+    reassembler: Reassembler,
+    data: Vec<u8>,
+    // time of the next segment
+    now: u64,
+}
+
+impl Output {
+    fn new(
+        seq: u32,
+        policy: OverlapPolicy,
+        flow_limit: usize,
+        memory: &Arc<ReassemblyMemory>,
+    ) -> Output {
+        let mut config = StreamConfig::new();
+        config.overlap_policy = policy;
+        config.flow_memory_limit = flow_limit;
+        config.gap_timeout = 5;
+        Output {
+            reassembler: Reassembler::new(seq, &config, memory.clone()),
+            data: Vec::new(),
+            now: 0,
+        }
+    }
+
+    fn insert(&mut self, seq: u32, payload: &[u8]) {
+        let data = &mut self.data;
+        self.reassembler
+            .insert(seq, payload, self.now, &mut |chunk| append(data, chunk));
+    }
+
+    fn expire(&mut self) {
+        let data = &mut self.data;
+        self.reassembler
+            .expire(self.now, &mut |chunk| append(data, chunk));
+    }
+
+    fn flush(&mut self) {
+        let data = &mut self.data;
+        self.reassembler.flush(&mut |chunk| append(data, chunk));
+    }
+}
+
+fn append(data: &mut Vec<u8>, chunk: Reassembled) {
+    match chunk {
+        Reassembled::Data(bytes) => data.extend_from_slice(bytes),
+        Reassembled::Gap(len) => data.extend_from_slice(format!("[{}]", len).as_bytes()),
+    }
+}
+
+fn output(seq: u32, policy: OverlapPolicy) -> Output {
+    Output::new(seq, policy, 1024, &Arc::new(ReassemblyMemory::new(1024)))
+}
+
+#[test]
+fn test_out_of_order() {
+    let mut out = output(1000, OverlapPolicy::First);
+    out.insert(1000, b"GET ");
+    out.insert(1009, b"HTTP/1.1");
+    out.insert(1005, b"/ ");
+    assert_eq!(out.data, b"GET ");
+    assert_eq!(out.reassembler.buffered(), 10);
+
+    out.insert(1004, b"/");
+    out.insert(1007, b"x ");
+    assert_eq!(out.data, b"GET // x HTTP/1.1");
+    assert_eq!(out.reassembler.next_seq(), 1017);
+    assert_eq!(out.reassembler.buffered(), 0);
+}
+
+#[test]
+fn test_retransmission() {
+    let mut out = output(1, OverlapPolicy::Last);
+    out.insert(1, b"abcd");
+    // sent again, whole or partly, after being handed on
+    out.insert(1, b"abcd");
+    out.insert(3, b"cdef");
+    out.insert(2, b"X");
+    assert_eq!(out.data, b"abcdef");
+
+    // and while still buffered
+    out.insert(9, b"ij");
+    out.insert(9, b"ij");
+    out.insert(7, b"gh");
+    assert_eq!(out.data, b"abcdefghij");
+}
+
+#[test]
+fn test_wraparound() {
+    let isn = 0xffff_fff0;
+    let mut out = output(isn, OverlapPolicy::First);
+    out.insert(isn, b"0123456789");
+    // past the wrap, ahead of the hole across it
+    out.insert(4, b"klmn");
+    out.insert(isn.wrapping_add(10), b"abcdef");
+    assert_eq!(out.data, b"0123456789abcdef");
+    out.insert(0, b"ghij");
+    assert_eq!(out.data, b"0123456789abcdefghijklmn");
+    assert_eq!(out.reassembler.next_seq(), 8);
+
+    // half the sequence space behind is old data
+    out.insert(0x8000_0010, b"old");
+    assert_eq!(out.reassembler.buffered(), 0);
+    out.insert(8, b"o");
+    assert_eq!(out.data, b"0123456789abcdefghijklmno");
+}
+
+#[test]
+fn test_overlap_policy() {
+    // bytes 10..20 buffered first, then a segment starting before them and one
+    // at the same place, before the hole is filled
+    let cases = [
+        (OverlapPolicy::First, "aaaaacccccbbbbbbbbbb"),
+        (OverlapPolicy::Last, "aaaaacccccdddddddddd"),
+        (OverlapPolicy::Bsd, "aaaaaccccccccccbbbbb"),
+        (OverlapPolicy::Linux, "aaaaaccccccccccddddd"),
+        (OverlapPolicy::Windows, "aaaaacccccbbbbbbbbbb"),
+    ];
+    for &(policy, expect) in cases.iter() {
+        let mut out = output(100, policy);
+        out.insert(110, b"bbbbbbbbbb");
+        out.insert(105, b"cccccccccc");
+        out.insert(110, b"dddddddddd");
+        out.insert(100, b"aaaaa");
+        assert_eq!(String::from_utf8_lossy(&out.data), expect, "{:?}", policy);
+        assert_eq!(out.reassembler.buffered(), 0);
+    }
+
+    // windows takes a new segment covering an old one from before it
+    let mut out = output(100, OverlapPolicy::Windows);
+    out.insert(105, b"bb");
+    out.insert(103, b"cccccc");
+    out.insert(100, b"aaa");
+    assert_eq!(out.data, b"aaacccccc");
+}
+
+#[test]
+fn test_memory_limits() {
+    let memory = Arc::new(ReassemblyMemory::new(1024));
+    let mut out = Output::new(0, OverlapPolicy::First, 8, &memory);
+    out.insert(4, b"efgh");
+    out.insert(10, b"klmn");
+    assert_eq!(memory.used(), 8);
+
+    // over the flow limit, the oldest hole is given up on
+    out.insert(20, b"uv");
+    assert_eq!(out.data, b"[4]efgh");
+    assert_eq!(out.reassembler.buffered(), 6);
+    out.insert(8, b"ij");
+    assert_eq!(out.data, b"[4]efghijklmn");
+
+    // the pieces left go out at the end, past their holes
+    out.flush();
+    assert_eq!(out.data, b"[4]efghijklmn[6]uv");
+    assert_eq!(memory.used(), 0);
+
+    // another flow holds the shared memory, this one cannot buffer at all
+    let memory = Arc::new(ReassemblyMemory::new(6));
+    let mut other = Output::new(0, OverlapPolicy::First, 1024, &memory);
+    other.insert(10, b"xxxxxx");
+    let mut out = Output::new(0, OverlapPolicy::First, 1024, &memory);
+    out.insert(0, b"ab");
+    out.insert(4, b"ef");
+    assert_eq!(out.data, b"ab[2]ef");
+
+    drop(other);
+    assert_eq!(memory.used(), 0);
+}
+
+#[test]
+fn test_gap_timeout() {
+    let mut out = output(0, OverlapPolicy::First);
+    out.insert(0, b"ab");
+    out.now = SECOND;
+    out.insert(4, b"ef");
+    out.now = 3 * SECOND;
+    out.insert(8, b"ij");
+    out.now = 5 * SECOND;
+    out.expire();
+    assert_eq!(out.data, b"ab");
+
+    // the hole opened when ef was buffered, the next one waits from then on
+    out.now = 6 * SECOND;
+    out.expire();
+    assert_eq!(out.data, b"ab[2]ef");
+    out.now = 10 * SECOND;
+    out.insert(20, b"uv");
+    assert_eq!(out.data, b"ab[2]ef");
+    out.now = 11 * SECOND;
+    out.insert(21, b"v");
+    assert_eq!(out.data, b"ab[2]ef[2]ij");
+
+    // filled in time, nothing is lost
+    out.insert(10, b"klmnopqrst");
+    assert_eq!(out.data, b"ab[2]ef[2]ijklmnopqrstuv");
+    out.now = 30 * SECOND;
+    out.expire();
+    assert_eq!(out.data, b"ab[2]ef[2]ijklmnopqrstuv");
+}
+
+#[test]
+fn test_late_syn() {
+    // a syn reordered behind the first segment moves the start
+    let mut out = output(500, OverlapPolicy::First);
+    out.reassembler.on_syn(99);
+    out.insert(100, b"abc");
+    assert_eq!(out.data, b"abc");
+
+    // not once data was handed on
+    out.reassembler.on_syn(0);
+    out.insert(103, b"d");
+    assert_eq!(out.data, b"abcd");
+}
+
+#[test]
+fn test_ack_queue() {
+    let mut config = StreamConfig::new();
+    config.flow_memory_limit = 8;
+    config.gap_timeout = 5;
+    let memory = Arc::new(ReassemblyMemory::new(1024));
+    let mut queue = AckQueue::new(&config, memory.clone());
+    let mut data = Vec::new();
+
+    // a request of 4 bytes, a hole of 2 and 3 more bytes, ending at seq 109
+    queue.push(Reassembled::Data(b"GET "), 0);
+    queue.push(Reassembled::Gap(2), 0);
+    queue.push(Reassembled::Data(b"abc"), 0);
+    queue.set_end_seq(109);
+    assert_eq!(memory.used(), 7);
+
+    queue.ack(100, 0, &mut |chunk| append(&mut data, chunk));
+    assert!(data.is_empty());
+    queue.ack(102, 0, &mut |chunk| append(&mut data, chunk));
+    assert_eq!(data, b"GE");
+    // reaching into the hole releases it whole
+    queue.ack(105, 0, &mut |chunk| append(&mut data, chunk));
+    assert_eq!(data, b"GET [2]");
+    // old acks do nothing
+    queue.ack(90, 0, &mut |chunk| append(&mut data, chunk));
+    queue.ack(109, 0, &mut |chunk| append(&mut data, chunk));
+    assert_eq!(data, b"GET [2]abc");
+    assert_eq!(memory.used(), 0);
+
+    // past the limit the oldest data goes out unacknowledged
+    data.clear();
+    queue.push(Reassembled::Data(b"12345"), SECOND);
+    queue.push(Reassembled::Data(b"6789"), SECOND);
+    queue.set_end_seq(118);
+    queue.expire(SECOND, &mut |chunk| append(&mut data, chunk));
+    assert_eq!(data, b"12345");
+    assert_eq!(memory.used(), 4);
+
+    // and anything held for the timeout, when the other side is not captured
+    queue.expire(5 * SECOND, &mut |chunk| append(&mut data, chunk));
+    assert_eq!(data, b"12345");
+    queue.expire(6 * SECOND, &mut |chunk| append(&mut data, chunk));
+    assert_eq!(data, b"123456789");
+    assert_eq!(memory.used(), 0);
+}
+
+#[test]
+fn test_ack_queue_progress() {
+    let mut config = StreamConfig::new();
+    config.gap_timeout = 5;
+    let mut queue = AckQueue::new(&config, Arc::new(ReassemblyMemory::new(1024)));
+    let mut data = Vec::new();
+
+    // a bulk transfer, 4 bytes a second with the last ones always in flight
+    let mut seq = 0u32;
+    for t in 0..20 {
+        let now = t * SECOND;
+        queue.push(Reassembled::Data(b"abcd"), now);
+        seq += 4;
+        queue.set_end_seq(seq);
+        queue.ack(seq - 4, now, &mut |chunk| append(&mut data, chunk));
+        queue.expire(now, &mut |chunk| append(&mut data, chunk));
+        // only acknowledged data went out, the timeout never fired
+        assert_eq!(data.len() as u32, seq - 4);
+    }
+
+    // the acks stop, the rest goes out once the last of them is old enough
+    queue.expire(23 * SECOND, &mut |chunk| append(&mut data, chunk));
+    assert_eq!(data.len(), 76);
+    queue.expire(24 * SECOND, &mut |chunk| append(&mut data, chunk));
+    assert_eq!(data.len(), 80);
+}
+
+#[test]
+fn test_ack_queue_memory() {
+    let config = StreamConfig::new();
+    let memory = Arc::new(ReassemblyMemory::new(6));
+    let mut queue = AckQueue::new(&config, memory.clone());
+    let mut data = Vec::new();
+
+    // held data counts against the shared memory, over it the oldest goes out
+    queue.push(Reassembled::Data(b"abcd"), 0);
+    queue.push(Reassembled::Data(b"efgh"), 0);
+    queue.set_end_seq(8);
+    assert_eq!(memory.used(), 8);
+    queue.expire(0, &mut |chunk| append(&mut data, chunk));
+    assert_eq!(data, b"abcd");
+    assert_eq!(memory.used(), 4);
+
+    drop(queue);
+    assert_eq!(memory.used(), 0);
+}
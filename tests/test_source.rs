@@ -0,0 +1,37 @@
+extern crate layers;
+
+mod common;
+
+use layers::daq::{MemorySource, PacketSource};
+use layers::layer::packet::Packet;
+use std::cell::Cell;
+use std::sync::Arc;
+
+// ipv4 10.0.0.1:1234 -> 10.0.0.2:53 udp "ping" in an ethernet frame
+fn udp_frame() -> Vec<u8> {
+    let udp = common::udp(1234, 53, b"ping");
+    let ip = common::ipv4([10, 0, 0, 1], [10, 0, 0, 2], 17, &udp);
+    common::ethernet(0x0800, &ip)
+}
+
+#[test]
+fn test_memory_source() {
+    let source = MemorySource::new("memory");
+    source.push_frame(1, &udp_frame());
+    source.push_frame(2, &udp_frame()[..20]);
+    source.push_frame(3, &udp_frame());
+
+    let count = Cell::new(0);
+    source.run(&|packet: Arc<Packet>| {
+        assert_eq!(packet.state & Packet::STATE_UDP, Packet::STATE_UDP);
+        assert_eq!(packet.src_port, 1234);
+        assert_eq!(packet.dst_port, 53);
+        assert_eq!(packet.payload_slice(), b"ping");
+        assert_eq!(&*packet.interface, "memory");
+        count.set(count.get() + 1);
+    });
+
+    assert_eq!(count.get(), 2);
+    assert_eq!(source.stats().received, 2);
+    assert!(source.is_offline());
+}
@@ -0,0 +1,27 @@
+extern crate layers;
+
+use layers::daq::{MemorySource, PacketSource};
+use layers::layer::packet::Packet;
+use layers::stats;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+#[test]
+fn test_stats_report() {
+    let source = Arc::new(MemorySource::new("mem0"));
+    source.run(&|_packet: Arc<Packet>| {});
+    stats::register_source(source.clone());
+
+    let worker = stats::register_worker();
+    worker.enqueued.fetch_add(5, Ordering::Relaxed);
+    worker.processed.fetch_add(3, Ordering::Relaxed);
+    worker.tcp_streams.store(2, Ordering::Relaxed);
+    worker.tcp_streams_total.fetch_add(7, Ordering::Relaxed);
+    worker.detect_success.fetch_add(4, Ordering::Relaxed);
+    assert_eq!(worker.queue_depth(), 2);
+
+    let report = stats::report();
+    assert!(report.contains("source mem0 received 0 dropped 0 if_dropped 0"));
+    assert!(report.contains("worker 0 queue 2 processed 3 tcp_streams 2/7 udp_streams 0/0"));
+    assert!(report.contains("detect_success 4 detect_failed 0"));
+}
@@ -0,0 +1,201 @@
+extern crate layers;
+
+mod common;
+
+use layers::event::Event;
+use layers::layer::packet::Packet;
+use layers::layer::{LinkType, ReassemblyMemory, TCPAnalyzer, TCPCounters, TCPHeader};
+use std::sync::Arc;
+
+const SYN: u8 = TCPHeader::SYN;
+const ACK: u8 = TCPHeader::ACK;
+const FIN: u8 = TCPHeader::FIN;
+
+// ipv4 tcp 10.0.0.1:1234 -> 10.0.0.2:80
+fn segment(flags: u8, seq: u32, ack: u32, window: u16, payload: &[u8]) -> Arc<Packet> {
+    let tcp = common::tcp(1234, 80, flags, seq, ack, window, payload);
+    let data = common::ipv4([10, 0, 0, 1], [10, 0, 0, 2], 6, &tcp);
+    let interface: Arc<str> = Arc::from("test");
+    Packet::new(&interface, LinkType::RAW, 0, &data)
+}
+
+fn feed(analyzer: &mut TCPAnalyzer, packet: &Arc<Packet>) -> u64 {
+    let payload = if packet.state & Packet::STATE_PAYLOAD > 0 {
+        packet.payload_slice()
+    } else {
+        &[]
+    };
+    analyzer.on_segment(&packet.tcp().unwrap(), payload)
+}
+
+#[test]
+fn test_retransmission_and_out_of_order() {
+    let mut analyzer = TCPAnalyzer::new(Arc::new(ReassemblyMemory::new(1 << 20)));
+    feed(&mut analyzer, &segment(SYN, 100, 0, 1000, &[]));
+    feed(&mut analyzer, &segment(ACK, 101, 1, 1000, b"abcd"));
+    // the syn and the first segment sent again
+    feed(&mut analyzer, &segment(SYN, 100, 0, 1000, &[]));
+    feed(&mut analyzer, &segment(ACK, 101, 1, 1000, b"abcd"));
+    assert_eq!(analyzer.counters().retransmissions, 2);
+
+    // 109.. arrives before 105..109, which then fills the hole
+    feed(&mut analyzer, &segment(ACK, 109, 1, 1000, b"ijkl"));
+    feed(&mut analyzer, &segment(ACK, 105, 1, 1000, b"efgh"));
+    assert_eq!(analyzer.counters().out_of_order, 1);
+    // the hole is gone, the same bytes again are a retransmission
+    feed(&mut analyzer, &segment(ACK, 105, 1, 1000, b"ef"));
+    assert_eq!(analyzer.counters().retransmissions, 3);
+
+    // a keep-alive is neither
+    feed(&mut analyzer, &segment(ACK, 112, 1, 1000, b"l"));
+    assert_eq!(analyzer.counters().retransmissions, 3);
+    assert_eq!(analyzer.counters().out_of_order, 1);
+
+    // the fin counts like data
+    feed(&mut analyzer, &segment(FIN | ACK, 113, 1, 1000, &[]));
+    feed(&mut analyzer, &segment(FIN | ACK, 113, 1, 1000, &[]));
+    assert_eq!(analyzer.counters().retransmissions, 4);
+}
+
+#[test]
+fn test_wrapped_holes() {
+    let mut analyzer = TCPAnalyzer::new(Arc::new(ReassemblyMemory::new(1 << 20)));
+    feed(&mut analyzer, &segment(ACK, 0xffff_fffc, 1, 1000, b"ab"));
+    feed(&mut analyzer, &segment(ACK, 2, 1, 1000, b"cd"));
+    // part of the hole across the wrap, then the rest and a bit seen before
+    feed(&mut analyzer, &segment(ACK, 0xffff_fffe, 1, 1000, b"xy"));
+    feed(&mut analyzer, &segment(ACK, 0, 1, 1000, b"zzz"));
+    assert_eq!(analyzer.counters().out_of_order, 2);
+    assert_eq!(analyzer.counters().retransmissions, 0);
+    feed(&mut analyzer, &segment(ACK, 0xffff_ffff, 1, 1000, b"yz"));
+    assert_eq!(analyzer.counters().retransmissions, 1);
+}
+
+#[test]
+fn test_windows_and_acks() {
+    let mut analyzer = TCPAnalyzer::new(Arc::new(ReassemblyMemory::new(1 << 20)));
+    feed(&mut analyzer, &segment(ACK, 1, 500, 1000, &[]));
+    feed(&mut analyzer, &segment(ACK, 1, 500, 1000, &[]));
+    feed(&mut analyzer, &segment(ACK, 1, 500, 1000, &[]));
+    // a window update is not a duplicate
+    feed(&mut analyzer, &segment(ACK, 1, 500, 2000, &[]));
+    // and data carrying the same ack neither
+    feed(&mut analyzer, &segment(ACK, 1, 500, 2000, b"abc"));
+    assert_eq!(analyzer.counters().duplicate_acks, 2);
+
+    // a zero window held over several segments counts once
+    feed(&mut analyzer, &segment(ACK, 4, 600, 0, &[]));
+    feed(&mut analyzer, &segment(ACK, 4, 700, 0, &[]));
+    feed(&mut analyzer, &segment(ACK, 4, 800, 100, &[]));
+    feed(&mut analyzer, &segment(ACK, 4, 900, 0, &[]));
+    assert_eq!(analyzer.counters().zero_windows, 2);
+
+    assert_eq!(
+        *analyzer.counters(),
+        TCPCounters {
+            retransmissions: 0,
+            out_of_order: 0,
+            zero_windows: 2,
+            duplicate_acks: 2,
+            conflicting_overlaps: 0,
+        }
+    );
+}
+
+#[test]
+fn test_conflicting_overlaps() {
+    // no reassembler behind it, as on a stream nothing was detected on
+    let mut analyzer = TCPAnalyzer::new(Arc::new(ReassemblyMemory::new(1 << 20)));
+    assert_eq!(feed(&mut analyzer, &segment(SYN, 100, 0, 1000, &[])), 0);
+    assert_eq!(
+        feed(&mut analyzer, &segment(ACK, 101, 1, 1000, b"GET /")),
+        0
+    );
+    // the same bytes sent again are fine
+    assert_eq!(
+        feed(&mut analyzer, &segment(ACK, 101, 1, 1000, b"GET /")),
+        0
+    );
+    // data already handed on, sent again with other content
+    assert_eq!(
+        feed(&mut analyzer, &segment(ACK, 103, 1, 1000, b"X /ab")),
+        1
+    );
+    assert_eq!(analyzer.counters().conflicting_overlaps, 1);
+
+    // an out of order segment, then a copy of it and of the hole before it
+    assert_eq!(feed(&mut analyzer, &segment(ACK, 110, 1, 1000, b"wxyz")), 0);
+    assert_eq!(
+        feed(&mut analyzer, &segment(ACK, 108, 1, 1000, b"cdwxzz")),
+        1
+    );
+    // the hole was filled by the one before
+    assert_eq!(feed(&mut analyzer, &segment(ACK, 108, 1, 1000, b"ee")), 2);
+    assert_eq!(analyzer.counters().conflicting_overlaps, 3);
+
+    // a keep-alive carries a garbage byte
+    assert_eq!(feed(&mut analyzer, &segment(ACK, 113, 1, 1000, &[0])), 0);
+    assert_eq!(analyzer.counters().conflicting_overlaps, 3);
+    assert_eq!(analyzer.counters().retransmissions, 3);
+}
+
+#[test]
+fn test_overlaps_forgotten() {
+    let mut analyzer = TCPAnalyzer::new(Arc::new(ReassemblyMemory::new(1 << 20)));
+    let data = vec![b'a'; 1000];
+    let mut seq = 0xffff_f000u32;
+    for _ in 0..20 {
+        feed(&mut analyzer, &segment(ACK, seq, 1, 1000, &data));
+        seq = seq.wrapping_add(1000);
+    }
+    // the last bytes are compared across the wrap, the oldest are forgotten
+    let other = vec![b'b'; 1000];
+    assert_eq!(
+        feed(&mut analyzer, &segment(ACK, seq - 1000, 1, 1000, &other)),
+        1000
+    );
+    assert_eq!(
+        feed(&mut analyzer, &segment(ACK, 0xffff_f000, 1, 1000, &other)),
+        0
+    );
+}
+
+#[test]
+fn test_overlaps_memory() {
+    let memory = Arc::new(ReassemblyMemory::new(2000));
+    let mut analyzer = TCPAnalyzer::new(memory.clone());
+    let data = vec![b'a'; 1000];
+    for i in 0..3 {
+        feed(&mut analyzer, &segment(ACK, 1000 * i, 1, 1000, &data));
+    }
+    assert_eq!(memory.used(), 2000);
+
+    // the first copy made room for the last ones
+    let other = vec![b'b'; 1000];
+    assert_eq!(feed(&mut analyzer, &segment(ACK, 0, 1, 1000, &other)), 0);
+    assert_eq!(
+        feed(&mut analyzer, &segment(ACK, 2000, 1, 1000, &other)),
+        1000
+    );
+
+    drop(analyzer);
+    assert_eq!(memory.used(), 0);
+}
+
+#[test]
+fn test_overlap_event() {
+    let event = Event::TCPOverlap {
+        timestamp: 1,
+        interface: Arc::from("eth0"),
+        src_ip: "10.0.0.1".parse().unwrap(),
+        src_port: 1234,
+        dst_ip: "10.0.0.2".parse().unwrap(),
+        dst_port: 80,
+        bytes: 8,
+    };
+    assert!(event.is_alert());
+    assert_eq!(
+        event.to_string(),
+        "tcp_overlap eth0 10.0.0.1:1234 -> 10.0.0.2:80 bytes 8"
+    );
+}
@@ -0,0 +1,152 @@
+extern crate layers;
+
+mod common;
+
+use layers::layer::packet::Packet;
+use layers::layer::{Anomaly, LinkType, RttEstimator, SynFingerprint};
+use layers::layer::{TCPOption, TCPOptionKind, TCPOptions};
+use std::sync::Arc;
+
+// linux syn: mss 1460, sack permitted, timestamp 100/0, nop, window scale 7
+const LINUX_SYN: [u8; 20] = [
+    2, 4, 0x05, 0xb4, 4, 2, 8, 10, 0, 0, 0, 100, 0, 0, 0, 0, 1, 3, 3, 7,
+];
+
+// ipv4 10.0.0.1 -> 10.0.0.2 ttl 57 df, tcp 1234 -> 80 with the options
+fn segment(flags: u8, options: &[u8]) -> Vec<u8> {
+    // the options take the place of the payload, inside the header
+    let mut tcp = common::tcp(1234, 80, flags, 1000, 0, 0x7210, options);
+    tcp[12] = (((20 + options.len()) / 4) << 4) as u8;
+    let mut ip = common::ipv4([10, 0, 0, 1], [10, 0, 0, 2], 6, &tcp);
+    ip[8] = 57;
+    ip
+}
+
+fn decode(data: &[u8]) -> Arc<Packet> {
+    let interface: Arc<str> = Arc::from("test");
+    Packet::new(&interface, LinkType::RAW, 0, data)
+}
+
+#[test]
+fn test_option_iter() {
+    let packet = decode(&segment(0x02, &LINUX_SYN));
+    assert!(packet.valid());
+    assert!(packet.anomalies.is_empty());
+
+    let tcp = packet.tcp().unwrap();
+    let options: Vec<TCPOption> = tcp.options().collect();
+    assert_eq!(
+        options,
+        vec![
+            TCPOption::MSS(1460),
+            TCPOption::SackPermitted,
+            TCPOption::Timestamp {
+                value: 100,
+                echo: 0
+            },
+            TCPOption::Nop,
+            TCPOption::WindowScale(7),
+        ]
+    );
+
+    let parsed = TCPOptions::parse(&tcp);
+    assert_eq!(parsed.mss, Some(1460));
+    assert_eq!(parsed.window_scale, Some(7));
+    assert!(parsed.sack_permitted);
+    assert_eq!(parsed.timestamp, Some((100, 0)));
+    assert!(!parsed.malformed);
+    assert_eq!(packet.payload_len(), 0);
+}
+
+#[test]
+fn test_sack_and_unknown() {
+    // nop nop sack with two blocks, then padding and an unknown kind 30
+    let mut options = vec![1, 1, 5, 18];
+    options.extend_from_slice(&[0, 0, 0, 10, 0, 0, 0, 20, 0, 0, 0, 30, 0, 0, 0, 40]);
+    options.extend_from_slice(&[1, 1, 30, 2]);
+    let packet = decode(&segment(0x10, &options));
+    let tcp = packet.tcp().unwrap();
+
+    let mut sack = None;
+    for option in tcp.options() {
+        if let TCPOption::Sack(blocks) = option {
+            sack = Some(blocks.collect::<Vec<(u32, u32)>>());
+        }
+    }
+    assert_eq!(sack, Some(vec![(10, 20), (30, 40)]));
+    let kinds: Vec<TCPOptionKind> = tcp.options().map(|option| option.kind()).collect();
+    assert_eq!(kinds.last(), Some(&TCPOptionKind(30)));
+
+    // an mss with the wrong length is kept as an unknown option
+    let packet = decode(&segment(0x02, &[2, 3, 0, 0]));
+    let option = packet.tcp().unwrap().options().next();
+    assert_eq!(option, Some(TCPOption::Other(TCPOptionKind::MSS, &[0][..])));
+}
+
+#[test]
+fn test_malformed_options() {
+    // length running past the header
+    let packet = decode(&segment(0x02, &[2, 4, 0x05, 0xb4, 8, 12, 0, 0]));
+    assert!(packet.valid());
+    assert_eq!(packet.anomalies, vec![Anomaly::TCPOptions]);
+
+    let parsed = TCPOptions::parse(&packet.tcp().unwrap());
+    assert_eq!(parsed.mss, Some(1460));
+    assert!(parsed.malformed);
+
+    // a zero length would loop forever
+    let packet = decode(&segment(0x02, &[3, 0, 0, 0]));
+    assert_eq!(packet.anomalies, vec![Anomaly::TCPOptions]);
+
+    // end of list, the rest is padding
+    let packet = decode(&segment(0x02, &[0, 9, 9, 9]));
+    assert!(packet.anomalies.is_empty());
+}
+
+#[test]
+fn test_syn_fingerprint() {
+    let packet = decode(&segment(0x02, &LINUX_SYN));
+    let fingerprint = SynFingerprint::from_packet(&packet).unwrap();
+    assert_eq!(fingerprint.ttl, 57);
+    assert_eq!(fingerprint.initial_ttl(), 64);
+    assert_eq!(fingerprint.window, 29200);
+    assert_eq!(
+        fingerprint.to_string(),
+        "4:64:1460:29200,7:mss,sok,ts,nop,ws:df"
+    );
+
+    // no options, no df
+    let mut data = segment(0x02, &[]);
+    data[6] = 0;
+    let fingerprint = SynFingerprint::from_packet(&decode(&data)).unwrap();
+    assert_eq!(fingerprint.to_string(), "4:64:*:29200,*::");
+
+    assert!(SynFingerprint::from_packet(&decode(&segment(0x10, &LINUX_SYN))).is_none());
+}
+
+#[test]
+fn test_rtt_estimator() {
+    let mut rtt = RttEstimator::new();
+    assert_eq!(rtt.rtt(), None);
+
+    rtt.on_value(100, 1000);
+    // a retransmission keeps the first time
+    rtt.on_value(100, 1500);
+    rtt.on_echo(99, 1800);
+    assert_eq!(rtt.rtt(), None);
+    rtt.on_echo(100, 2000);
+    assert_eq!(rtt.rtt(), Some(1000));
+
+    // smoothed
+    rtt.on_value(101, 3000);
+    rtt.on_echo(101, 3800);
+    assert_eq!(rtt.rtt(), Some(1000 - 1000 / 8 + 800 / 8));
+    assert_eq!(rtt.samples(), 2);
+
+    // never echoed, a later value replaces it
+    rtt.on_value(102, 4000);
+    rtt.on_echo(103, 5000);
+    rtt.on_value(104, 6000);
+    rtt.on_echo(104, 6100);
+    assert_eq!(rtt.samples(), 3);
+}
@@ -0,0 +1,188 @@
+extern crate layers;
+
+mod common;
+
+use layers::layer::packet::Packet;
+use layers::layer::{LinkType, TCPConnection, TCPHeader, TCPOutcome, TCPState, TCPStream};
+use std::sync::Arc;
+
+const SYN: u8 = TCPHeader::SYN;
+const ACK: u8 = TCPHeader::ACK;
+const FIN: u8 = TCPHeader::FIN;
+const RST: u8 = TCPHeader::RST;
+
+// ipv4 tcp between 10.0.0.1:1234 and 10.0.0.2:80, direction does not matter here
+fn segment(flags: u8, seq: u32, ack: u32, payload: &[u8]) -> Arc<Packet> {
+    segment_ports(1234, 80, flags, seq, ack, payload)
+}
+
+fn segment_ports(
+    src_port: u16,
+    dst_port: u16,
+    flags: u8,
+    seq: u32,
+    ack: u32,
+    payload: &[u8],
+) -> Arc<Packet> {
+    let tcp = common::tcp(src_port, dst_port, flags, seq, ack, 0xffff, payload);
+    let data = common::ipv4([10, 0, 0, 1], [10, 0, 0, 2], 6, &tcp);
+    let interface: Arc<str> = Arc::from("test");
+    Packet::new(&interface, LinkType::RAW, 0, &data)
+}
+
+fn feed(connection: &mut TCPConnection, from_client: bool, packet: &Arc<Packet>, now: u64) {
+    let tcp = packet.tcp().unwrap();
+    connection.on_segment(from_client, &tcp, packet.payload_len(), now);
+}
+
+fn handshake(connection: &mut TCPConnection) {
+    feed(connection, true, &segment(SYN, 100, 0, &[]), 1);
+    assert_eq!(connection.state(), TCPState::SynSent);
+    feed(connection, false, &segment(SYN | ACK, 500, 101, &[]), 2);
+    assert_eq!(connection.state(), TCPState::SynReceived);
+    feed(connection, true, &segment(ACK, 101, 501, &[]), 3);
+    assert_eq!(connection.state(), TCPState::Established);
+}
+
+#[test]
+fn test_half_close_and_time_wait() {
+    let mut connection = TCPConnection::new();
+    handshake(&mut connection);
+
+    // the client is done sending, the response is still on its way
+    feed(
+        &mut connection,
+        true,
+        &segment(FIN | ACK, 101, 501, b"GET"),
+        4,
+    );
+    assert_eq!(connection.state(), TCPState::ClientHalfClosed);
+    feed(
+        &mut connection,
+        false,
+        &segment(ACK, 501, 105, b"HTTP/1.0 200"),
+        5,
+    );
+    assert_eq!(connection.state(), TCPState::ClientHalfClosed);
+    assert!(!connection.is_finished(5));
+    assert_eq!(connection.outcome(), None);
+
+    feed(
+        &mut connection,
+        false,
+        &segment(FIN | ACK, 513, 105, &[]),
+        6,
+    );
+    assert_eq!(connection.state(), TCPState::Closing);
+    assert_eq!(connection.outcome(), Some(TCPOutcome::Closed));
+
+    // the last ack
+    feed(&mut connection, true, &segment(ACK, 105, 514, &[]), 7);
+    assert_eq!(connection.state(), TCPState::TimeWait);
+    assert!(connection.is_reusable());
+    assert!(!connection.is_closed());
+    assert!(!connection.is_finished(8));
+    assert!(connection.is_finished(7 + TCPConnection::TIME_WAIT));
+}
+
+#[test]
+fn test_fin_not_acked() {
+    let mut connection = TCPConnection::new();
+    handshake(&mut connection);
+
+    feed(
+        &mut connection,
+        false,
+        &segment(FIN | ACK, 501, 101, &[]),
+        4,
+    );
+    assert_eq!(connection.state(), TCPState::ServerHalfClosed);
+    // acks below the fin
+    feed(&mut connection, true, &segment(FIN | ACK, 101, 501, &[]), 5);
+    assert_eq!(connection.state(), TCPState::Closing);
+    feed(&mut connection, false, &segment(ACK, 502, 102, &[]), 6);
+    assert_eq!(connection.state(), TCPState::Closing);
+    feed(&mut connection, true, &segment(ACK, 102, 502, &[]), 7);
+    assert_eq!(connection.state(), TCPState::TimeWait);
+}
+
+#[test]
+fn test_refused_and_reset() {
+    let mut connection = TCPConnection::new();
+    feed(&mut connection, true, &segment(SYN, 100, 0, &[]), 1);
+    feed(&mut connection, false, &segment(RST | ACK, 0, 101, &[]), 2);
+    assert_eq!(connection.state(), TCPState::Closed);
+    assert_eq!(connection.outcome(), Some(TCPOutcome::Refused));
+    assert!(connection.is_finished(2));
+
+    let mut connection = TCPConnection::new();
+    handshake(&mut connection);
+    feed(&mut connection, true, &segment(RST, 101, 0, &[]), 4);
+    assert_eq!(connection.outcome(), Some(TCPOutcome::Reset));
+
+    // a reset after both fins does not change how it ended
+    let mut connection = TCPConnection::new();
+    handshake(&mut connection);
+    feed(&mut connection, true, &segment(FIN | ACK, 101, 501, &[]), 4);
+    feed(
+        &mut connection,
+        false,
+        &segment(FIN | ACK, 501, 102, &[]),
+        5,
+    );
+    feed(&mut connection, true, &segment(RST, 102, 0, &[]), 6);
+    assert_eq!(connection.state(), TCPState::Closed);
+    assert_eq!(connection.outcome(), Some(TCPOutcome::Closed));
+}
+
+#[test]
+fn test_timeout() {
+    let mut connection = TCPConnection::new();
+    handshake(&mut connection);
+    connection.on_timeout();
+    assert_eq!(connection.state(), TCPState::Closed);
+    assert_eq!(connection.outcome(), Some(TCPOutcome::Timeout));
+
+    // data without the syn-ack establishes the connection
+    let mut connection = TCPConnection::new();
+    feed(&mut connection, true, &segment(SYN, 100, 0, &[]), 1);
+    feed(&mut connection, true, &segment(ACK, 101, 501, b"GET"), 2);
+    assert_eq!(connection.state(), TCPState::Established);
+    assert!(!connection.is_reusable());
+}
+
+#[test]
+fn test_midstream_sides() {
+    // the lower port is the service
+    assert!(TCPStream::is_client_side(&segment(ACK, 101, 501, b"GET")));
+    assert!(!TCPStream::is_client_side(&segment_ports(
+        80, 1234, ACK, 501, 101, b"HTTP"
+    )));
+    // unless the flags tell
+    assert!(!TCPStream::is_client_side(&segment(
+        SYN | ACK,
+        500,
+        101,
+        &[]
+    )));
+    assert!(TCPStream::is_client_side(&segment_ports(
+        80,
+        1234,
+        SYN,
+        100,
+        0,
+        &[]
+    )));
+
+    // picked up with data, the state machine starts established
+    let mut connection = TCPConnection::new();
+    feed(&mut connection, true, &segment(ACK, 101, 501, b"GET"), 1);
+    assert_eq!(connection.state(), TCPState::Established);
+    feed(
+        &mut connection,
+        false,
+        &segment(FIN | ACK, 501, 104, &[]),
+        2,
+    );
+    assert_eq!(connection.state(), TCPState::ServerHalfClosed);
+}
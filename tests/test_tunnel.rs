@@ -0,0 +1,196 @@
+extern crate layers;
+
+mod common;
+
+use common::{ethernet, ipv4, udp};
+use layers::layer::dispatcher;
+use layers::layer::packet::Packet;
+use layers::layer::{LinkType, TunnelType};
+use std::sync::Arc;
+
+const IPIP: u8 = 4;
+const UDP: u8 = 17;
+const GRE: u8 = 47;
+
+// the flow the tunnels carry, 192.168.0.1:1234 -> 192.168.0.2:53
+fn inner_ipv4() -> Vec<u8> {
+    ipv4([192, 168, 0, 1], [192, 168, 0, 2], UDP, &udp(1234, 53, b"ping"))
+}
+
+// 10.0.0.1 -> 10.0.0.2 on the wire
+fn outer(proto: u8, payload: &[u8]) -> Vec<u8> {
+    ethernet(0x0800, &ipv4([10, 0, 0, 1], [10, 0, 0, 2], proto, payload))
+}
+
+fn decode(data: &[u8]) -> Arc<Packet> {
+    let interface: Arc<str> = Arc::from("test");
+    Packet::new(&interface, LinkType::EN10MB, 0, data)
+}
+
+fn assert_inner(packet: &Packet) {
+    assert!(packet.valid());
+    assert_eq!(packet.state & Packet::STATE_UDP, Packet::STATE_UDP);
+    assert_eq!(packet.src_ip_str(), "192.168.0.1");
+    assert_eq!(packet.dst_ip_str(), "192.168.0.2");
+    assert_eq!(packet.src_port, 1234);
+    assert_eq!(packet.dst_port, 53);
+    assert_eq!(packet.payload_slice(), b"ping");
+}
+
+#[test]
+fn test_vxlan() {
+    let mut vxlan = vec![0x08, 0, 0, 0, 0x00, 0x12, 0x34, 0x00];
+    vxlan.extend_from_slice(&ethernet(0x0800, &inner_ipv4()));
+    let data = outer(UDP, &udp(40000, 4789, &vxlan));
+
+    let packet = decode(&data);
+    assert_inner(&packet);
+    assert_eq!(packet.tunnels.len(), 1);
+    assert_eq!(packet.tunnels[0].kind, TunnelType::VXLAN);
+    assert_eq!(packet.tunnels[0].id, 0x1234);
+    assert_eq!(packet.tunnels[0].src_ip.to_string(), "10.0.0.1");
+    assert_eq!(packet.tunnels_str(), "vxlan(10.0.0.1->10.0.0.2 id 4660)");
+
+    // without the vni flag the udp packet is left alone
+    vxlan[0] = 0;
+    let data = outer(UDP, &udp(40000, 4789, &vxlan));
+    let packet = decode(&data);
+    assert!(packet.tunnels.is_empty());
+    assert_eq!(packet.dst_port, 4789);
+}
+
+#[test]
+fn test_tunnel_worker() {
+    // the outer source port changes, the inner stream stays on its worker
+    let plain = decode(&ethernet(0x0800, &inner_ipv4()));
+    let mut vxlan = vec![0x08, 0, 0, 0, 0x00, 0x12, 0x34, 0x00];
+    vxlan.extend_from_slice(&ethernet(0x0800, &inner_ipv4()));
+    let mut gre = vec![0x00, 0x00, 0x08, 0x00];
+    gre.extend_from_slice(&inner_ipv4());
+    let packets = [
+        decode(&outer(UDP, &udp(40000, 4789, &vxlan))),
+        decode(&outer(UDP, &udp(50123, 4789, &vxlan))),
+        decode(&outer(GRE, &gre)),
+    ];
+    for packet in packets.iter() {
+        for workers in 1..9 {
+            assert_eq!(
+                dispatcher::worker_index(packet, workers),
+                dispatcher::worker_index(&plain, workers)
+            );
+        }
+    }
+}
+
+#[test]
+fn test_geneve() {
+    // one 4 bytes option
+    let mut geneve = vec![0x01, 0, 0x65, 0x58, 0, 0, 0x2a, 0, 0, 0, 0, 0];
+    geneve.extend_from_slice(&ethernet(0x0800, &inner_ipv4()));
+    let data = outer(UDP, &udp(40000, 6081, &geneve));
+
+    let packet = decode(&data);
+    assert_inner(&packet);
+    assert_eq!(packet.tunnels[0].kind, TunnelType::GENEVE);
+    assert_eq!(packet.tunnels[0].id, 0x2a);
+}
+
+#[test]
+fn test_gre() {
+    // key present
+    let mut gre = vec![0x20, 0x00, 0x08, 0x00, 0, 0, 0, 99];
+    gre.extend_from_slice(&inner_ipv4());
+    let packet = decode(&outer(GRE, &gre));
+    assert_inner(&packet);
+    assert_eq!(packet.tunnels[0].kind, TunnelType::GRE);
+    assert_eq!(packet.tunnels[0].id, 99);
+
+    // key announced but cut off
+    let packet = decode(&outer(GRE, &gre[..6]));
+    assert!(!packet.valid());
+}
+
+#[test]
+fn test_erspan() {
+    // type II: sequence number then the erspan header with session 5
+    let mut gre = vec![0x10, 0x00, 0x88, 0xbe, 0, 0, 0, 1];
+    gre.extend_from_slice(&[0x10, 0x00, 0x00, 0x05, 0, 0, 0, 0]);
+    gre.extend_from_slice(&ethernet(0x0800, &inner_ipv4()));
+    let packet = decode(&outer(GRE, &gre));
+    assert_inner(&packet);
+    assert_eq!(packet.tunnels[0].kind, TunnelType::ERSPAN);
+    assert_eq!(packet.tunnels[0].id, 5);
+
+    // type I: no sequence number, the mirrored frame follows gre
+    let mut gre = vec![0x00, 0x00, 0x88, 0xbe];
+    gre.extend_from_slice(&ethernet(0x0800, &inner_ipv4()));
+    let packet = decode(&outer(GRE, &gre));
+    assert_inner(&packet);
+    assert_eq!(packet.tunnels[0].kind, TunnelType::ERSPAN);
+}
+
+#[test]
+fn test_ipip_nested() {
+    let packet = decode(&outer(IPIP, &inner_ipv4()));
+    assert_inner(&packet);
+    assert_eq!(packet.tunnels[0].kind, TunnelType::IPIP);
+
+    // gre inside vxlan
+    let mut gre = vec![0x00, 0x00, 0x08, 0x00];
+    gre.extend_from_slice(&inner_ipv4());
+    let middle = ipv4([172, 16, 0, 1], [172, 16, 0, 2], GRE, &gre);
+    let mut vxlan = vec![0x08, 0, 0, 0, 0, 0, 0x01, 0];
+    vxlan.extend_from_slice(&ethernet(0x0800, &middle));
+    let packet = decode(&outer(UDP, &udp(40000, 4789, &vxlan)));
+    assert_inner(&packet);
+    assert_eq!(packet.tunnels.len(), 2);
+    assert_eq!(packet.tunnels[0].kind, TunnelType::VXLAN);
+    assert_eq!(packet.tunnels[1].kind, TunnelType::GRE);
+    assert_eq!(packet.tunnels[1].src_ip.to_string(), "172.16.0.1");
+}
+
+#[test]
+fn test_tunnel_depth() {
+    let mut ip = inner_ipv4();
+    for _ in 0..6 {
+        ip = ipv4([10, 0, 0, 1], [10, 0, 0, 2], IPIP, &ip);
+    }
+    let packet = decode(&ethernet(0x0800, &ip));
+    assert!(packet.valid());
+    assert_eq!(packet.tunnels.len(), 4);
+    assert_eq!(packet.state & Packet::STATE_UDP, 0);
+
+    // bridged frames in bridged frames count too
+    let mut frame = ethernet(0x0800, &inner_ipv4());
+    for _ in 0..6 {
+        frame = ethernet(0x6558, &frame);
+    }
+    let packet = decode(&frame);
+    assert!(packet.valid());
+    assert!(packet.tunnels.is_empty());
+    assert_eq!(packet.state & Packet::STATE_UDP, 0);
+
+    // and so do mpls pseudowires
+    let mut frame = ethernet(0x0800, &inner_ipv4());
+    for _ in 0..6 {
+        let mut pseudowire = vec![0x00, 0x02, 0xa1, 0x40, 0, 0, 0, 0];
+        pseudowire.extend_from_slice(&frame);
+        frame = ethernet(0x8847, &pseudowire);
+    }
+    let packet = decode(&frame);
+    assert!(packet.valid());
+    // the labels in front of the pseudowire left alone are still read
+    assert_eq!(packet.mpls_labels.len(), 5);
+    assert_eq!(packet.state & Packet::STATE_UDP, 0);
+
+    // a bridged frame in gre is one level
+    let mut gre = vec![0x00, 0x00, 0x65, 0x58];
+    gre.extend_from_slice(&ethernet(0x0800, &inner_ipv4()));
+    let mut ip = ipv4([10, 0, 0, 1], [10, 0, 0, 2], GRE, &gre);
+    for _ in 0..3 {
+        ip = ipv4([10, 0, 0, 1], [10, 0, 0, 2], IPIP, &ip);
+    }
+    let packet = decode(&ethernet(0x0800, &ip));
+    assert_inner(&packet);
+    assert_eq!(packet.tunnels.len(), 4);
+}
@@ -0,0 +1,104 @@
+extern crate layers;
+
+mod common;
+
+use common::{ethernet, ipv4, udp, vlan};
+use layers::layer::packet::Packet;
+use layers::layer::{LinkType, StreamID};
+use std::net::IpAddr;
+use std::sync::Arc;
+
+// ipv4 10.0.0.1 -> 10.0.0.2, udp 1234 -> 53 "ping"
+fn ipv4_udp() -> Vec<u8> {
+    ipv4([10, 0, 0, 1], [10, 0, 0, 2], 17, &udp(1234, 53, b"ping"))
+}
+
+fn mpls(label: u32, bottom: bool) -> Vec<u8> {
+    let entry = label << 12 | if bottom { 0x100 } else { 0 } | 64;
+    vec![(entry >> 24) as u8, (entry >> 16) as u8, (entry >> 8) as u8, entry as u8]
+}
+
+fn decode(data: &[u8]) -> Arc<Packet> {
+    let interface: Arc<str> = Arc::from("test");
+    Packet::new(&interface, LinkType::EN10MB, 0, data)
+}
+
+fn assert_udp(packet: &Packet) {
+    assert!(packet.valid());
+    assert_eq!(packet.state & Packet::STATE_UDP, Packet::STATE_UDP);
+    assert_eq!(packet.src_ip_str(), "10.0.0.1");
+    assert_eq!(packet.dst_port, 53);
+    assert_eq!(packet.payload_slice(), b"ping");
+}
+
+#[test]
+fn test_vlan_stack() {
+    let mut tags = vlan(100, 0x8100);
+    tags.extend_from_slice(&vlan(200, 0x8100));
+    tags.extend_from_slice(&vlan(300, 0x0800));
+    tags.extend_from_slice(&ipv4_udp());
+    let packet = decode(&ethernet(0x88a8, &tags));
+    assert_udp(&packet);
+    assert_eq!(packet.vlan_ids, vec![100, 200, 300]);
+    assert_eq!(packet.vlan_key(), [100, 200]);
+
+    let mut tags = vlan(7, 0x86dd);
+    tags.extend_from_slice(&[0x60, 0, 0]);
+    let packet = decode(&ethernet(0x8100, &tags));
+    assert!(!packet.valid());
+    assert_eq!(packet.vlan_key(), [7, 0]);
+
+    // tag cut off
+    assert!(!decode(&ethernet(0x8100, &[0, 7])).valid());
+}
+
+#[test]
+fn test_mpls() {
+    let mut labels = mpls(16, false);
+    labels.extend_from_slice(&mpls(1000, true));
+    labels.extend_from_slice(&ipv4_udp());
+    let packet = decode(&ethernet(0x8847, &labels));
+    assert_udp(&packet);
+    assert_eq!(packet.mpls_labels, vec![16, 1000]);
+
+    // ethernet pseudowire behind a control word
+    let mut labels = mpls(42, true);
+    labels.extend_from_slice(&[0, 0, 0, 0]);
+    labels.extend_from_slice(&ethernet(0x0800, &ipv4_udp()));
+    let packet = decode(&ethernet(0x8847, &labels));
+    assert_udp(&packet);
+    assert_eq!(packet.mpls_labels, vec![42]);
+
+    // no bottom of stack
+    assert!(!decode(&ethernet(0x8847, &mpls(16, false))).valid());
+}
+
+#[test]
+fn test_pppoe() {
+    let ip = ipv4_udp();
+    let length = ip.len() + 2;
+    let mut pppoe = vec![0x11, 0x00, 0x12, 0x34, (length >> 8) as u8, length as u8, 0x00, 0x21];
+    pppoe.extend_from_slice(&ip);
+    // ethernet padding is not part of the session payload
+    pppoe.extend_from_slice(&[0; 6]);
+    let packet = decode(&ethernet(0x8864, &pppoe));
+    assert_udp(&packet);
+    assert_eq!(packet.ip_layer_len, ip.len());
+
+    // length beyond the frame
+    pppoe[5] = 0xff;
+    assert!(!decode(&ethernet(0x8864, &pppoe)).valid());
+}
+
+#[test]
+fn test_stream_id_vlan() {
+    let a: IpAddr = "10.0.0.1".parse().unwrap();
+    let b: IpAddr = "10.0.0.2".parse().unwrap();
+
+    assert_eq!(StreamID::new(a, b, 1234, 53), StreamID::with_vlan([0, 0], b, a, 53, 1234));
+    assert_eq!(
+        StreamID::with_vlan([10, 0], a, b, 1234, 53),
+        StreamID::with_vlan([10, 0], b, a, 53, 1234)
+    );
+    assert!(StreamID::with_vlan([10, 0], a, b, 1234, 53) != StreamID::with_vlan([20, 0], a, b, 1234, 53));
+}